@@ -0,0 +1,223 @@
+//! Measures how many queries for the quantity of products in every local a running database
+//! answers per second, with many e-commerce connections asking at once while the stock keeps
+//! changing.
+//!
+//! Start a fresh database first, with the stock partitions to measure, e.g.:
+//!
+//! ```text
+//! cargo run --release -p ferris_db -- -p 0 --insecure
+//! cargo run --release -p ferris_db --example stock_queries_bench -- 127.0.0.1:9999 64 500
+//! ```
+//!
+//! The arguments are the address of the database, the number of connections and the number of
+//! queries each connection makes, one after the other.
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Write},
+    net::TcpStream,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use shared::{
+    communication::{db_auth::db_token_from_env, db_request::DBRequest, db_response::DBResponse},
+    model::{constants::DATABASE_IP, stock_product::Product},
+};
+
+const LOCALS: usize = 20;
+const PRODUCTS: usize = 200;
+const PRODUCTS_PER_QUERY: usize = 5;
+const WRITE_INTERVAL_MICROS: u64 = 500;
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Connection {
+    fn open(addr: &str) -> Result<Self, String> {
+        let writer = TcpStream::connect(addr).map_err(|err| err.to_string())?;
+        let reader = BufReader::new(writer.try_clone().map_err(|err| err.to_string())?);
+        let mut connection = Connection { reader, writer };
+        let token = db_token_from_env().unwrap_or_default();
+        connection.send(&DBRequest::Authenticate {
+            request_id: 0,
+            token,
+        })?;
+        match connection.receive()? {
+            DBResponse::Authenticated { .. } => Ok(connection),
+            response => Err(format!("Could not authenticate: {:?}", response)),
+        }
+    }
+
+    fn send(&mut self, request: &DBRequest) -> Result<(), String> {
+        let line = format!("{}\n", request.to_string()?);
+        self.writer
+            .write_all(line.as_bytes())
+            .map_err(|err| err.to_string())
+    }
+
+    fn receive(&mut self) -> Result<DBResponse, String> {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => Err("The database closed the connection".to_string()),
+            Ok(_) => DBResponse::from_string(line.trim()),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+}
+
+fn product_name(index: usize) -> String {
+    format!("product{}", index % PRODUCTS)
+}
+
+/// Acquires a leader epoch for the writes, registers the locals and uploads their stock,
+/// returning the epoch and their ids.
+fn seed_stock(addr: &str) -> Result<(u64, Vec<u16>), String> {
+    let mut connection = Connection::open(addr)?;
+    connection.send(&DBRequest::AcquireLeaderEpoch { request_id: 0 })?;
+    let DBResponse::LeaderEpoch { epoch, .. } = connection.receive()? else {
+        return Err("Could not acquire a leader epoch".to_string());
+    };
+    let mut local_ids = Vec::new();
+    for request_id in 0..LOCALS as u64 {
+        connection.send(&DBRequest::GetNewLocalId { request_id, epoch })?;
+        let DBResponse::NewLocalId { local_id, .. } = connection.receive()? else {
+            return Err("Could not get a new local id".to_string());
+        };
+        let stock: HashMap<String, Product> = (0..PRODUCTS)
+            .map(|index| (product_name(index), Product::new(product_name(index), 1000)))
+            .collect();
+        connection.send(&DBRequest::PostStockFromLocal {
+            request_id,
+            local_id,
+            stock,
+            version: 1,
+            epoch,
+        })?;
+        local_ids.push(local_id);
+    }
+    Ok((epoch, local_ids))
+}
+
+/// Keeps changing the stock of the locals until told to stop, returning how many changes it sent.
+fn change_stock(
+    addr: &str,
+    epoch: u64,
+    local_ids: &[u16],
+    stop: &AtomicBool,
+) -> Result<usize, String> {
+    let mut connection = Connection::open(addr)?;
+    // Only errors are answered, and they are drained so the database never blocks writing them.
+    let replies = connection
+        .writer
+        .try_clone()
+        .map_err(|err| err.to_string())?;
+    thread::spawn(move || for _ in BufReader::new(replies).lines().map_while(Result::ok) {});
+    let mut changes = 0;
+    while !stop.load(Ordering::Relaxed) {
+        let local_id = local_ids[changes % local_ids.len()];
+        connection.send(&DBRequest::PostStockDelta {
+            request_id: changes as u64,
+            local_id,
+            product_name: product_name(changes),
+            change: if changes % 2 == 0 { -1 } else { 1 },
+            version: 2 + (changes / local_ids.len()) as u64,
+            epoch,
+        })?;
+        changes += 1;
+        thread::sleep(Duration::from_micros(WRITE_INTERVAL_MICROS));
+    }
+    Ok(changes)
+}
+
+/// Makes the given number of queries one after the other, returning how many were answered
+/// with the quantities rather than with an error.
+fn query_stock(addr: &str, worker_id: u16, queries: usize) -> Result<usize, String> {
+    let mut connection = Connection::open(addr)?;
+    let mut answered = 0;
+    for query in 0..queries {
+        let product_names = (0..PRODUCTS_PER_QUERY)
+            .map(|offset| product_name(query * PRODUCTS_PER_QUERY + offset))
+            .collect();
+        connection.send(&DBRequest::GetProductsQuantityFromAllLocals {
+            request_id: query as u64,
+            ss_id: 0,
+            worker_id,
+            product_names,
+        })?;
+        if let DBResponse::ProductsQuantityFromAllLocals { .. } = connection.receive()? {
+            answered += 1;
+        }
+    }
+    Ok(answered)
+}
+
+fn main() -> Result<(), String> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let addr = args.first().cloned().unwrap_or(DATABASE_IP.to_string());
+    let connections: usize = args
+        .get(1)
+        .map_or(Ok(64), |arg| arg.parse())
+        .map_err(|_| "Invalid number of connections")?;
+    let queries: usize = args
+        .get(2)
+        .map_or(Ok(500), |arg| arg.parse())
+        .map_err(|_| "Invalid number of queries")?;
+
+    let (epoch, local_ids) = seed_stock(&addr)?;
+    let stop = Arc::new(AtomicBool::new(false));
+    let writer = {
+        let addr = addr.clone();
+        let stop = stop.clone();
+        thread::spawn(move || change_stock(&addr, epoch, &local_ids, &stop))
+    };
+
+    let answered = Arc::new(AtomicUsize::new(0));
+    let start = Instant::now();
+    let readers: Vec<_> = (0..connections)
+        .map(|worker_id| {
+            let addr = addr.clone();
+            let answered = answered.clone();
+            thread::spawn(move || {
+                let result = query_stock(&addr, worker_id as u16, queries);
+                if let Ok(count) = result {
+                    answered.fetch_add(count, Ordering::Relaxed);
+                }
+                result
+            })
+        })
+        .collect();
+    for reader in readers {
+        if let Err(err) = reader.join().map_err(|_| "A connection panicked")? {
+            println!("A connection failed: {}", err);
+        }
+    }
+    let elapsed = start.elapsed();
+    let answered = answered.load(Ordering::Relaxed);
+    stop.store(true, Ordering::Relaxed);
+    let changes = writer.join().map_err(|_| "The writer panicked")??;
+
+    println!(
+        "{} connections made {} queries in {:.2?} while {} stock changes were sent",
+        connections,
+        connections * queries,
+        elapsed,
+        changes
+    );
+    println!(
+        "{} queries were answered and {} failed",
+        answered,
+        connections * queries - answered
+    );
+    println!(
+        "Throughput: {:.0} queries per second",
+        answered as f64 / elapsed.as_secs_f64()
+    );
+    Ok(())
+}
@@ -0,0 +1,45 @@
+//! This module contains the `AppliedOrders`, which keep track of the order results already applied
+//! to the stock, so that a resent order result is not applied twice.
+//!
+//! A completed order is identified by the id it is given when it is taken, which is kept while it
+//! is resent after a reconnection, and every id is remembered so that a resend is recognized even
+//! after newer stock of its local arrived.
+
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct AppliedOrders {
+    // Snapshots from before orders had ids kept no order ids, so they start with none.
+    #[serde(default)]
+    order_ids: HashSet<u64>,
+}
+
+impl AppliedOrders {
+    pub fn new() -> Self {
+        AppliedOrders::default()
+    }
+
+    pub fn contains(&self, order_id: u64) -> bool {
+        self.order_ids.contains(&order_id)
+    }
+
+    pub fn insert(&mut self, order_id: u64) {
+        self.order_ids.insert(order_id);
+    }
+}
+
+// ====================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshots_from_before_order_ids_have_no_applied_orders() {
+        let applied_orders: AppliedOrders =
+            serde_json::from_str(r#"{"stock_versions_by_local":{"1":[2,3]}}"#).unwrap();
+        assert_eq!(applied_orders, AppliedOrders::new());
+    }
+}
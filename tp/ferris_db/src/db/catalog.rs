@@ -0,0 +1,109 @@
+//! This module contains the `Catalog`, which holds the metadata of the products sold by the locals.
+//!
+//! It is loaded from a file when the database starts. Once a catalog is loaded, order results
+//! with products that are not in it are rejected. Without one, every product is accepted.
+
+use std::collections::HashMap;
+
+use shared::{
+    model::{catalog_product::CatalogProduct, stock_product::Product},
+    parsers::catalog_parser::CatalogParser,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Catalog {
+    products: HashMap<String, CatalogProduct>,
+}
+
+impl Catalog {
+    pub fn new() -> Self {
+        Catalog::default()
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let parser = CatalogParser::new(path).map_err(|err| format!("{}: {}", path, err))?;
+        Ok(Catalog {
+            products: parser.get_products(),
+        })
+    }
+
+    pub fn get(&self, product_name: &str) -> Option<&CatalogProduct> {
+        self.products.get(product_name)
+    }
+
+    /// Returns the products in the given category, or all of them if none is given, sorted by name.
+    pub fn products(&self, category: Option<&str>) -> Vec<CatalogProduct> {
+        let mut products: Vec<CatalogProduct> = self
+            .products
+            .values()
+            .filter(|product| category.map_or(true, |category| product.get_category() == category))
+            .cloned()
+            .collect();
+        products.sort_by_key(|product| product.get_name());
+        products
+    }
+
+    pub fn check_products_are_known(&self, products: &[Product]) -> Result<(), String> {
+        if self.products.is_empty() {
+            return Ok(());
+        }
+        match products
+            .iter()
+            .find(|product| !self.products.contains_key(&product.get_name()))
+        {
+            Some(product) => Err(format!(
+                "Product {} is not in the catalog",
+                product.get_name()
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+// ====================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn catalog_product(name: &str, category: &str) -> CatalogProduct {
+        CatalogProduct::new(
+            name.to_string(),
+            format!("SKU-{}", name),
+            name.to_string(),
+            100,
+            category.to_string(),
+        )
+    }
+
+    #[test]
+    fn test_only_products_in_the_catalog_are_known_once_it_is_loaded() {
+        let product = |name: &str| Product::new(name.to_string(), 1);
+        assert_eq!(
+            Catalog::new().check_products_are_known(&[product("product9")]),
+            Ok(())
+        );
+
+        let catalog = Catalog {
+            products: HashMap::from([
+                ("product2".to_string(), catalog_product("product2", "toys")),
+                ("product1".to_string(), catalog_product("product1", "toys")),
+                ("product3".to_string(), catalog_product("product3", "food")),
+            ]),
+        };
+        assert_eq!(
+            catalog.check_products_are_known(&[product("product1"), product("product3")]),
+            Ok(())
+        );
+        assert!(catalog
+            .check_products_are_known(&[product("product1"), product("product9")])
+            .is_err());
+        assert_eq!(
+            catalog.products(Some("toys")),
+            vec![
+                catalog_product("product1", "toys"),
+                catalog_product("product2", "toys")
+            ]
+        );
+    }
+}
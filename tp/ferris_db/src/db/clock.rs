@@ -0,0 +1,11 @@
+//! This module contains the helpers to timestamp the records kept by the database.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Returns the current time as the number of seconds since the UNIX epoch.
+pub fn current_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
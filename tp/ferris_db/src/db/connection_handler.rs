@@ -1,21 +1,40 @@
 //! This module contains the `ConnectionHandler` actor, which is responsible for managing connections.
 //!
-//! It keeps track of the last local id assigned and maintains a map of
-//! database communicators for each server connected to the database.
+//! It keeps the registry of local ids issued and maintains a map of
+//! database communicators for each server connected to the database,
+//! from which they are removed when their connection is closed.
 //!
 //! It also handles messages from the database communicators and forwards them
-//! to the `StockHandler`.
+//! to the `StockHandler`, and periodically asks it to take a snapshot of the state.
+//!
+//! Locals that were not seen for longer than the staleness threshold are left out of the replies
+//! to stock queries, so that no orders are assigned to them.
+//!
+//! The queries for the quantity of products in every local are answered by the stock partitions,
+//! if there are any: each one is asked for the products it holds, and their answers are merged.
 
-use actix::{Actor, Addr, AsyncContext, Context, Handler, Message};
+use actix::{
+    fut::wrap_future, Actor, ActorFutureExt, Addr, AsyncContext, Context, Handler, MailboxError,
+    Message,
+};
 use shared::{
-    communication::db_response::DBResponse,
+    communication::db_response::{DBErrorKind, DBResponse},
     model::{order::Order, stock_product::Product},
 };
-use std::collections::HashMap;
+use std::{collections::HashMap, future::Future, path::PathBuf, time::Duration};
+use tracing::{debug, error, info, warn};
 
 use super::{
+    clock::current_timestamp,
+    constants::SNAPSHOT_INTERVAL_SECS,
+    db_error::DBError,
     db_middleman::{DBMiddleman, SendOnlineMsg},
-    stock_handler::{self, StockHandler},
+    local_registry::LocalRegistry,
+    replication::ReplicationMessage,
+    stock_handler::{self, OrderResultOutcome, StockHandler},
+    stock_partition::{group_by_partition, GetAvailableQuantities, StockPartition},
+    subscriptions::StockFilter,
+    wal::WalEntry,
 };
 
 /// `ConnectionHandler` is responsible for managing connections.
@@ -24,29 +43,178 @@ use super::{
 #[derive(Debug, PartialEq, Eq)]
 pub struct ConnectionHandler {
     pub stock_handler: Addr<StockHandler>,
-    pub last_local_id: u16,
+    pub stock_partitions: Vec<Addr<StockPartition>>,
+    pub local_registry: LocalRegistry,
     pub db_middlemen: HashMap<u16, Addr<DBMiddleman>>,
+    pub stale_local_threshold_secs: u64,
 }
 
 impl Actor for ConnectionHandler {
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(
+            Duration::from_secs(SNAPSHOT_INTERVAL_SECS),
+            |connection_handler, ctx| connection_handler.take_snapshot(ctx),
+        );
+    }
 }
 
 impl ConnectionHandler {
-    pub fn new(stock_handler: Addr<StockHandler>) -> Self {
+    pub fn new(
+        stock_handler: Addr<StockHandler>,
+        stock_partitions: Vec<Addr<StockPartition>>,
+        local_registry: LocalRegistry,
+        stale_local_threshold_secs: u64,
+    ) -> Self {
         ConnectionHandler {
             stock_handler,
-            last_local_id: 0,
+            stock_partitions,
+            local_registry,
             db_middlemen: HashMap::new(),
+            stale_local_threshold_secs,
+        }
+    }
+
+    /// Asks each partition for the quantity of its products in every local, merging the answers.
+    fn quantities_from_partitions(
+        &self,
+        product_names: &[String],
+    ) -> impl Future<Output = Result<HashMap<String, HashMap<u16, i32>>, MailboxError>> {
+        let requests: Vec<_> = group_by_partition(product_names, self.stock_partitions.len())
+            .into_iter()
+            .map(|(partition_index, product_names)| {
+                self.stock_partitions[partition_index]
+                    .send(GetAvailableQuantities { product_names })
+            })
+            .collect();
+        async move {
+            let mut products_quantity_in_locals = HashMap::new();
+            for request in requests {
+                products_quantity_in_locals.extend(request.await?);
+            }
+            Ok(products_quantity_in_locals)
         }
     }
 
-    pub fn get_new_local_id(&mut self) -> u16 {
-        self.last_local_id += 1;
-        self.last_local_id
+    /// Removes the locals considered offline from the quantities of a product in each local.
+    fn omit_offline_locals(&self, quantity_by_local_id: &mut HashMap<u16, i32>) {
+        let now = current_timestamp();
+        quantity_by_local_id.retain(|local_id, _| {
+            let online =
+                self.local_registry
+                    .is_online(*local_id, now, self.stale_local_threshold_secs);
+            if !online {
+                debug!(
+                    "[ConnectionHandler] Local {} is offline, leaving it out of the stock query",
+                    local_id
+                );
+            }
+            online
+        });
+    }
+
+    /// Ids of the e-commerce servers currently connected, sorted.
+    pub fn connected_ecommerce_ids(&self) -> Vec<u16> {
+        let mut ecommerce_ids: Vec<u16> = self.db_middlemen.keys().copied().collect();
+        ecommerce_ids.sort();
+        ecommerce_ids
+    }
+
+    /// Updates the last time a local was seen, rejecting the ids that were never issued.
+    fn mark_local_seen(&mut self, local_id: u16) -> Result<(), DBError> {
+        self.local_registry
+            .mark_seen(local_id, current_timestamp())
+            .map_err(|err| DBError::new(DBErrorKind::UnknownLocal, err))
+    }
+
+    /// Asks the `StockHandler` to take a snapshot including the registry of local ids.
+    ///
+    /// No other message is handled until it is done, so no new local id can be issued
+    /// in the meantime and be lost when the log is truncated.
+    fn take_snapshot(&mut self, ctx: &mut Context<Self>) {
+        let snapshot_request = self.stock_handler.send(stock_handler::TakeSnapshot {
+            local_registry: self.local_registry.clone(),
+        });
+        ctx.wait(
+            wrap_future::<_, Self>(snapshot_request).map(|result, _, _| match result {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => error!("[ConnectionHandler] Error taking snapshot: {}", err),
+                Err(err) => error!("[ConnectionHandler] Error taking snapshot: {}", err),
+            }),
+        );
     }
 }
 
+/// Waits for the result of a request forwarded to the `StockHandler`, and reports it back
+/// to the requestor if it failed.
+fn reply_on_failure(
+    ctx: &mut Context<ConnectionHandler>,
+    request: impl Future<Output = Result<Result<(), DBError>, MailboxError>> + 'static,
+    requestor_db_middleman: Addr<DBMiddleman>,
+    request_id: u64,
+) {
+    ctx.spawn(
+        wrap_future::<_, ConnectionHandler>(request).map(move |result, _, _| {
+            if let Err(error) = result.map_err(DBError::from).and_then(|result| result) {
+                error.reply_to(&requestor_db_middleman, request_id);
+            }
+        }),
+    );
+}
+
+/// Waits for the answer to a query forwarded to the `StockHandler`, and replies to the requestor
+/// with the response built from it, or with the error it failed with.
+fn reply_with<T: 'static>(
+    ctx: &mut Context<ConnectionHandler>,
+    request: impl Future<Output = Result<Result<T, DBError>, MailboxError>> + 'static,
+    requestor_db_middleman: Addr<DBMiddleman>,
+    request_id: u64,
+    to_response: impl FnOnce(T) -> DBResponse + 'static,
+) {
+    ctx.spawn(
+        wrap_future::<_, ConnectionHandler>(request).map(move |result, _, _| {
+            let answer = match result.map_err(DBError::from).and_then(|result| result) {
+                Ok(answer) => answer,
+                Err(error) => {
+                    error.reply_to(&requestor_db_middleman, request_id);
+                    return;
+                }
+            };
+            let msg_to_send = match to_response(answer).to_string() {
+                Ok(msg_to_send) => msg_to_send,
+                Err(err) => {
+                    error!("[ConnectionHandler] Error serializing response: {}", err);
+                    return;
+                }
+            };
+            // Sent even if the mailbox is full, since the requestor waits for it.
+            requestor_db_middleman.do_send(SendOnlineMsg { msg_to_send });
+        }),
+    );
+}
+
+/// Waits for the quantities asked to the partitions, and handles the message built from them,
+/// which replies to the requestor, or replies with the error a partition failed with.
+fn reply_with_quantities<M>(
+    ctx: &mut Context<ConnectionHandler>,
+    request: impl Future<Output = Result<HashMap<String, HashMap<u16, i32>>, MailboxError>> + 'static,
+    requestor_db_middleman: Addr<DBMiddleman>,
+    request_id: u64,
+    to_reply: impl FnOnce(HashMap<String, HashMap<u16, i32>>) -> M + 'static,
+) where
+    M: Message + Send + 'static,
+    M::Result: Send,
+    ConnectionHandler: Handler<M>,
+{
+    ctx.spawn(wrap_future::<_, ConnectionHandler>(request).map(
+        move |result, _, ctx| match result {
+            Ok(products_quantity_in_locals) => ctx.notify(to_reply(products_quantity_in_locals)),
+            Err(err) => DBError::from(err).reply_to(&requestor_db_middleman, request_id),
+        },
+    ));
+}
+
 // ====================================================================
 
 #[derive(Message, Debug, PartialEq, Eq)]
@@ -60,8 +228,48 @@ impl Handler<SaveDBMiddlemanWithId> for ConnectionHandler {
     type Result = Result<(), String>;
 
     fn handle(&mut self, msg: SaveDBMiddlemanWithId, _: &mut Self::Context) -> Self::Result {
-        self.db_middlemen
-            .insert(msg.ecommerce_id, msg.db_middleman_addr);
+        if let Some(previous) = self
+            .db_middlemen
+            .insert(msg.ecommerce_id, msg.db_middleman_addr.clone())
+        {
+            if previous != msg.db_middleman_addr && previous.connected() {
+                warn!(
+                    "[ConnectionHandler] E-commerce {} connected again, replacing its previous connection",
+                    msg.ecommerce_id
+                );
+            }
+        }
+        info!(
+            "[ConnectionHandler] E-commerce {} connected. Connected e-commerce servers: {:?}",
+            msg.ecommerce_id,
+            self.connected_ecommerce_ids()
+        );
+        Ok(())
+    }
+}
+
+/// Sent by a `DBMiddleman` when its connection is closed.
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct RemoveDBMiddleman {
+    pub db_middleman_addr: Addr<DBMiddleman>,
+    pub ecommerce_id: u16,
+}
+
+impl Handler<RemoveDBMiddleman> for ConnectionHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: RemoveDBMiddleman, _: &mut Self::Context) -> Self::Result {
+        // If the e-commerce already connected again, its new middleman is kept.
+        if self.db_middlemen.get(&msg.ecommerce_id) != Some(&msg.db_middleman_addr) {
+            return Ok(());
+        }
+        self.db_middlemen.remove(&msg.ecommerce_id);
+        info!(
+            "[ConnectionHandler] E-commerce {} disconnected. Connected e-commerce servers: {:?}",
+            msg.ecommerce_id,
+            self.connected_ecommerce_ids()
+        );
         Ok(())
     }
 }
@@ -70,55 +278,644 @@ impl Handler<SaveDBMiddlemanWithId> for ConnectionHandler {
 #[rtype(result = "Result<(), String>")]
 pub struct GetNewLocalId {
     pub db_middleman_addr: Addr<DBMiddleman>,
+    pub request_id: u64,
+    pub epoch: u64,
 }
 
 impl Handler<GetNewLocalId> for ConnectionHandler {
     type Result = Result<(), String>;
 
-    fn handle(&mut self, msg: GetNewLocalId, _: &mut Self::Context) -> Self::Result {
-        let local_id = self.get_new_local_id();
-        let msg_to_send = DBResponse::NewLocalId { local_id }
-            .to_string()
-            .map_err(|err| err.to_string())?;
-        msg.db_middleman_addr
-            .try_send(SendOnlineMsg { msg_to_send })
-            .map_err(|err| err.to_string())
+    fn handle(&mut self, msg: GetNewLocalId, ctx: &mut Self::Context) -> Self::Result {
+        let local_id = match self.local_registry.next_id() {
+            Ok(local_id) => local_id,
+            Err(err) => {
+                DBError::new(DBErrorKind::LocalIdsExhausted, &err)
+                    .reply_to(&msg.db_middleman_addr, msg.request_id);
+                return Err(err);
+            }
+        };
+        let registered_at = current_timestamp();
+        let msg_to_send = DBResponse::NewLocalId {
+            request_id: msg.request_id,
+            local_id,
+        }
+        .to_string()
+        .map_err(|err| err.to_string())?;
+
+        // The id is only handed out once it is persisted, so that it is never issued again after a restart,
+        // and only taken once the epoch of the requestor is checked, so that a deposed leader cannot take it.
+        let record_request = self.stock_handler.send(stock_handler::RecordIssuedLocalId {
+            local_id,
+            registered_at,
+            epoch: msg.epoch,
+        });
+        ctx.wait(
+            wrap_future::<_, Self>(record_request).map(move |result, act, _| {
+                match result.map_err(DBError::from).and_then(|result| result) {
+                    Ok(()) => {
+                        act.local_registry.register(local_id, registered_at);
+                        info!("[ConnectionHandler] New local id issued: [{}]", local_id);
+                        msg.db_middleman_addr.do_send(SendOnlineMsg { msg_to_send });
+                    }
+                    Err(error) => error.reply_to(&msg.db_middleman_addr, msg.request_id),
+                }
+            }),
+        );
+        Ok(())
     }
 }
 
 #[derive(Message, Debug, PartialEq, Eq)]
 #[rtype(result = "Result<(), String>")]
 pub struct PostStockFromLocal {
+    pub requestor_db_middleman: Addr<DBMiddleman>,
+    pub request_id: u64,
     pub local_id: u16,
     pub stock: HashMap<String, Product>,
+    pub version: u64,
+    pub ecommerce_id: Option<u16>,
+    pub epoch: u64,
 }
 
 impl Handler<PostStockFromLocal> for ConnectionHandler {
     type Result = Result<(), String>;
 
-    fn handle(&mut self, msg: PostStockFromLocal, _: &mut Self::Context) -> Self::Result {
-        self.stock_handler
-            .try_send(stock_handler::PostStockFromLocal {
-                local_id: msg.local_id,
-                stock: msg.stock,
-            })
-            .map_err(|err| err.to_string())
+    fn handle(&mut self, msg: PostStockFromLocal, ctx: &mut Self::Context) -> Self::Result {
+        if let Err(error) = self.mark_local_seen(msg.local_id) {
+            error.reply_to(&msg.requestor_db_middleman, msg.request_id);
+            return Ok(());
+        }
+        let post_request = self.stock_handler.send(stock_handler::PostStockFromLocal {
+            local_id: msg.local_id,
+            stock: msg.stock,
+            version: msg.version,
+            ecommerce_id: msg.ecommerce_id,
+            epoch: msg.epoch,
+        });
+        reply_on_failure(
+            ctx,
+            post_request,
+            msg.requestor_db_middleman,
+            msg.request_id,
+        );
+        Ok(())
     }
 }
 
 #[derive(Message, Debug, PartialEq, Eq)]
 #[rtype(result = "Result<(), String>")]
 pub struct PostOrderResult {
+    pub requestor_db_middleman: Addr<DBMiddleman>,
+    pub request_id: u64,
     pub order: Order,
+    pub stock_version: u64,
+    pub ecommerce_id: Option<u16>,
+    pub epoch: u64,
 }
 
 impl Handler<PostOrderResult> for ConnectionHandler {
     type Result = Result<(), String>;
 
-    fn handle(&mut self, msg: PostOrderResult, _: &mut Self::Context) -> Self::Result {
-        self.stock_handler
-            .try_send(stock_handler::PostOrderResult { order: msg.order })
-            .map_err(|err| err.to_string())
+    fn handle(&mut self, msg: PostOrderResult, ctx: &mut Self::Context) -> Self::Result {
+        if let Some(local_id) = msg.order.get_local_id() {
+            if let Err(error) = self.mark_local_seen(local_id) {
+                error.reply_to(&msg.requestor_db_middleman, msg.request_id);
+                return Ok(());
+            }
+        }
+        let post_request = self.stock_handler.send(stock_handler::PostOrderResult {
+            order: msg.order,
+            stock_version: msg.stock_version,
+            ecommerce_id: msg.ecommerce_id,
+            epoch: msg.epoch,
+        });
+        ctx.spawn(
+            wrap_future::<_, Self>(post_request).map(move |result, _, _| {
+                match result.map_err(DBError::from).and_then(|result| result) {
+                    Ok(OrderResultOutcome::Accepted) => return,
+                    Ok(OrderResultOutcome::AlreadyApplied) => {}
+                    Err(error) => {
+                        error.reply_to(&msg.requestor_db_middleman, msg.request_id);
+                        return;
+                    }
+                }
+                let msg_to_send = match (DBResponse::OrderResultAlreadyApplied {
+                    request_id: msg.request_id,
+                })
+                .to_string()
+                {
+                    Ok(msg_to_send) => msg_to_send,
+                    Err(err) => {
+                        error!(
+                            "[ConnectionHandler] Error serializing order result: {}",
+                            err
+                        );
+                        return;
+                    }
+                };
+                msg.requestor_db_middleman
+                    .do_send(SendOnlineMsg { msg_to_send });
+            }),
+        );
+        Ok(())
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct PostStockDelta {
+    pub requestor_db_middleman: Addr<DBMiddleman>,
+    pub request_id: u64,
+    pub local_id: u16,
+    pub product_name: String,
+    pub change: i32,
+    pub version: u64,
+    pub ecommerce_id: Option<u16>,
+    pub epoch: u64,
+}
+
+impl Handler<PostStockDelta> for ConnectionHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: PostStockDelta, ctx: &mut Self::Context) -> Self::Result {
+        if let Err(error) = self.mark_local_seen(msg.local_id) {
+            error.reply_to(&msg.requestor_db_middleman, msg.request_id);
+            return Ok(());
+        }
+        let post_request = self.stock_handler.send(stock_handler::PostStockDelta {
+            local_id: msg.local_id,
+            product_name: msg.product_name,
+            change: msg.change,
+            version: msg.version,
+            ecommerce_id: msg.ecommerce_id,
+            epoch: msg.epoch,
+        });
+        reply_on_failure(
+            ctx,
+            post_request,
+            msg.requestor_db_middleman,
+            msg.request_id,
+        );
+        Ok(())
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct ReserveStock {
+    pub requestor_db_middleman: Addr<DBMiddleman>,
+    pub request_id: u64,
+    pub local_id: u16,
+    pub order_id: u64,
+    pub products: Vec<Product>,
+    pub epoch: u64,
+}
+
+impl Handler<ReserveStock> for ConnectionHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: ReserveStock, ctx: &mut Self::Context) -> Self::Result {
+        let reserve_request = self.stock_handler.send(stock_handler::ReserveStock {
+            local_id: msg.local_id,
+            order_id: msg.order_id,
+            products: msg.products,
+            epoch: msg.epoch,
+        });
+        ctx.spawn(
+            wrap_future::<_, Self>(reserve_request).map(move |result, _, _| {
+                let reservation_id = match result.map_err(DBError::from).and_then(|result| result) {
+                    Ok(reservation_id) => reservation_id,
+                    Err(error) => {
+                        error.reply_to(&msg.requestor_db_middleman, msg.request_id);
+                        return;
+                    }
+                };
+                let msg_to_send = match (DBResponse::StockReserved {
+                    request_id: msg.request_id,
+                    order_id: msg.order_id,
+                    reservation_id,
+                })
+                .to_string()
+                {
+                    Ok(msg_to_send) => msg_to_send,
+                    Err(err) => {
+                        error!("[ConnectionHandler] Error serializing reservation: {}", err);
+                        return;
+                    }
+                };
+                msg.requestor_db_middleman
+                    .do_send(SendOnlineMsg { msg_to_send });
+            }),
+        );
+        Ok(())
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct ReleaseReservation {
+    pub requestor_db_middleman: Addr<DBMiddleman>,
+    pub request_id: u64,
+    pub reservation_id: u64,
+    pub epoch: u64,
+}
+
+impl Handler<ReleaseReservation> for ConnectionHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: ReleaseReservation, ctx: &mut Self::Context) -> Self::Result {
+        let release_request = self.stock_handler.send(stock_handler::ReleaseReservation {
+            reservation_id: msg.reservation_id,
+            epoch: msg.epoch,
+        });
+        reply_on_failure(
+            ctx,
+            release_request,
+            msg.requestor_db_middleman,
+            msg.request_id,
+        );
+        Ok(())
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct GetStockHistory {
+    pub requestor_db_middleman: Addr<DBMiddleman>,
+    pub request_id: u64,
+    pub local_id: Option<u16>,
+    pub product_name: Option<String>,
+    pub from: u64,
+    pub to: u64,
+}
+
+impl Handler<GetStockHistory> for ConnectionHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: GetStockHistory, ctx: &mut Self::Context) -> Self::Result {
+        let history_request = self.stock_handler.send(stock_handler::GetStockHistory {
+            local_id: msg.local_id,
+            product_name: msg.product_name,
+            from: msg.from,
+            to: msg.to,
+        });
+        reply_with(
+            ctx,
+            async move { history_request.await.map(Ok) },
+            msg.requestor_db_middleman,
+            msg.request_id,
+            move |changes| DBResponse::StockHistory {
+                request_id: msg.request_id,
+                changes,
+            },
+        );
+        Ok(())
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct GetOrderResults {
+    pub requestor_db_middleman: Addr<DBMiddleman>,
+    pub request_id: u64,
+    pub local_id: Option<u16>,
+    pub ecommerce_id: Option<u16>,
+    pub product_name: Option<String>,
+    pub from: u64,
+    pub to: u64,
+}
+
+impl Handler<GetOrderResults> for ConnectionHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: GetOrderResults, ctx: &mut Self::Context) -> Self::Result {
+        let order_results_request = self.stock_handler.send(stock_handler::GetOrderResults {
+            local_id: msg.local_id,
+            ecommerce_id: msg.ecommerce_id,
+            product_name: msg.product_name,
+            from: msg.from,
+            to: msg.to,
+        });
+        reply_with(
+            ctx,
+            async move { order_results_request.await.map(Ok) },
+            msg.requestor_db_middleman,
+            msg.request_id,
+            move |order_results| DBResponse::OrderResults {
+                request_id: msg.request_id,
+                order_results,
+            },
+        );
+        Ok(())
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct GetCatalogProduct {
+    pub requestor_db_middleman: Addr<DBMiddleman>,
+    pub request_id: u64,
+    pub product_name: String,
+}
+
+impl Handler<GetCatalogProduct> for ConnectionHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: GetCatalogProduct, ctx: &mut Self::Context) -> Self::Result {
+        let catalog_request = self.stock_handler.send(stock_handler::GetCatalogProduct {
+            product_name: msg.product_name,
+        });
+        reply_with(
+            ctx,
+            catalog_request,
+            msg.requestor_db_middleman,
+            msg.request_id,
+            move |product| DBResponse::CatalogProduct {
+                request_id: msg.request_id,
+                product,
+            },
+        );
+        Ok(())
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct GetCatalog {
+    pub requestor_db_middleman: Addr<DBMiddleman>,
+    pub request_id: u64,
+    pub category: Option<String>,
+}
+
+impl Handler<GetCatalog> for ConnectionHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: GetCatalog, ctx: &mut Self::Context) -> Self::Result {
+        let catalog_request = self.stock_handler.send(stock_handler::GetCatalog {
+            category: msg.category,
+        });
+        reply_with(
+            ctx,
+            async move { catalog_request.await.map(Ok) },
+            msg.requestor_db_middleman,
+            msg.request_id,
+            move |products| DBResponse::Catalog {
+                request_id: msg.request_id,
+                products,
+            },
+        );
+        Ok(())
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct ReconcileStock {
+    pub requestor_db_middleman: Addr<DBMiddleman>,
+    pub request_id: u64,
+    pub local_id: u16,
+    pub stock: HashMap<String, Product>,
+    pub version: u64,
+    pub ecommerce_id: Option<u16>,
+    pub epoch: u64,
+}
+
+impl Handler<ReconcileStock> for ConnectionHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: ReconcileStock, ctx: &mut Self::Context) -> Self::Result {
+        if let Err(error) = self.mark_local_seen(msg.local_id) {
+            error.reply_to(&msg.requestor_db_middleman, msg.request_id);
+            return Ok(());
+        }
+        let reconcile_request = self.stock_handler.send(stock_handler::ReconcileStock {
+            local_id: msg.local_id,
+            stock: msg.stock,
+            version: msg.version,
+            ecommerce_id: msg.ecommerce_id,
+            epoch: msg.epoch,
+        });
+        let local_id = msg.local_id;
+        reply_with(
+            ctx,
+            reconcile_request,
+            msg.requestor_db_middleman,
+            msg.request_id,
+            move |reconciliation| DBResponse::StockReconciled {
+                request_id: msg.request_id,
+                local_id,
+                policy: reconciliation.policy,
+                discrepancies: reconciliation.discrepancies,
+                corrections: reconciliation.corrections,
+            },
+        );
+        Ok(())
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct SubscribeToStock {
+    pub requestor_db_middleman: Addr<DBMiddleman>,
+    pub request_id: u64,
+    pub product_names: Vec<String>,
+    pub below: Option<i32>,
+}
+
+impl Handler<SubscribeToStock> for ConnectionHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: SubscribeToStock, ctx: &mut Self::Context) -> Self::Result {
+        let subscribe_request = self.stock_handler.send(stock_handler::SubscribeToStock {
+            subscriber: msg.requestor_db_middleman.clone(),
+            filter: StockFilter {
+                product_names: msg.product_names.into_iter().collect(),
+                below: msg.below,
+            },
+        });
+        reply_with(
+            ctx,
+            async move { subscribe_request.await.map(Ok) },
+            msg.requestor_db_middleman,
+            msg.request_id,
+            move |subscription_id| DBResponse::Subscribed {
+                request_id: msg.request_id,
+                subscription_id,
+            },
+        );
+        Ok(())
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct UnsubscribeFromStock {
+    pub requestor_db_middleman: Addr<DBMiddleman>,
+    pub request_id: u64,
+    pub subscription_id: u64,
+}
+
+impl Handler<UnsubscribeFromStock> for ConnectionHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: UnsubscribeFromStock, ctx: &mut Self::Context) -> Self::Result {
+        let unsubscribe_request = self
+            .stock_handler
+            .send(stock_handler::UnsubscribeFromStock {
+                subscriber: msg.requestor_db_middleman.clone(),
+                subscription_id: msg.subscription_id,
+            });
+        reply_with(
+            ctx,
+            unsubscribe_request,
+            msg.requestor_db_middleman,
+            msg.request_id,
+            move |()| DBResponse::Unsubscribed {
+                request_id: msg.request_id,
+            },
+        );
+        Ok(())
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct AcquireLeaderEpoch {
+    pub requestor_db_middleman: Addr<DBMiddleman>,
+    pub request_id: u64,
+}
+
+impl Handler<AcquireLeaderEpoch> for ConnectionHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: AcquireLeaderEpoch, ctx: &mut Self::Context) -> Self::Result {
+        let epoch_request = self
+            .stock_handler
+            .send(stock_handler::AcquireLeaderEpoch {});
+        reply_with(
+            ctx,
+            epoch_request,
+            msg.requestor_db_middleman,
+            msg.request_id,
+            move |epoch| DBResponse::LeaderEpoch {
+                request_id: msg.request_id,
+                epoch,
+            },
+        );
+        Ok(())
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct GetLocals {
+    pub requestor_db_middleman: Addr<DBMiddleman>,
+    pub request_id: u64,
+}
+
+impl Handler<GetLocals> for ConnectionHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: GetLocals, ctx: &mut Self::Context) -> Self::Result {
+        let locals_request = self.stock_handler.send(stock_handler::GetLocals {});
+        reply_with(
+            ctx,
+            async move { locals_request.await.map(Ok) },
+            msg.requestor_db_middleman,
+            msg.request_id,
+            move |local_ids| DBResponse::Locals {
+                request_id: msg.request_id,
+                local_ids,
+            },
+        );
+        Ok(())
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct GetLocalStock {
+    pub requestor_db_middleman: Addr<DBMiddleman>,
+    pub request_id: u64,
+    pub local_id: u16,
+}
+
+impl Handler<GetLocalStock> for ConnectionHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: GetLocalStock, ctx: &mut Self::Context) -> Self::Result {
+        let stock_request = self.stock_handler.send(stock_handler::GetLocalStock {
+            local_id: msg.local_id,
+        });
+        reply_with(
+            ctx,
+            stock_request,
+            msg.requestor_db_middleman,
+            msg.request_id,
+            move |quantity_by_product| DBResponse::LocalStock {
+                request_id: msg.request_id,
+                local_id: msg.local_id,
+                quantity_by_product,
+            },
+        );
+        Ok(())
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct GetTotalQuantityByProduct {
+    pub requestor_db_middleman: Addr<DBMiddleman>,
+    pub request_id: u64,
+}
+
+impl Handler<GetTotalQuantityByProduct> for ConnectionHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: GetTotalQuantityByProduct, ctx: &mut Self::Context) -> Self::Result {
+        let totals_request = self
+            .stock_handler
+            .send(stock_handler::GetTotalQuantityByProduct {});
+        reply_with(
+            ctx,
+            async move { totals_request.await.map(Ok) },
+            msg.requestor_db_middleman,
+            msg.request_id,
+            move |quantity_by_product| DBResponse::TotalQuantityByProduct {
+                request_id: msg.request_id,
+                quantity_by_product,
+            },
+        );
+        Ok(())
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct GetTopProducts {
+    pub requestor_db_middleman: Addr<DBMiddleman>,
+    pub request_id: u64,
+    pub limit: usize,
+}
+
+impl Handler<GetTopProducts> for ConnectionHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: GetTopProducts, ctx: &mut Self::Context) -> Self::Result {
+        let top_request = self
+            .stock_handler
+            .send(stock_handler::GetTopProducts { limit: msg.limit });
+        reply_with(
+            ctx,
+            async move { top_request.await.map(Ok) },
+            msg.requestor_db_middleman,
+            msg.request_id,
+            move |products| DBResponse::TopProducts {
+                request_id: msg.request_id,
+                products,
+            },
+        );
+        Ok(())
     }
 }
 
@@ -126,6 +923,7 @@ impl Handler<PostOrderResult> for ConnectionHandler {
 #[rtype(result = "Result<(), String>")]
 pub struct GetProductQuantityFromAllLocals {
     pub requestor_db_middleman: Addr<DBMiddleman>,
+    pub request_id: u64,
     pub requestor_ss_id: u16,
     pub requestor_worker_id: u16,
     pub product_name: String,
@@ -139,15 +937,47 @@ impl Handler<GetProductQuantityFromAllLocals> for ConnectionHandler {
         msg: GetProductQuantityFromAllLocals,
         ctx: &mut Self::Context,
     ) -> Self::Result {
-        self.stock_handler
-            .try_send(stock_handler::GetProductQuantityFromAllLocals {
-                requestor_db_middleman: msg.requestor_db_middleman,
-                connection_handler: ctx.address(),
-                requestor_ss_id: msg.requestor_ss_id,
-                requestor_worker_id: msg.requestor_worker_id,
-                product_name: msg.product_name,
-            })
-            .map_err(|err| err.to_string())
+        if !self.stock_partitions.is_empty() {
+            let request = self.quantities_from_partitions(std::slice::from_ref(&msg.product_name));
+            let requestor_db_middleman = msg.requestor_db_middleman.clone();
+            reply_with_quantities(
+                ctx,
+                request,
+                requestor_db_middleman,
+                msg.request_id,
+                move |mut products_quantity_in_locals| {
+                    ReplyToRequestorWithProductQuantityFromAllLocals {
+                        requestor_db_middleman: msg.requestor_db_middleman,
+                        request_id: msg.request_id,
+                        product_quantity_in_locals: products_quantity_in_locals
+                            .remove(&msg.product_name)
+                            .unwrap_or_default(),
+                        requestor_ss_id: msg.requestor_ss_id,
+                        requestor_worker_id: msg.requestor_worker_id,
+                        product_name: msg.product_name,
+                    }
+                },
+            );
+            return Ok(());
+        }
+        // Waits for room in the mailbox instead of failing, since the requestor waits for the answer.
+        let query_request =
+            self.stock_handler
+                .send(stock_handler::GetProductQuantityFromAllLocals {
+                    requestor_db_middleman: msg.requestor_db_middleman.clone(),
+                    request_id: msg.request_id,
+                    connection_handler: ctx.address(),
+                    requestor_ss_id: msg.requestor_ss_id,
+                    requestor_worker_id: msg.requestor_worker_id,
+                    product_name: msg.product_name,
+                });
+        reply_on_failure(
+            ctx,
+            query_request,
+            msg.requestor_db_middleman,
+            msg.request_id,
+        );
+        Ok(())
     }
 }
 
@@ -155,6 +985,7 @@ impl Handler<GetProductQuantityFromAllLocals> for ConnectionHandler {
 #[rtype(result = "Result<(),String>")]
 pub struct ReplyToRequestorWithProductQuantityFromAllLocals {
     pub requestor_db_middleman: Addr<DBMiddleman>,
+    pub request_id: u64,
     pub product_quantity_in_locals: HashMap<u16, i32>,
     pub requestor_ss_id: u16,
     pub requestor_worker_id: u16,
@@ -166,10 +997,12 @@ impl Handler<ReplyToRequestorWithProductQuantityFromAllLocals> for ConnectionHan
 
     fn handle(
         &mut self,
-        msg: ReplyToRequestorWithProductQuantityFromAllLocals,
+        mut msg: ReplyToRequestorWithProductQuantityFromAllLocals,
         _: &mut Self::Context,
     ) -> Self::Result {
+        self.omit_offline_locals(&mut msg.product_quantity_in_locals);
         let msg_to_send = DBResponse::ProductQuantityFromAllLocals {
+            request_id: msg.request_id,
             ss_id: msg.requestor_ss_id,
             worker_id: msg.requestor_worker_id,
             product_name: msg.product_name,
@@ -178,7 +1011,325 @@ impl Handler<ReplyToRequestorWithProductQuantityFromAllLocals> for ConnectionHan
         .to_string()
         .map_err(|err| err.to_string())?;
         msg.requestor_db_middleman
-            .try_send(SendOnlineMsg { msg_to_send })
-            .map_err(|err| err.to_string())
+            .do_send(SendOnlineMsg { msg_to_send });
+        Ok(())
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct GetProductsQuantityFromAllLocals {
+    pub requestor_db_middleman: Addr<DBMiddleman>,
+    pub request_id: u64,
+    pub requestor_ss_id: u16,
+    pub requestor_worker_id: u16,
+    pub product_names: Vec<String>,
+}
+
+impl Handler<GetProductsQuantityFromAllLocals> for ConnectionHandler {
+    type Result = Result<(), String>;
+
+    fn handle(
+        &mut self,
+        msg: GetProductsQuantityFromAllLocals,
+        ctx: &mut Self::Context,
+    ) -> Self::Result {
+        if !self.stock_partitions.is_empty() {
+            let request = self.quantities_from_partitions(&msg.product_names);
+            let requestor_db_middleman = msg.requestor_db_middleman.clone();
+            reply_with_quantities(
+                ctx,
+                request,
+                requestor_db_middleman,
+                msg.request_id,
+                move |products_quantity_in_locals| {
+                    ReplyToRequestorWithProductsQuantityFromAllLocals {
+                        requestor_db_middleman: msg.requestor_db_middleman,
+                        request_id: msg.request_id,
+                        products_quantity_in_locals,
+                        requestor_ss_id: msg.requestor_ss_id,
+                        requestor_worker_id: msg.requestor_worker_id,
+                    }
+                },
+            );
+            return Ok(());
+        }
+        // Waits for room in the mailbox instead of failing, since the requestor waits for the answer.
+        let query_request =
+            self.stock_handler
+                .send(stock_handler::GetProductsQuantityFromAllLocals {
+                    requestor_db_middleman: msg.requestor_db_middleman.clone(),
+                    request_id: msg.request_id,
+                    connection_handler: ctx.address(),
+                    requestor_ss_id: msg.requestor_ss_id,
+                    requestor_worker_id: msg.requestor_worker_id,
+                    product_names: msg.product_names,
+                });
+        reply_on_failure(
+            ctx,
+            query_request,
+            msg.requestor_db_middleman,
+            msg.request_id,
+        );
+        Ok(())
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(),String>")]
+pub struct ReplyToRequestorWithProductsQuantityFromAllLocals {
+    pub requestor_db_middleman: Addr<DBMiddleman>,
+    pub request_id: u64,
+    pub products_quantity_in_locals: HashMap<String, HashMap<u16, i32>>,
+    pub requestor_ss_id: u16,
+    pub requestor_worker_id: u16,
+}
+
+impl Handler<ReplyToRequestorWithProductsQuantityFromAllLocals> for ConnectionHandler {
+    type Result = Result<(), String>;
+
+    fn handle(
+        &mut self,
+        mut msg: ReplyToRequestorWithProductsQuantityFromAllLocals,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        for quantity_by_local_id in msg.products_quantity_in_locals.values_mut() {
+            self.omit_offline_locals(quantity_by_local_id);
+        }
+        let msg_to_send = DBResponse::ProductsQuantityFromAllLocals {
+            request_id: msg.request_id,
+            ss_id: msg.requestor_ss_id,
+            worker_id: msg.requestor_worker_id,
+            product_quantity_by_local_id: msg.products_quantity_in_locals,
+        }
+        .to_string()
+        .map_err(|err| err.to_string())?;
+        msg.requestor_db_middleman
+            .do_send(SendOnlineMsg { msg_to_send });
+        Ok(())
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct LocalsHeartbeat {
+    pub requestor_db_middleman: Addr<DBMiddleman>,
+    pub request_id: u64,
+    pub local_ids: Vec<u16>,
+    pub epoch: u64,
+}
+
+impl Handler<LocalsHeartbeat> for ConnectionHandler {
+    type Result = Result<(), String>;
+
+    /// Only the leader keeps the locals connected to it online, so that a deposed one cannot keep
+    /// orders being assigned to locals it is no longer in touch with.
+    fn handle(&mut self, msg: LocalsHeartbeat, ctx: &mut Self::Context) -> Self::Result {
+        let epoch_check = self
+            .stock_handler
+            .send(stock_handler::CheckLeaderEpoch { epoch: msg.epoch });
+        ctx.wait(
+            wrap_future::<_, Self>(epoch_check).map(move |result, act, _| {
+                if let Err(error) = result.map_err(DBError::from).and_then(|result| result) {
+                    error.reply_to(&msg.requestor_db_middleman, msg.request_id);
+                    return;
+                }
+                let now = current_timestamp();
+                for local_id in msg.local_ids {
+                    if let Err(err) = act.local_registry.mark_seen(local_id, now) {
+                        warn!("[ConnectionHandler] Ignoring heartbeat: {}", err);
+                    }
+                }
+            }),
+        );
+        Ok(())
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct RegisterBackup {
+    pub backup_db_middleman: Addr<DBMiddleman>,
+    pub request_id: u64,
+}
+
+impl Handler<RegisterBackup> for ConnectionHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: RegisterBackup, ctx: &mut Self::Context) -> Self::Result {
+        // No local id can be issued until the backup has received the full state,
+        // otherwise it could miss it.
+        let add_backup_request = self.stock_handler.send(stock_handler::AddBackup {
+            backup_db_middleman: msg.backup_db_middleman.clone(),
+            local_registry: self.local_registry.clone(),
+        });
+        ctx.wait(
+            wrap_future::<_, Self>(add_backup_request).map(move |result, _, _| match result {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => DBError::new(DBErrorKind::Internal, err)
+                    .reply_to(&msg.backup_db_middleman, msg.request_id),
+                Err(err) => DBError::from(err).reply_to(&msg.backup_db_middleman, msg.request_id),
+            }),
+        );
+        Ok(())
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct ApplyReplicationMessage {
+    pub message: ReplicationMessage,
+}
+
+impl Handler<ApplyReplicationMessage> for ConnectionHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: ApplyReplicationMessage, _: &mut Self::Context) -> Self::Result {
+        match msg.message {
+            ReplicationMessage::FullState { mut snapshot } => {
+                snapshot.migrate()?;
+                info!(
+                    "[ConnectionHandler] Received full state from upstream database, up to log record {}",
+                    snapshot.last_lsn
+                );
+                self.local_registry = snapshot.local_registry.clone();
+                // Sent even if the mailbox is full, since the state would diverge from upstream without it.
+                self.stock_handler
+                    .do_send(stock_handler::RestoreFromUpstream {
+                        snapshot: *snapshot,
+                    });
+                Ok(())
+            }
+            ReplicationMessage::Record { record } => {
+                if let WalEntry::LocalIdIssued {
+                    local_id,
+                    registered_at,
+                } = record.entry
+                {
+                    self.local_registry.register(local_id, registered_at);
+                }
+                self.stock_handler
+                    .do_send(stock_handler::ApplyReplicatedRecord { record });
+                Ok(())
+            }
+        }
+    }
+}
+
+// ====================================================================
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct ShowStock {
+    pub local_id: Option<u16>,
+}
+
+impl Handler<ShowStock> for ConnectionHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: ShowStock, ctx: &mut Self::Context) -> Self::Result {
+        let show_request = self.stock_handler.send(stock_handler::ShowStock {
+            local_id: msg.local_id,
+        });
+        ctx.spawn(wrap_future::<_, Self>(show_request).map(|result, _, _| {
+            if let Err(err) = result
+                .map_err(|err| err.to_string())
+                .and_then(|result| result)
+            {
+                error!("[ConnectionHandler] Error showing stock: {}", err);
+            }
+        }));
+        Ok(())
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct ExportStock {
+    pub dir: PathBuf,
+}
+
+impl Handler<ExportStock> for ConnectionHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: ExportStock, ctx: &mut Self::Context) -> Self::Result {
+        let export_request = self.stock_handler.send(stock_handler::ExportStock {
+            dir: msg.dir.clone(),
+        });
+        ctx.spawn(
+            wrap_future::<_, Self>(export_request).map(move |result, _, _| {
+                match result
+                    .map_err(|err| err.to_string())
+                    .and_then(|result| result)
+                {
+                    Ok(()) => info!("[ConnectionHandler] Stock exported to {:?}", msg.dir),
+                    Err(err) => error!("[ConnectionHandler] Error exporting stock: {}", err),
+                }
+            }),
+        );
+        Ok(())
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct ShowConnectedServers {}
+
+impl Handler<ShowConnectedServers> for ConnectionHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, _: ShowConnectedServers, _: &mut Self::Context) -> Self::Result {
+        info!(
+            "[ConnectionHandler] Connected e-commerce servers: {:?}",
+            self.connected_ecommerce_ids()
+        );
+        Ok(())
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct ShowLastLocalId {}
+
+impl Handler<ShowLastLocalId> for ConnectionHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, _: ShowLastLocalId, _: &mut Self::Context) -> Self::Result {
+        info!(
+            "[ConnectionHandler] Last local id issued: {}",
+            self.local_registry.last_local_id()
+        );
+        Ok(())
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct ForgetLocal {
+    pub local_id: u16,
+}
+
+impl Handler<ForgetLocal> for ConnectionHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: ForgetLocal, ctx: &mut Self::Context) -> Self::Result {
+        let forget_request = self.stock_handler.send(stock_handler::ForgetLocal {
+            local_id: msg.local_id,
+        });
+        ctx.spawn(
+            wrap_future::<_, Self>(forget_request).map(move |result, _, _| {
+                match result
+                    .map_err(|err| err.to_string())
+                    .and_then(|result| result)
+                {
+                    Ok(()) => info!("[ConnectionHandler] Local {} forgotten", msg.local_id),
+                    Err(err) => error!(
+                        "[ConnectionHandler] Error forgetting local {}: {}",
+                        msg.local_id, err
+                    ),
+                }
+            }),
+        );
+        Ok(())
     }
 }
@@ -0,0 +1,27 @@
+pub const WAL_FILENAME: &str = "stock.wal";
+pub const SNAPSHOT_FILENAME: &str = "stock.snapshot";
+pub const SNAPSHOT_INTERVAL_SECS: u64 = 30;
+pub const SNAPSHOT_FORMAT_VERSION: u32 = 1;
+pub const MAX_STOCK_HISTORY_LEN: usize = 10_000;
+pub const STOCK_JSON_FILENAME: &str = "stock.json";
+pub const LOCAL_STOCK_FILE_PREFIX: &str = "local_";
+pub const LOCAL_STOCK_FILE_EXTENSION: &str = ".txt";
+pub const STOCK_STORE_DIRNAME: &str = "stock_store";
+pub const STOCK_STORE_FILE_EXTENSION: &str = ".json";
+pub const DEFAULT_STALE_LOCAL_THRESHOLD_SECS: u64 = 60;
+pub const DEFAULT_STOCK_PARTITIONS: usize = 0;
+pub const MAX_QUEUED_REQUESTS_PER_CONNECTION: usize = 32;
+pub const RESERVATION_TTL_SECS: u64 = 120;
+pub const RESERVATION_EXPIRY_CHECK_INTERVAL_SECS: u64 = 10;
+
+pub const UPSTREAM_CONNECTION_ATTEMPTS: u32 = 3;
+pub const UPSTREAM_RETRY_DELAY_MILLIS: u64 = 1000;
+pub const EXIT_POLL_INTERVAL_MILLIS: u64 = 500;
+pub const UPSTREAM_PROBE_INTERVAL_MILLIS: u64 = 2000;
+
+// ==================== COMMANDS ====================
+pub const SHOW_STOCK_COMMAND: &str = "stock";
+pub const SHOW_SERVERS_COMMAND: &str = "servers";
+pub const SHOW_LAST_LOCAL_ID_COMMAND: &str = "last_id";
+pub const FORGET_LOCAL_COMMAND: &str = "forget";
+pub const EXPORT_STOCK_COMMAND: &str = "export";
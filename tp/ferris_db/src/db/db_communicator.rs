@@ -3,10 +3,13 @@
 //! It uses the `actix` framework for actor creation upon connection establishment, and `tokio` for async I/O and task spawning.
 //!
 //! The main entry point is the `setup_db_listener` function, which spawns a task to handle incoming connections from servers.
+//!
+//! Connections must present the token the database was started with before any other request,
+//! or any token if it was started as insecure.
 
 use actix::{Actor, Addr, StreamHandler};
 use actix_rt::System;
-use shared::model::constants::{DATABASE_IP, EXIT_COMMAND};
+use shared::model::constants::EXIT_COMMAND;
 use std::sync::{mpsc, Arc};
 use tokio::{
     io::{split, AsyncBufReadExt, BufReader},
@@ -15,16 +18,25 @@ use tokio::{
     task::JoinHandle,
 };
 use tokio_stream::wrappers::LinesStream;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 use super::{connection_handler::ConnectionHandler, db_middleman::DBMiddleman};
 
 pub fn setup_db_listener(
     connection_handler: Addr<ConnectionHandler>,
+    listen_addr: String,
     rx_from_input: mpsc::Receiver<String>,
+    expected_token: Option<String>,
 ) -> JoinHandle<()> {
     actix::spawn(async move {
-        if let Err(e) = handle_incoming_servers(connection_handler, rx_from_input).await {
+        if let Err(e) = handle_incoming_servers(
+            connection_handler,
+            listen_addr,
+            rx_from_input,
+            expected_token,
+        )
+        .await
+        {
             error!("{}", e);
             if let Some(system) = System::try_current() {
                 system.stop()
@@ -35,19 +47,24 @@ pub fn setup_db_listener(
 
 async fn handle_incoming_servers(
     connection_handler: Addr<ConnectionHandler>,
+    listen_addr: String,
     rx_from_input: mpsc::Receiver<String>,
+    expected_token: Option<String>,
 ) -> Result<(), String> {
-    let listener = AsyncTcpListener::bind(DATABASE_IP)
+    let listener = AsyncTcpListener::bind(&listen_addr)
         .await
         .map_err(|err| err.to_string())?;
-    info!("[{}] Listening to servers...", DATABASE_IP);
+    info!("[{}] Listening to servers...", listen_addr);
+    if expected_token.is_none() {
+        warn!("Started as insecure, connections are accepted with any token");
+    }
     loop {
         if let Ok((stream, stream_addr)) = listener.accept().await {
             if is_exit_required(&rx_from_input) {
                 return Ok(());
             }
             info!("Server connected: [{:?}]", stream_addr);
-            handle_connected_server(stream, &connection_handler)?;
+            handle_connected_server(stream, &connection_handler, &expected_token)?;
         };
     }
 }
@@ -55,21 +72,19 @@ async fn handle_incoming_servers(
 fn handle_connected_server(
     stream: AsyncTcpStream,
     connection_handler: &Addr<ConnectionHandler>,
+    expected_token: &Option<String>,
 ) -> Result<(), String> {
     let (read, write_half) = split(stream);
     let writer = Arc::new(Mutex::new(write_half));
     DBMiddleman::create(|ctx| {
         DBMiddleman::add_stream(LinesStream::new(BufReader::new(read).lines()), ctx);
-        DBMiddleman {
-            writer,
-            connection_handler: connection_handler.clone(),
-        }
+        DBMiddleman::new(writer, connection_handler.clone(), expected_token.clone())
     });
 
     Ok(())
 }
 
-fn is_exit_required(rx_from_input: &mpsc::Receiver<String>) -> bool {
+pub fn is_exit_required(rx_from_input: &mpsc::Receiver<String>) -> bool {
     if let Ok(msg) = rx_from_input.try_recv() {
         if msg == EXIT_COMMAND {
             return true;
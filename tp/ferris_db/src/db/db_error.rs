@@ -0,0 +1,83 @@
+//! This module contains the errors that the database reports back to the requestors,
+//! as `DBResponse::Error` messages.
+//!
+//! A request that finds the mailbox of the next actor full is answered as `Overloaded`, so the
+//! requestor knows it can send it again later instead of waiting for a reply that never comes.
+
+use actix::{prelude::SendError, Addr, MailboxError};
+use shared::communication::db_response::{DBErrorKind, DBResponse};
+use tracing::{error, warn};
+
+use super::db_middleman::{DBMiddleman, SendOnlineMsg};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DBError {
+    pub kind: DBErrorKind,
+    pub detail: String,
+}
+
+impl DBError {
+    pub fn new(kind: DBErrorKind, detail: impl ToString) -> Self {
+        DBError {
+            kind,
+            detail: detail.to_string(),
+        }
+    }
+
+    /// Reports the error to the requestor of the request with the given id.
+    pub fn reply_to(self, requestor_db_middleman: &Addr<DBMiddleman>, request_id: u64) {
+        warn!(
+            "[DBError] Request [{}] failed ({:?}): {}",
+            request_id, self.kind, self.detail
+        );
+        let msg_to_send = match (DBResponse::Error {
+            request_id,
+            kind: self.kind,
+            detail: self.detail,
+        })
+        .to_string()
+        {
+            Ok(msg_to_send) => msg_to_send,
+            Err(err) => {
+                error!("[DBError] Error serializing error response: {}", err);
+                return;
+            }
+        };
+        // Sent even if the mailbox is full, since the requestor waits for it.
+        requestor_db_middleman.do_send(SendOnlineMsg { msg_to_send });
+    }
+}
+
+impl From<MailboxError> for DBError {
+    fn from(err: MailboxError) -> Self {
+        DBError::new(DBErrorKind::Internal, err)
+    }
+}
+
+impl<M> From<SendError<M>> for DBError {
+    fn from(err: SendError<M>) -> Self {
+        match err {
+            SendError::Full(_) => DBError::new(DBErrorKind::Overloaded, err),
+            SendError::Closed(_) => DBError::new(DBErrorKind::Internal, err),
+        }
+    }
+}
+
+// ====================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_a_full_mailbox_is_reported_as_overloaded() {
+        assert_eq!(
+            DBError::from(SendError::Full(())).kind,
+            DBErrorKind::Overloaded
+        );
+        assert_eq!(
+            DBError::from(SendError::Closed(())).kind,
+            DBErrorKind::Internal
+        );
+    }
+}
@@ -2,24 +2,165 @@
 //!
 //! The `DBMiddleman` actor is responsible for handling messages received from the database and forwarding them to the `ConnectionHandler`.
 //!
+//! When the database is configured with a token, the first request on a connection must present
+//! it with `DBRequest::Authenticate`. Otherwise, the connection is answered with an `Unauthorized`
+//! error and closed.
+//!
+//! Requests are forwarded to the `ConnectionHandler` in the order they arrive. While its mailbox is
+//! full they wait in the queue of this connection, and once that queue is full too every new
+//! request is answered with an `Overloaded` error, so that the e-commerce server can send it again
+//! later instead of waiting for an answer that never comes.
+//!
+//! Once the connection is closed, the `ConnectionHandler` is told to forget this middleman.
 
 use super::connection_handler::{
-    ConnectionHandler, GetNewLocalId, GetProductQuantityFromAllLocals, PostOrderResult,
-    PostStockFromLocal, SaveDBMiddlemanWithId,
+    AcquireLeaderEpoch, ConnectionHandler, GetCatalog, GetCatalogProduct, GetLocalStock, GetLocals,
+    GetNewLocalId, GetOrderResults, GetProductQuantityFromAllLocals,
+    GetProductsQuantityFromAllLocals, GetStockHistory, GetTopProducts, GetTotalQuantityByProduct,
+    LocalsHeartbeat, PostOrderResult, PostStockDelta, PostStockFromLocal, ReconcileStock,
+    RegisterBackup, ReleaseReservation, RemoveDBMiddleman, ReserveStock, SaveDBMiddlemanWithId,
+    SubscribeToStock, UnsubscribeFromStock,
 };
+use super::{constants::MAX_QUEUED_REQUESTS_PER_CONNECTION, db_error::DBError};
 use actix::{fut::wrap_future, prelude::*};
-use shared::communication::db_request::DBRequest;
+use shared::{
+    communication::{
+        db_auth::tokens_match,
+        db_request::DBRequest,
+        db_response::{DBErrorKind, DBResponse},
+    },
+    model::constants::UNKNOWN_REQUEST_ID,
+};
 use std::sync::Arc;
 use tokio::{
     io::{AsyncWriteExt, WriteHalf},
     net::TcpStream,
     sync::Mutex,
 };
-use tracing::{debug, error, warn};
+use tracing::{debug, info, warn};
 
 pub struct DBMiddleman {
     pub writer: Arc<Mutex<WriteHalf<TcpStream>>>,
     pub connection_handler: Addr<ConnectionHandler>,
+    /// The id of the e-commerce server at the other end, once it tells it.
+    pub ecommerce_id: Option<u16>,
+    /// The token the other end must present before any other request, or `None` if the
+    /// database was started as insecure and any token is accepted.
+    pub expected_token: Option<String>,
+    pub authenticated: bool,
+    /// The requests waiting for room in the mailbox of the `ConnectionHandler`.
+    pub queued_requests: usize,
+    /// The most requests that were ever waiting at once.
+    pub peak_queued_requests: usize,
+    /// The requests answered with `Overloaded` because the queue was full.
+    pub overloaded_requests: u64,
+}
+
+impl DBMiddleman {
+    pub fn new(
+        writer: Arc<Mutex<WriteHalf<TcpStream>>>,
+        connection_handler: Addr<ConnectionHandler>,
+        expected_token: Option<String>,
+    ) -> Self {
+        DBMiddleman {
+            writer,
+            connection_handler,
+            ecommerce_id: None,
+            authenticated: false,
+            expected_token,
+            queued_requests: 0,
+            peak_queued_requests: 0,
+            overloaded_requests: 0,
+        }
+    }
+
+    /// Forwards a request to the `ConnectionHandler`, queueing it while its mailbox is full, or
+    /// answers it with `Overloaded` if the queue of this connection is full too.
+    fn forward<M>(
+        &mut self,
+        msg: M,
+        request_id: u64,
+        ctx: &mut Context<Self>,
+    ) -> Result<(), DBError>
+    where
+        M: Message<Result = Result<(), String>> + Send + 'static,
+        ConnectionHandler: Handler<M>,
+    {
+        // Once a request is queued the following ones are queued behind it, so they keep their order.
+        let msg = if self.queued_requests == 0 {
+            match self.connection_handler.try_send(msg) {
+                Ok(()) => return Ok(()),
+                Err(SendError::Full(msg)) => msg,
+                Err(err) => return Err(DBError::from(err)),
+            }
+        } else {
+            msg
+        };
+        if self.queued_requests >= MAX_QUEUED_REQUESTS_PER_CONNECTION {
+            self.overloaded_requests += 1;
+            return Err(DBError::new(
+                DBErrorKind::Overloaded,
+                format!(
+                    "{} requests of this connection are already waiting, {} rejected so far",
+                    self.queued_requests, self.overloaded_requests
+                ),
+            ));
+        }
+        self.queued_requests += 1;
+        self.peak_queued_requests = self.peak_queued_requests.max(self.queued_requests);
+        wrap_future::<_, Self>(self.connection_handler.send(msg))
+            .map(move |result, db_middleman, ctx| {
+                db_middleman.queued_requests -= 1;
+                if let Err(err) = result {
+                    DBError::from(err).reply_to(&ctx.address(), request_id);
+                }
+            })
+            .spawn(ctx);
+        Ok(())
+    }
+
+    fn authenticate(
+        &mut self,
+        token: &str,
+        request_id: u64,
+        ctx: &mut Context<Self>,
+    ) -> Result<(), String> {
+        if let Some(expected_token) = &self.expected_token {
+            if !tokens_match(token, expected_token) {
+                let err = "Invalid token".to_string();
+                self.close_unauthorized(&err, request_id, ctx);
+                return Err(err);
+            }
+        }
+        self.authenticated = true;
+        let msg_to_send = DBResponse::Authenticated { request_id }.to_string()?;
+        ctx.address().do_send(SendOnlineMsg { msg_to_send });
+        Ok(())
+    }
+
+    /// Tells the other end why its connection is refused, and closes it once that is written.
+    fn close_unauthorized(&self, detail: &str, request_id: u64, ctx: &mut Context<Self>) {
+        warn!(
+            "[DBMiddleman] Closing unauthenticated connection: {}",
+            detail
+        );
+        let response = DBResponse::Error {
+            request_id,
+            kind: DBErrorKind::Unauthorized,
+            detail: detail.to_string(),
+        }
+        .to_string();
+        let writer = self.writer.clone();
+        wrap_future::<_, Self>(async move {
+            let mut writer = writer.lock().await;
+            if let Ok(response) = response {
+                let _ = writer.write_all((response + "\n").as_bytes()).await;
+            }
+            let _ = writer.shutdown().await;
+        })
+        .map(|_, _, ctx: &mut Context<Self>| ctx.stop())
+        .spawn(ctx);
+    }
 }
 
 impl Actor for DBMiddleman {
@@ -28,18 +169,30 @@ impl Actor for DBMiddleman {
     fn started(&mut self, _ctx: &mut Self::Context) {
         debug!("[DBMiddleman] Started");
     }
+
+    fn stopped(&mut self, ctx: &mut Self::Context) {
+        if self.peak_queued_requests > 0 {
+            info!(
+                "[DBMiddleman] Connection closed, up to {} requests were waiting at once and {} were rejected as overloaded",
+                self.peak_queued_requests, self.overloaded_requests
+            );
+        }
+        if let Some(ecommerce_id) = self.ecommerce_id {
+            self.connection_handler.do_send(RemoveDBMiddleman {
+                db_middleman_addr: ctx.address(),
+                ecommerce_id,
+            });
+        }
+    }
 }
 
 impl StreamHandler<Result<String, std::io::Error>> for DBMiddleman {
     fn handle(&mut self, msg: Result<String, std::io::Error>, ctx: &mut Self::Context) {
         if let Ok(msg) = msg {
             debug!("[ONLINE RECEIVER DB] Received msg:\n{}", msg);
-            if ctx
-                .address()
-                .try_send(HandleOnlineMsg { received_msg: msg })
-                .is_err()
-            {
-                error!("[ONLINE RECEIVER DB] Error sending msg to handler");
+            // Handled right away rather than through the mailbox, which could be full.
+            if let Err(err) = self.handle_online_msg(&msg, ctx) {
+                debug!("[ONLINE RECEIVER DB] Could not handle msg: {}", err);
             }
         }
     }
@@ -50,54 +203,346 @@ impl StreamHandler<Result<String, std::io::Error>> for DBMiddleman {
     }
 }
 
-#[derive(Message, Debug, PartialEq, Eq)]
-#[rtype(result = "Result<(), String>")]
-struct HandleOnlineMsg {
-    received_msg: String,
-}
-
-impl Handler<HandleOnlineMsg> for DBMiddleman {
-    type Result = Result<(), String>;
-
-    fn handle(&mut self, msg: HandleOnlineMsg, ctx: &mut Self::Context) -> Self::Result {
-        match DBRequest::from_string(&msg.received_msg).map_err(|err| err.to_string())? {
-            DBRequest::TakeMyEcommerceId { ecommerce_id } => {
-                let db_middleman_addr = ctx.address();
-                self.connection_handler
-                    .try_send(SaveDBMiddlemanWithId {
-                        db_middleman_addr,
-                        ecommerce_id,
-                    })
-                    .map_err(|err| err.to_string())
+impl DBMiddleman {
+    /// Handles a request received from the other end of the connection.
+    fn handle_online_msg(
+        &mut self,
+        received_msg: &str,
+        ctx: &mut Context<Self>,
+    ) -> Result<(), String> {
+        let request = match DBRequest::from_string(received_msg) {
+            Ok(request) => request,
+            Err(err) if !self.authenticated => {
+                self.close_unauthorized(&err, UNKNOWN_REQUEST_ID, ctx);
+                return Err(err);
+            }
+            Err(err) => {
+                DBError::new(DBErrorKind::InvalidRequest, &err)
+                    .reply_to(&ctx.address(), UNKNOWN_REQUEST_ID);
+                return Err(err);
             }
-            DBRequest::GetNewLocalId => {
-                let db_middleman_addr = ctx.address();
-                self.connection_handler
-                    .try_send(GetNewLocalId { db_middleman_addr })
-                    .map_err(|err| err.to_string())
+        };
+        let request_id = request.request_id();
+        if let DBRequest::Authenticate { token, .. } = &request {
+            return self.authenticate(token, request_id, ctx);
+        }
+        if !self.authenticated {
+            let err = "The connection must authenticate before any other request".to_string();
+            self.close_unauthorized(&err, request_id, ctx);
+            return Err(err);
+        }
+        let requestor_db_middleman = ctx.address();
+        let result = match request {
+            DBRequest::Authenticate { .. } => Ok(()),
+            DBRequest::TakeMyEcommerceId { ecommerce_id, .. } => {
+                self.ecommerce_id = Some(ecommerce_id);
+                self.forward(
+                    SaveDBMiddlemanWithId {
+                        db_middleman_addr: requestor_db_middleman.clone(),
+                        ecommerce_id,
+                    },
+                    request_id,
+                    ctx,
+                )
             }
-            DBRequest::PostStockFromLocal { local_id, stock } => self
-                .connection_handler
-                .try_send(PostStockFromLocal { local_id, stock })
-                .map_err(|err| err.to_string()),
-            DBRequest::PostOrderResult { order } => self
-                .connection_handler
-                .try_send(PostOrderResult { order })
-                .map_err(|err| err.to_string()),
+            DBRequest::SubscribeToStock {
+                product_names,
+                below,
+                ..
+            } => self.forward(
+                SubscribeToStock {
+                    requestor_db_middleman: requestor_db_middleman.clone(),
+                    request_id,
+                    product_names,
+                    below,
+                },
+                request_id,
+                ctx,
+            ),
+            DBRequest::UnsubscribeFromStock {
+                subscription_id, ..
+            } => self.forward(
+                UnsubscribeFromStock {
+                    requestor_db_middleman: requestor_db_middleman.clone(),
+                    request_id,
+                    subscription_id,
+                },
+                request_id,
+                ctx,
+            ),
+            DBRequest::AcquireLeaderEpoch { .. } => self.forward(
+                AcquireLeaderEpoch {
+                    requestor_db_middleman: requestor_db_middleman.clone(),
+                    request_id,
+                },
+                request_id,
+                ctx,
+            ),
+            DBRequest::GetNewLocalId { epoch, .. } => self.forward(
+                GetNewLocalId {
+                    db_middleman_addr: requestor_db_middleman.clone(),
+                    request_id,
+                    epoch,
+                },
+                request_id,
+                ctx,
+            ),
+            DBRequest::PostStockFromLocal {
+                local_id,
+                stock,
+                version,
+                epoch,
+                ..
+            } => self.forward(
+                PostStockFromLocal {
+                    requestor_db_middleman: requestor_db_middleman.clone(),
+                    request_id,
+                    local_id,
+                    stock,
+                    version,
+                    ecommerce_id: self.ecommerce_id,
+                    epoch,
+                },
+                request_id,
+                ctx,
+            ),
+            DBRequest::ReconcileStock {
+                local_id,
+                stock,
+                version,
+                epoch,
+                ..
+            } => self.forward(
+                ReconcileStock {
+                    requestor_db_middleman: requestor_db_middleman.clone(),
+                    request_id,
+                    local_id,
+                    stock,
+                    version,
+                    ecommerce_id: self.ecommerce_id,
+                    epoch,
+                },
+                request_id,
+                ctx,
+            ),
+            DBRequest::PostOrderResult {
+                order,
+                stock_version,
+                epoch,
+                ..
+            } => self.forward(
+                PostOrderResult {
+                    requestor_db_middleman: requestor_db_middleman.clone(),
+                    request_id,
+                    order,
+                    stock_version,
+                    ecommerce_id: self.ecommerce_id,
+                    epoch,
+                },
+                request_id,
+                ctx,
+            ),
+            DBRequest::PostStockDelta {
+                local_id,
+                product_name,
+                change,
+                version,
+                epoch,
+                ..
+            } => self.forward(
+                PostStockDelta {
+                    requestor_db_middleman: requestor_db_middleman.clone(),
+                    request_id,
+                    local_id,
+                    product_name,
+                    change,
+                    version,
+                    ecommerce_id: self.ecommerce_id,
+                    epoch,
+                },
+                request_id,
+                ctx,
+            ),
             DBRequest::GetProductQuantityFromAllLocals {
                 ss_id,
                 worker_id,
                 product_name,
-            } => self
-                .connection_handler
-                .try_send(GetProductQuantityFromAllLocals {
-                    requestor_db_middleman: ctx.address(),
+                ..
+            } => self.forward(
+                GetProductQuantityFromAllLocals {
+                    requestor_db_middleman: requestor_db_middleman.clone(),
+                    request_id,
                     requestor_ss_id: ss_id,
                     requestor_worker_id: worker_id,
                     product_name,
-                })
-                .map_err(|err| err.to_string()),
-        }
+                },
+                request_id,
+                ctx,
+            ),
+            DBRequest::GetProductsQuantityFromAllLocals {
+                ss_id,
+                worker_id,
+                product_names,
+                ..
+            } => self.forward(
+                GetProductsQuantityFromAllLocals {
+                    requestor_db_middleman: requestor_db_middleman.clone(),
+                    request_id,
+                    requestor_ss_id: ss_id,
+                    requestor_worker_id: worker_id,
+                    product_names,
+                },
+                request_id,
+                ctx,
+            ),
+            DBRequest::LocalsHeartbeat {
+                local_ids, epoch, ..
+            } => self.forward(
+                LocalsHeartbeat {
+                    requestor_db_middleman: requestor_db_middleman.clone(),
+                    request_id,
+                    local_ids,
+                    epoch,
+                },
+                request_id,
+                ctx,
+            ),
+            DBRequest::RegisterBackup { .. } => self.forward(
+                RegisterBackup {
+                    backup_db_middleman: requestor_db_middleman.clone(),
+                    request_id,
+                },
+                request_id,
+                ctx,
+            ),
+            DBRequest::ReserveStock {
+                local_id,
+                order_id,
+                products,
+                epoch,
+                ..
+            } => self.forward(
+                ReserveStock {
+                    requestor_db_middleman: requestor_db_middleman.clone(),
+                    request_id,
+                    local_id,
+                    order_id,
+                    products,
+                    epoch,
+                },
+                request_id,
+                ctx,
+            ),
+            DBRequest::ReleaseReservation {
+                reservation_id,
+                epoch,
+                ..
+            } => self.forward(
+                ReleaseReservation {
+                    requestor_db_middleman: requestor_db_middleman.clone(),
+                    request_id,
+                    reservation_id,
+                    epoch,
+                },
+                request_id,
+                ctx,
+            ),
+            DBRequest::GetStockHistory {
+                local_id,
+                product_name,
+                from,
+                to,
+                ..
+            } => self.forward(
+                GetStockHistory {
+                    requestor_db_middleman: requestor_db_middleman.clone(),
+                    request_id,
+                    local_id,
+                    product_name,
+                    from,
+                    to,
+                },
+                request_id,
+                ctx,
+            ),
+            DBRequest::GetOrderResults {
+                local_id,
+                ecommerce_id,
+                product_name,
+                from,
+                to,
+                ..
+            } => self.forward(
+                GetOrderResults {
+                    requestor_db_middleman: requestor_db_middleman.clone(),
+                    request_id,
+                    local_id,
+                    ecommerce_id,
+                    product_name,
+                    from,
+                    to,
+                },
+                request_id,
+                ctx,
+            ),
+            DBRequest::GetCatalogProduct { product_name, .. } => self.forward(
+                GetCatalogProduct {
+                    requestor_db_middleman: requestor_db_middleman.clone(),
+                    request_id,
+                    product_name,
+                },
+                request_id,
+                ctx,
+            ),
+            DBRequest::GetCatalog { category, .. } => self.forward(
+                GetCatalog {
+                    requestor_db_middleman: requestor_db_middleman.clone(),
+                    request_id,
+                    category,
+                },
+                request_id,
+                ctx,
+            ),
+            DBRequest::GetLocals { .. } => self.forward(
+                GetLocals {
+                    requestor_db_middleman: requestor_db_middleman.clone(),
+                    request_id,
+                },
+                request_id,
+                ctx,
+            ),
+            DBRequest::GetLocalStock { local_id, .. } => self.forward(
+                GetLocalStock {
+                    requestor_db_middleman: requestor_db_middleman.clone(),
+                    request_id,
+                    local_id,
+                },
+                request_id,
+                ctx,
+            ),
+            DBRequest::GetTotalQuantityByProduct { .. } => self.forward(
+                GetTotalQuantityByProduct {
+                    requestor_db_middleman: requestor_db_middleman.clone(),
+                    request_id,
+                },
+                request_id,
+                ctx,
+            ),
+            DBRequest::GetTopProducts { limit, .. } => self.forward(
+                GetTopProducts {
+                    requestor_db_middleman: requestor_db_middleman.clone(),
+                    request_id,
+                    limit,
+                },
+                request_id,
+                ctx,
+            ),
+        };
+        result.map_err(|err| {
+            let detail = err.detail.clone();
+            err.reply_to(&requestor_db_middleman, request_id);
+            detail
+        })
     }
 }
 
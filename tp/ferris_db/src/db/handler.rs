@@ -1,21 +1,81 @@
 //! This module contains the main handler for the database. It is responsible for starting all the
-//! other components of the database, and for handling the input from the user.
+//! other components of the database from its `DbConfig`, and for handling the input from the user.
 //!
+//! A backup first follows its upstream, and only starts listening to the e-commerce servers at
+//! its own address once it takes over as primary.
 
 use actix::prelude::*;
-use std::sync::mpsc::{self, channel};
+use shared::{
+    model::reconciliation::ReconciliationPolicy, parsers::db_addrs_parser::default_db_addrs,
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc::{self, channel},
+};
 use tracing::info;
 
-use super::{connection_handler, db_communicator, input_handler, stock_handler};
+use super::{
+    catalog::Catalog,
+    clock::current_timestamp,
+    connection_handler,
+    constants::{SNAPSHOT_FILENAME, STOCK_STORE_DIRNAME, WAL_FILENAME},
+    db_communicator, input_handler,
+    local_registry::LocalRegistry,
+    replication::{self, FollowOutcome},
+    stock_export, stock_handler,
+    stock_partition::StockPartition,
+    stock_store::StockStoreKind,
+    wal::WriteAheadLog,
+};
 
-pub fn start() -> Result<(), String> {
+/// How the database was asked to run, as given at startup.
+pub struct DbConfig {
+    pub db_addrs: Vec<String>,
+    pub backup_number: Option<u16>,
+    pub stale_local_threshold_secs: u64,
+    pub import_dir: Option<PathBuf>,
+    pub catalog_path: Option<String>,
+    pub reconciliation_policy: ReconciliationPolicy,
+    pub stock_store_kind: StockStoreKind,
+    pub stock_partition_count: usize,
+    /// `None` if the database was started as insecure.
+    pub expected_token: Option<String>,
+}
+
+pub fn start(config: DbConfig) -> Result<(), String> {
     info!("[Database] Starting.");
+    let catalog = match &config.catalog_path {
+        Some(catalog_path) => Catalog::load(catalog_path)?,
+        None => Catalog::new(),
+    };
+
+    let position = config.backup_number.unwrap_or(0) as usize;
+    let listen_addr = config
+        .db_addrs
+        .get(position)
+        .ok_or("No address configured for this database")?
+        .clone();
+    let upstream_addrs = config.db_addrs[..position].to_vec();
 
     let (sender_of_tx_to_listener, receiver_of_tx_to_listener) = channel::<mpsc::Sender<String>>();
 
-    let input_handle = input_handler::setup_input_listener(receiver_of_tx_to_listener);
+    let (sender_of_connection_handler, receiver_of_connection_handler) =
+        channel::<Addr<connection_handler::ConnectionHandler>>();
 
-    System::new().block_on(start_async(sender_of_tx_to_listener))?;
+    let input_handle = input_handler::setup_input_listener(
+        receiver_of_tx_to_listener,
+        receiver_of_connection_handler,
+        listen_addr.clone(),
+    );
+
+    System::new().block_on(start_async(
+        sender_of_tx_to_listener,
+        sender_of_connection_handler,
+        config,
+        listen_addr,
+        upstream_addrs,
+        catalog,
+    ))?;
 
     input_handle
         .join()
@@ -24,24 +84,142 @@ pub fn start() -> Result<(), String> {
     Ok(())
 }
 
+/// Returns the directory where the database keeps its files.
+///
+/// Each backup keeps its own files, so that it can run on the same machine as the primary, and so
+/// does each database listening at an address other than the default one, so that several
+/// clusters can run side by side.
+fn data_dir(backup_number: Option<u16>, listen_addr: &str) -> String {
+    let position = backup_number.unwrap_or(0) as usize;
+    if default_db_addrs().get(position).map(String::as_str) != Some(listen_addr) {
+        return format!(
+            "{}/data/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            listen_addr.replace([':', '.'], "_")
+        );
+    }
+    match backup_number {
+        Some(backup_number) => format!(
+            "{}/data/backup_{}",
+            env!("CARGO_MANIFEST_DIR"),
+            backup_number
+        ),
+        None => format!("{}/data", env!("CARGO_MANIFEST_DIR")),
+    }
+}
+
+/// Loads the stock exported to a directory, registering the locals it belongs to
+/// so that their ids are not issued to new locals.
+fn seed_imported_stock(
+    stock_handler: &mut stock_handler::StockHandler,
+    local_registry: &mut LocalRegistry,
+    import_dir: &Path,
+) -> Result<(), String> {
+    let global_stock = stock_export::import_stock(import_dir)?;
+    let now = current_timestamp();
+    for local_id in global_stock.keys() {
+        if local_registry.get(*local_id).is_none() {
+            local_registry.register(*local_id, now);
+            stock_handler.record_issued_local_id(*local_id, now)?;
+        }
+    }
+    info!(
+        "[Database] Importing the stock of {} locals from {:?}",
+        global_stock.len(),
+        import_dir
+    );
+    stock_handler.seed_stock(global_stock)
+}
+
 async fn start_async(
     sender_of_tx_to_listener: mpsc::Sender<mpsc::Sender<String>>,
+    sender_of_connection_handler: mpsc::Sender<Addr<connection_handler::ConnectionHandler>>,
+    config: DbConfig,
+    listen_addr: String,
+    upstream_addrs: Vec<String>,
+    catalog: Catalog,
 ) -> Result<(), String> {
+    let DbConfig {
+        backup_number,
+        stale_local_threshold_secs,
+        import_dir,
+        reconciliation_policy,
+        stock_store_kind,
+        stock_partition_count,
+        expected_token,
+        ..
+    } = config;
     let (tx_from_input_to_listener, rx_from_input_to_listener) = channel::<String>();
 
-    let stock_handler = stock_handler::StockHandler::new().start();
-    let connection_handler =
-        connection_handler::ConnectionHandler::new(stock_handler.clone()).start();
+    let data_dir = data_dir(backup_number, &listen_addr);
+    let wal = WriteAheadLog::open(Path::new(&format!("{}/{}", data_dir, WAL_FILENAME)))?;
+    let snapshot_path = format!("{}/{}", data_dir, SNAPSHOT_FILENAME);
+    let stock_store =
+        stock_store_kind.open(Path::new(&format!("{}/{}", data_dir, STOCK_STORE_DIRNAME)))?;
+    let (mut stock_handler, mut local_registry) =
+        stock_handler::StockHandler::recover(wal, Path::new(&snapshot_path), stock_store)?;
+    stock_handler.set_catalog(catalog);
+    stock_handler.set_reconciliation_policy(reconciliation_policy);
+    if let Some(import_dir) = import_dir {
+        seed_imported_stock(&mut stock_handler, &mut local_registry, &import_dir)?;
+    }
+    info!(
+        "[Database] Last local id issued: {}",
+        local_registry.last_local_id()
+    );
+    let stock_partitions: Vec<Addr<StockPartition>> = (0..stock_partition_count)
+        .map(|_| {
+            StockPartition::start_in_arbiter(&Arbiter::new().handle(), |_| StockPartition::new())
+        })
+        .collect();
+    stock_handler.set_stock_partitions(stock_partitions.clone());
+    let stock_handler = stock_handler.start();
+    let connection_handler = connection_handler::ConnectionHandler::new(
+        stock_handler.clone(),
+        stock_partitions,
+        local_registry,
+        stale_local_threshold_secs,
+    )
+    .start();
 
-    let handle =
-        db_communicator::setup_db_listener(connection_handler.clone(), rx_from_input_to_listener);
     sender_of_tx_to_listener
         .send(tx_from_input_to_listener)
         .map_err(|_| "Error sending tx_from_input_to_listener")?;
+    sender_of_connection_handler
+        .send(connection_handler.clone())
+        .map_err(|_| "Error sending connection_handler")?;
+
+    if backup_number.is_some() {
+        let follow_outcome = replication::follow_until_takeover(
+            &upstream_addrs,
+            &connection_handler,
+            &rx_from_input_to_listener,
+        )
+        .await;
+        if follow_outcome == FollowOutcome::ExitRequested {
+            return Ok(());
+        }
+        info!("[Database] No upstream database left, taking over as primary.");
+    }
+    // Only the primary releases expired reservations, and the backups follow it through the log.
+    stock_handler.do_send(stock_handler::StartExpiringReservations {});
+
+    let handle = db_communicator::setup_db_listener(
+        connection_handler.clone(),
+        listen_addr,
+        rx_from_input_to_listener,
+        expected_token,
+    );
 
-    handle
-        .await
-        .map_err(|_| "Error joining db_communicator handle")?;
+    tokio::select! {
+        result = handle => result.map_err(|_| "Error joining db_communicator handle")?,
+        upstream_addr = replication::wait_for_upstream(&upstream_addrs) => {
+            return Err(format!(
+                "Database [{}] is reachable again, stopping so that it stays the only primary. Start again as backup to follow it",
+                upstream_addr
+            ));
+        }
+    }
 
     Ok(())
 }
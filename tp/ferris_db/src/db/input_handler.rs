@@ -1,14 +1,28 @@
 //! This module contains the input handler, which is responsible for listening to the input from the user.
+//!
+//! Besides exiting, it lets operators inspect the database while the cluster is running, and
+//! forget locals that are not coming back, and
+//! export the global stock to a directory. Those commands are sent to the `ConnectionHandler`.
 
-use std::{net::TcpStream, sync::mpsc};
+use std::{net::TcpStream, path::PathBuf, sync::mpsc};
 
 use actix::prelude::*;
-use shared::model::constants::{DATABASE_IP, EXIT_COMMAND};
+use shared::model::constants::EXIT_COMMAND;
 use std::thread::JoinHandle;
 use tracing::{info, warn};
 
+use super::{
+    connection_handler::{self, ConnectionHandler},
+    constants::{
+        EXPORT_STOCK_COMMAND, FORGET_LOCAL_COMMAND, SHOW_LAST_LOCAL_ID_COMMAND,
+        SHOW_SERVERS_COMMAND, SHOW_STOCK_COMMAND,
+    },
+};
+
 pub fn setup_input_listener(
     receiver_of_tx_to_listener: mpsc::Receiver<mpsc::Sender<String>>,
+    receiver_of_connection_handler: mpsc::Receiver<Addr<ConnectionHandler>>,
+    listen_addr: String,
 ) -> JoinHandle<Result<(), String>> {
     std::thread::spawn(move || -> Result<(), String> {
         info!("[InputHandler] Input listener started");
@@ -17,25 +31,76 @@ pub fn setup_input_listener(
         let tx_to_listener = receiver_of_tx_to_listener
             .recv()
             .map_err(|err| err.to_string())?;
+        let connection_handler = receiver_of_connection_handler
+            .recv()
+            .map_err(|err| err.to_string())?;
 
         while let Some(Ok(line)) = reader.next() {
             if line == EXIT_COMMAND {
                 info!("[InputHandler] Exit command received");
                 let _ = tx_to_listener.send(EXIT_COMMAND.to_string());
-                let _ = TcpStream::connect(DATABASE_IP);
+                let _ = TcpStream::connect(&listen_addr);
 
                 if let Some(system) = System::try_current() {
                     info!("Stopping system");
                     system.stop()
                 }
                 break;
-            } else {
+            }
+            if let Err(err) = handle_operator_command(&line, &connection_handler) {
                 warn!(
-                    "[InputHandler] Unknown command. Available commands: {}.",
-                    EXIT_COMMAND
+                    "[InputHandler] {}. Available commands: {}, {} [<local_id>], {}, {}, {} <local_id>, {} <dir>.",
+                    err,
+                    EXIT_COMMAND,
+                    SHOW_STOCK_COMMAND,
+                    SHOW_SERVERS_COMMAND,
+                    SHOW_LAST_LOCAL_ID_COMMAND,
+                    FORGET_LOCAL_COMMAND,
+                    EXPORT_STOCK_COMMAND
                 );
             }
         }
         Ok(())
     })
 }
+
+fn parse_local_id(local_id: &str) -> Result<u16, String> {
+    local_id
+        .parse::<u16>()
+        .map_err(|_| format!("Invalid local id: {}", local_id))
+}
+
+fn handle_operator_command(
+    line: &str,
+    connection_handler: &Addr<ConnectionHandler>,
+) -> Result<(), String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+
+    match words.as_slice() {
+        [SHOW_STOCK_COMMAND] => connection_handler
+            .try_send(connection_handler::ShowStock { local_id: None })
+            .map_err(|err| err.to_string()),
+        [SHOW_STOCK_COMMAND, local_id] => connection_handler
+            .try_send(connection_handler::ShowStock {
+                local_id: Some(parse_local_id(local_id)?),
+            })
+            .map_err(|err| err.to_string()),
+        [SHOW_SERVERS_COMMAND] => connection_handler
+            .try_send(connection_handler::ShowConnectedServers {})
+            .map_err(|err| err.to_string()),
+        [SHOW_LAST_LOCAL_ID_COMMAND] => connection_handler
+            .try_send(connection_handler::ShowLastLocalId {})
+            .map_err(|err| err.to_string()),
+        [FORGET_LOCAL_COMMAND, local_id] => connection_handler
+            .try_send(connection_handler::ForgetLocal {
+                local_id: parse_local_id(local_id)?,
+            })
+            .map_err(|err| err.to_string()),
+        [EXPORT_STOCK_COMMAND, dir] => connection_handler
+            .try_send(connection_handler::ExportStock {
+                dir: PathBuf::from(dir),
+            })
+            .map_err(|err| err.to_string()),
+        _ => Err(format!("Unknown command: {}", line)),
+    }
+}
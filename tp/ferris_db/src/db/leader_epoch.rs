@@ -0,0 +1,69 @@
+//! This module contains the `LeaderEpoch`, which fences off the writes of deposed e-commerce leaders.
+//!
+//! Every e-commerce server that becomes leader obtains an epoch higher than every one issued
+//! before, and attaches it to its writes. Writes carrying an epoch older than the highest one
+//! known come from a leader that was already replaced, so they are rejected, and so are writes
+//! carrying an epoch that was never issued nor received from the upstream.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LeaderEpoch {
+    highest: u64,
+}
+
+impl LeaderEpoch {
+    pub fn new() -> Self {
+        LeaderEpoch::default()
+    }
+
+    pub fn highest(&self) -> u64 {
+        self.highest
+    }
+
+    /// The epoch to issue to the next leader.
+    pub fn next(&self) -> u64 {
+        self.highest + 1
+    }
+
+    pub fn is_stale(&self, epoch: u64) -> bool {
+        epoch < self.highest
+    }
+
+    /// Whether the epoch is the highest one, which is the only one writes are accepted with.
+    /// No epoch is current before the first one is issued.
+    pub fn is_current(&self, epoch: u64) -> bool {
+        self.highest > 0 && epoch == self.highest
+    }
+
+    pub fn raise(&mut self, epoch: u64) {
+        self.highest = self.highest.max(epoch);
+    }
+}
+
+// ====================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_the_highest_epoch_is_current() {
+        let mut leader_epoch = LeaderEpoch::new();
+        assert!(!leader_epoch.is_stale(0));
+        assert!(!leader_epoch.is_current(0));
+
+        leader_epoch.raise(leader_epoch.next());
+        leader_epoch.raise(leader_epoch.next());
+        assert_eq!(leader_epoch.highest(), 2);
+        assert!(leader_epoch.is_stale(0));
+        assert!(leader_epoch.is_stale(1));
+        assert!(!leader_epoch.is_stale(2));
+        assert!(leader_epoch.is_current(2));
+        assert!(!leader_epoch.is_stale(3));
+        assert!(!leader_epoch.is_current(3));
+
+        leader_epoch.raise(1);
+        assert_eq!(leader_epoch.highest(), 2);
+    }
+}
@@ -0,0 +1,137 @@
+//! This module contains the `LocalRegistry`, which keeps track of every local id issued by the database.
+//!
+//! Ids are handed out in increasing order and are never issued again, even after a restart,
+//! since the registry is persisted through the write-ahead log and the snapshots.
+//!
+//! It also keeps the last time each local was seen, to tell which ones are offline.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LocalRecord {
+    pub registered_at: u64,
+    pub last_seen: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct LocalRegistry {
+    last_local_id: u16,
+    locals: HashMap<u16, LocalRecord>,
+}
+
+impl LocalRegistry {
+    pub fn new() -> Self {
+        LocalRegistry::default()
+    }
+
+    pub fn last_local_id(&self) -> u16 {
+        self.last_local_id
+    }
+
+    pub fn get(&self, local_id: u16) -> Option<&LocalRecord> {
+        self.locals.get(&local_id)
+    }
+
+    /// The id to issue next, which was never issued before. It is only taken once registered.
+    pub fn next_id(&self) -> Result<u16, String> {
+        self.last_local_id
+            .checked_add(1)
+            .ok_or("No more local ids available".to_string())
+    }
+
+    /// Registers an issued id, either when issuing it or when replaying the write-ahead log.
+    pub fn register(&mut self, local_id: u16, registered_at: u64) {
+        self.last_local_id = self.last_local_id.max(local_id);
+        self.locals.entry(local_id).or_insert(LocalRecord {
+            registered_at,
+            last_seen: registered_at,
+        });
+    }
+
+    /// Updates the last time a local was seen. Ids that were never issued are rejected, so that a
+    /// local cannot take an id that would later be issued to another one.
+    pub fn mark_seen(&mut self, local_id: u16, now: u64) -> Result<(), String> {
+        let local = self
+            .locals
+            .get_mut(&local_id)
+            .ok_or(format!("Local id {} was never issued", local_id))?;
+        local.last_seen = local.last_seen.max(now);
+        Ok(())
+    }
+
+    /// Returns whether a local was seen within the given threshold. Unknown ids are considered offline.
+    pub fn is_online(&self, local_id: u16, now: u64, stale_threshold_secs: u64) -> bool {
+        self.locals
+            .get(&local_id)
+            .is_some_and(|local| now.saturating_sub(local.last_seen) <= stale_threshold_secs)
+    }
+}
+
+// ====================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_issued_ids_are_never_reissued() {
+        let mut registry = LocalRegistry::new();
+        assert_eq!(registry.next_id(), Ok(1));
+        registry.register(1, 10);
+        assert_eq!(registry.next_id(), Ok(2));
+        registry.register(5, 20);
+        assert_eq!(registry.next_id(), Ok(6));
+        assert_eq!(
+            registry.get(5),
+            Some(&LocalRecord {
+                registered_at: 20,
+                last_seen: 20
+            })
+        );
+    }
+
+    #[test]
+    fn test_mark_seen_keeps_registration_time() {
+        let mut registry = LocalRegistry::new();
+        let local_id = registry.next_id().unwrap();
+        registry.register(local_id, 10);
+        registry.mark_seen(local_id, 50).unwrap();
+        assert_eq!(
+            registry.get(local_id),
+            Some(&LocalRecord {
+                registered_at: 10,
+                last_seen: 50
+            })
+        );
+    }
+
+    #[test]
+    fn test_locals_not_seen_within_threshold_are_offline() {
+        let mut registry = LocalRegistry::new();
+        registry.register(1, 100);
+        registry.register(2, 150);
+        assert!(!registry.is_online(1, 200, 60));
+        assert!(registry.is_online(2, 200, 60));
+        assert!(!registry.is_online(3, 200, 60));
+        registry.mark_seen(1, 190).unwrap();
+        assert!(registry.is_online(1, 200, 60));
+    }
+
+    #[test]
+    fn test_ids_never_issued_cannot_be_seen() {
+        let mut registry = LocalRegistry::new();
+        registry.register(1, 10);
+        assert!(registry.mark_seen(2, 20).is_err());
+        assert_eq!(registry.get(2), None);
+        assert_eq!(registry.next_id(), Ok(2));
+    }
+
+    #[test]
+    fn test_cannot_issue_ids_once_exhausted() {
+        let mut registry = LocalRegistry::new();
+        registry.register(u16::MAX, 0);
+        assert!(registry.next_id().is_err());
+    }
+}
@@ -1,6 +1,25 @@
+mod applied_orders;
+mod catalog;
+mod clock;
 mod connection_handler;
+pub mod constants;
 mod db_communicator;
+mod db_error;
 mod db_middleman;
 pub mod handler;
 mod input_handler;
+mod leader_epoch;
+mod local_registry;
+mod order_log;
+mod reconciliation;
+mod replication;
+mod reservations;
+mod snapshot;
+mod stock_export;
 mod stock_handler;
+mod stock_history;
+mod stock_partition;
+pub mod stock_store;
+mod stock_versions;
+mod subscriptions;
+mod wal;
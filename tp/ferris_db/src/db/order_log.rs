@@ -0,0 +1,102 @@
+//! This module contains the `OrderLog`, which keeps every order result applied to the stock, so
+//! that the sales of a local, an e-commerce server or a product in a period of time can be
+//! looked up after the stock was already updated.
+
+use serde::{Deserialize, Serialize};
+use shared::model::db_order_result::OrderResult;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct OrderLog {
+    order_results: Vec<OrderResult>,
+}
+
+impl OrderLog {
+    pub fn new() -> Self {
+        OrderLog::default()
+    }
+
+    pub fn record(&mut self, order_result: OrderResult) {
+        self.order_results.push(order_result);
+    }
+
+    /// Returns the order results applied between `from` and `to` (both included), of the given
+    /// local, e-commerce server and/or product if any, from oldest to newest.
+    pub fn query(
+        &self,
+        local_id: Option<u16>,
+        ecommerce_id: Option<u16>,
+        product_name: Option<&str>,
+        from: u64,
+        to: u64,
+    ) -> Vec<OrderResult> {
+        self.order_results
+            .iter()
+            .filter(|order_result| (from..=to).contains(&order_result.get_timestamp()))
+            .filter(|order_result| {
+                local_id.map_or(true, |local_id| order_result.get_local_id() == local_id)
+            })
+            .filter(|order_result| {
+                ecommerce_id.map_or(true, |ecommerce_id| {
+                    order_result.get_ecommerce_id() == Some(ecommerce_id)
+                })
+            })
+            .filter(|order_result| {
+                product_name.map_or(true, |product_name| {
+                    order_result.contains_product(product_name)
+                })
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+// ====================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::model::{
+        order::{LocalOrder, Order},
+        stock_product::Product,
+    };
+
+    fn order_result_of(
+        local_id: u16,
+        ecommerce_id: Option<u16>,
+        product_name: &str,
+        timestamp: u64,
+    ) -> OrderResult {
+        let mut order = Order::Local(LocalOrder::new(vec![Product::new(
+            product_name.to_string(),
+            1,
+        )]));
+        order.set_local_id(local_id);
+        OrderResult::new(order, ecommerce_id, local_id, timestamp)
+    }
+
+    #[test]
+    fn test_order_results_are_filtered_by_local_ecommerce_product_and_time_range() {
+        let mut order_log = OrderLog::new();
+        let first = order_result_of(3, Some(1), "product1", 10);
+        let second = order_result_of(3, Some(2), "product2", 20);
+        let third = order_result_of(4, None, "product1", 30);
+        order_log.record(first.clone());
+        order_log.record(second.clone());
+        order_log.record(third.clone());
+
+        assert_eq!(
+            order_log.query(Some(3), None, None, 0, u64::MAX),
+            vec![first.clone(), second.clone()]
+        );
+        assert_eq!(
+            order_log.query(None, Some(2), None, 0, u64::MAX),
+            vec![second]
+        );
+        assert_eq!(
+            order_log.query(None, None, Some("product1"), 0, u64::MAX),
+            vec![first, third.clone()]
+        );
+        assert_eq!(order_log.query(None, None, None, 25, 30), vec![third]);
+        assert_eq!(order_log.query(Some(4), Some(1), None, 0, u64::MAX), vec![]);
+    }
+}
@@ -0,0 +1,108 @@
+//! This module contains the comparison between the stock a local reports and the copy of it kept
+//! by the database, which finds the products whose quantities drifted apart (e.g. because an order
+//! result was lost or applied twice).
+
+use std::collections::{BTreeSet, HashMap};
+
+use shared::model::{
+    reconciliation::{ReconciliationPolicy, StockDiscrepancy},
+    stock_product::Product,
+};
+
+/// The outcome of comparing the stock of a local with the copy of the database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StockReconciliation {
+    pub policy: ReconciliationPolicy,
+    pub discrepancies: Vec<StockDiscrepancy>,
+    /// How much the local must change each product, when the database is trusted.
+    pub corrections: Option<HashMap<String, i32>>,
+}
+
+/// Returns the products whose quantity differs between both stocks, sorted by name.
+/// A product missing from one of them counts as having a quantity of 0 in it.
+pub fn find_discrepancies(
+    local_stock: &HashMap<String, Product>,
+    db_stock: &HashMap<String, Product>,
+) -> Vec<StockDiscrepancy> {
+    let quantity_in = |stock: &HashMap<String, Product>, product_name: &str| {
+        stock
+            .get(product_name)
+            .map(|product| product.get_quantity())
+            .unwrap_or(0)
+    };
+    local_stock
+        .keys()
+        .chain(db_stock.keys())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(|product_name| StockDiscrepancy {
+            product_name: product_name.clone(),
+            local_quantity: quantity_in(local_stock, product_name),
+            db_quantity: quantity_in(db_stock, product_name),
+        })
+        .filter(|discrepancy| discrepancy.local_quantity != discrepancy.db_quantity)
+        .collect()
+}
+
+/// How much the local must change each product to match the database.
+pub fn corrections_for(discrepancies: &[StockDiscrepancy]) -> HashMap<String, i32> {
+    discrepancies
+        .iter()
+        .map(|discrepancy| {
+            (
+                discrepancy.product_name.clone(),
+                discrepancy.db_quantity - discrepancy.local_quantity,
+            )
+        })
+        .collect()
+}
+
+// ====================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stock_of(products: &[(&str, i32)]) -> HashMap<String, Product> {
+        products
+            .iter()
+            .map(|(name, quantity)| (name.to_string(), Product::new(name.to_string(), *quantity)))
+            .collect()
+    }
+
+    #[test]
+    fn test_only_products_with_different_quantities_are_discrepancies() {
+        let local_stock = stock_of(&[("product1", 5), ("product2", 3), ("product3", 1)]);
+        let db_stock = stock_of(&[("product1", 5), ("product2", 4), ("product4", 2)]);
+
+        let discrepancies = find_discrepancies(&local_stock, &db_stock);
+        assert_eq!(
+            discrepancies,
+            vec![
+                StockDiscrepancy {
+                    product_name: "product2".to_string(),
+                    local_quantity: 3,
+                    db_quantity: 4,
+                },
+                StockDiscrepancy {
+                    product_name: "product3".to_string(),
+                    local_quantity: 1,
+                    db_quantity: 0,
+                },
+                StockDiscrepancy {
+                    product_name: "product4".to_string(),
+                    local_quantity: 0,
+                    db_quantity: 2,
+                },
+            ]
+        );
+        assert_eq!(
+            corrections_for(&discrepancies),
+            HashMap::from([
+                ("product2".to_string(), 1),
+                ("product3".to_string(), -1),
+                ("product4".to_string(), 2),
+            ])
+        );
+    }
+}
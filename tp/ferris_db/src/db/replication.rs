@@ -0,0 +1,234 @@
+//! This module contains the logic of a backup database following the primary one.
+//!
+//! A backup connects to its upstream database, authenticates with the configured token (if any)
+//! and registers itself with `DBRequest::RegisterBackup`.
+//! The upstream answers with its full state, and then streams every record it appends to its
+//! write-ahead log, which the backup applies in the same order.
+//!
+//! When the upstream is lost, the backup looks for another database to follow (the primary first,
+//! then the backups with a lower number, which may have already taken over). If none is reachable,
+//! it takes over as primary.
+//!
+//! Records are streamed to the backups without waiting for them to be applied, so the records a
+//! backup had not received when it takes over are lost. The stock they changed is restored by the
+//! next stock the locals send, which supersedes the updates left waiting for them.
+//!
+//! Since a database only listens once it is the primary, a backup that took over keeps probing
+//! the databases it could have followed, and if one of them is reachable again (the upstream was
+//! only unreachable, not lost), it stops, so that two primaries do not keep running. It must then
+//! be started again as backup, and the writes it accepted meanwhile are lost.
+
+use std::{sync::mpsc, time::Duration};
+
+use actix::Addr;
+use serde::{Deserialize, Serialize};
+use shared::{
+    communication::{db_auth::db_token_from_env, db_request::DBRequest, db_response::DBResponse},
+    model::constants::UNKNOWN_REQUEST_ID,
+};
+use tokio::{
+    io::{split, AsyncBufReadExt, AsyncWriteExt, BufReader, Lines, ReadHalf, WriteHalf},
+    net::TcpStream as AsyncTcpStream,
+    time::{sleep, timeout},
+};
+use tracing::{info, warn};
+
+use super::{
+    connection_handler::{ApplyReplicationMessage, ConnectionHandler},
+    constants::{
+        EXIT_POLL_INTERVAL_MILLIS, UPSTREAM_CONNECTION_ATTEMPTS, UPSTREAM_PROBE_INTERVAL_MILLIS,
+        UPSTREAM_RETRY_DELAY_MILLIS,
+    },
+    db_communicator::is_exit_required,
+    snapshot::Snapshot,
+    wal::WalRecord,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum ReplicationMessage {
+    FullState { snapshot: Box<Snapshot> },
+    Record { record: WalRecord },
+}
+
+impl ReplicationMessage {
+    pub fn from_string(msg: &str) -> Result<Self, String> {
+        serde_json::from_str(msg).map_err(|err| err.to_string())
+    }
+
+    pub fn to_string(&self) -> Result<String, String> {
+        serde_json::to_string(self).map_err(|err| err.to_string())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FollowOutcome {
+    TakeOver,
+    ExitRequested,
+}
+
+/// Follows the first reachable database of `upstream_addrs` (in order) until none of them is
+/// reachable anymore, in which case this database has to take over as primary.
+pub async fn follow_until_takeover(
+    upstream_addrs: &[String],
+    connection_handler: &Addr<ConnectionHandler>,
+    rx_from_input: &mpsc::Receiver<String>,
+) -> FollowOutcome {
+    'search: loop {
+        for upstream_addr in upstream_addrs {
+            for _ in 0..UPSTREAM_CONNECTION_ATTEMPTS {
+                if is_exit_required(rx_from_input) {
+                    return FollowOutcome::ExitRequested;
+                }
+                if let Ok(stream) = AsyncTcpStream::connect(upstream_addr).await {
+                    info!("[Replication] Following database: [{}]", upstream_addr);
+                    if follow_upstream(stream, connection_handler, rx_from_input).await
+                        == UpstreamOutcome::ExitRequested
+                    {
+                        return FollowOutcome::ExitRequested;
+                    }
+                    warn!("[Replication] Lost database: [{}]", upstream_addr);
+                    continue 'search;
+                }
+                sleep(Duration::from_millis(UPSTREAM_RETRY_DELAY_MILLIS)).await;
+            }
+        }
+        return FollowOutcome::TakeOver;
+    }
+}
+
+/// Waits until one of `upstream_addrs` is reachable, returning its address, or forever if there
+/// are none, as is the case of the primary.
+pub async fn wait_for_upstream(upstream_addrs: &[String]) -> String {
+    loop {
+        sleep(Duration::from_millis(UPSTREAM_PROBE_INTERVAL_MILLIS)).await;
+        for upstream_addr in upstream_addrs {
+            if AsyncTcpStream::connect(upstream_addr).await.is_ok() {
+                return upstream_addr.clone();
+            }
+        }
+    }
+}
+
+/// Presents the token to the upstream and waits for it to be accepted.
+async fn authenticate(
+    writer: &mut WriteHalf<AsyncTcpStream>,
+    lines: &mut Lines<BufReader<ReadHalf<AsyncTcpStream>>>,
+    token: String,
+) -> Result<(), String> {
+    // It is sent before any other request on this connection, so its id needs no tracking.
+    let authenticate_msg = (DBRequest::Authenticate {
+        request_id: UNKNOWN_REQUEST_ID,
+        token,
+    })
+    .to_string()?
+        + "\n";
+    writer
+        .write_all(authenticate_msg.as_bytes())
+        .await
+        .map_err(|err| err.to_string())?;
+    let line = lines
+        .next_line()
+        .await
+        .map_err(|err| err.to_string())?
+        .ok_or("Connection closed by upstream")?;
+    match DBResponse::from_string(&line)? {
+        DBResponse::Authenticated { .. } => Ok(()),
+        DBResponse::Error { detail, .. } => Err(detail),
+        response => Err(format!("Unexpected response: {:?}", response)),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum UpstreamOutcome {
+    Lost,
+    ExitRequested,
+}
+
+async fn follow_upstream(
+    stream: AsyncTcpStream,
+    connection_handler: &Addr<ConnectionHandler>,
+    rx_from_input: &mpsc::Receiver<String>,
+) -> UpstreamOutcome {
+    let (reader, mut writer) = split(stream);
+    let mut lines = BufReader::new(reader).lines();
+    let token = db_token_from_env().unwrap_or_default();
+    if let Err(err) = authenticate(&mut writer, &mut lines, token).await {
+        warn!(
+            "[Replication] Could not authenticate with upstream: {}",
+            err
+        );
+        return UpstreamOutcome::Lost;
+    }
+    // It is the only request sent on this connection, so its id needs no tracking.
+    let register_msg = match (DBRequest::RegisterBackup { request_id: 1 }).to_string() {
+        Ok(msg) => msg + "\n",
+        Err(err) => {
+            warn!("[Replication] Error building register message: {}", err);
+            return UpstreamOutcome::Lost;
+        }
+    };
+    if writer.write_all(register_msg.as_bytes()).await.is_err() {
+        return UpstreamOutcome::Lost;
+    }
+
+    loop {
+        let line = match timeout(
+            Duration::from_millis(EXIT_POLL_INTERVAL_MILLIS),
+            lines.next_line(),
+        )
+        .await
+        {
+            Err(_) => {
+                if is_exit_required(rx_from_input) {
+                    return UpstreamOutcome::ExitRequested;
+                }
+                continue;
+            }
+            Ok(Ok(Some(line))) => line,
+            Ok(Ok(None)) | Ok(Err(_)) => return UpstreamOutcome::Lost,
+        };
+
+        let result = match ReplicationMessage::from_string(&line) {
+            Ok(message) => connection_handler
+                .send(ApplyReplicationMessage { message })
+                .await
+                .map_err(|err| err.to_string())
+                .and_then(|result| result),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            // Reconnecting makes the upstream send its full state again.
+            warn!("[Replication] Error applying replicated data: {}", err);
+            return UpstreamOutcome::Lost;
+        }
+    }
+}
+
+// ====================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener as AsyncTcpListener;
+
+    #[actix_rt::test]
+    async fn test_a_database_that_took_over_notices_when_its_upstream_is_back() {
+        let upstream = AsyncTcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream.local_addr().unwrap().to_string();
+
+        let reachable_addr = timeout(
+            Duration::from_millis(UPSTREAM_PROBE_INTERVAL_MILLIS * 3),
+            wait_for_upstream(&["127.0.0.1:1".to_string(), upstream_addr.clone()]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(reachable_addr, upstream_addr);
+
+        assert!(timeout(
+            Duration::from_millis(UPSTREAM_PROBE_INTERVAL_MILLIS + EXIT_POLL_INTERVAL_MILLIS),
+            wait_for_upstream(&[])
+        )
+        .await
+        .is_err());
+    }
+}
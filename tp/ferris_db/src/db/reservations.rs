@@ -0,0 +1,156 @@
+//! This module contains the `Reservations`, which keep track of the stock held for orders that
+//! were assigned to a local but not completed yet.
+//!
+//! Reserved quantities are not available to other orders until the reservation is committed
+//! (and the quantities are taken from the local's stock), consumed by the result of its order, or
+//! released, which happens by itself once it is older than the reservation TTL.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use shared::model::stock_product::Product;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Reservation {
+    pub local_id: u16,
+    pub products: Vec<Product>,
+    /// The order the stock is held for, whose result consumes the reservation.
+    #[serde(default)]
+    pub order_id: Option<u64>,
+    /// When the reservation was made, in seconds since the UNIX epoch.
+    #[serde(default)]
+    pub reserved_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct Reservations {
+    last_reservation_id: u64,
+    active: HashMap<u64, Reservation>,
+}
+
+impl Reservations {
+    pub fn new() -> Self {
+        Reservations::default()
+    }
+
+    /// Returns the id the next reservation will have.
+    pub fn next_id(&self) -> u64 {
+        self.last_reservation_id + 1
+    }
+
+    pub fn get(&self, reservation_id: u64) -> Option<&Reservation> {
+        self.active.get(&reservation_id)
+    }
+
+    pub fn insert(&mut self, reservation_id: u64, reservation: Reservation) {
+        self.last_reservation_id = self.last_reservation_id.max(reservation_id);
+        self.active.insert(reservation_id, reservation);
+    }
+
+    pub fn remove(&mut self, reservation_id: u64) -> Option<Reservation> {
+        self.active.remove(&reservation_id)
+    }
+
+    /// Returns the id of the active reservation held for an order, if there is one.
+    pub fn find_by_order(&self, order_id: u64) -> Option<u64> {
+        self.active
+            .iter()
+            .find(|(_, reservation)| reservation.order_id == Some(order_id))
+            .map(|(reservation_id, _)| *reservation_id)
+    }
+
+    /// Returns the ids of the active reservations made at least `ttl_secs` before `now`, sorted.
+    pub fn expired(&self, now: u64, ttl_secs: u64) -> Vec<u64> {
+        let mut reservation_ids: Vec<u64> = self
+            .active
+            .iter()
+            .filter(|(_, reservation)| reservation.reserved_at.saturating_add(ttl_secs) <= now)
+            .map(|(reservation_id, _)| *reservation_id)
+            .collect();
+        reservation_ids.sort();
+        reservation_ids
+    }
+
+    /// Removes every active reservation of a local.
+    pub fn remove_local(&mut self, local_id: u16) {
+        self.active
+            .retain(|_, reservation| reservation.local_id != local_id);
+    }
+
+    /// Returns the quantity of a product held by the active reservations of a local.
+    pub fn reserved_quantity(&self, local_id: u16, product_name: &str) -> i32 {
+        self.active
+            .values()
+            .filter(|reservation| reservation.local_id == local_id)
+            .flat_map(|reservation| reservation.products.iter())
+            .filter(|product| product.get_name() == product_name)
+            .map(|product| product.get_quantity())
+            .sum()
+    }
+}
+
+// ====================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserved_quantity_only_counts_the_given_local() {
+        let mut reservations = Reservations::new();
+        reservations.insert(
+            reservations.next_id(),
+            Reservation {
+                local_id: 1,
+                products: vec![Product::new("product1".to_string(), 2)],
+                order_id: None,
+                reserved_at: 0,
+            },
+        );
+        reservations.insert(
+            reservations.next_id(),
+            Reservation {
+                local_id: 1,
+                products: vec![Product::new("product1".to_string(), 3)],
+                order_id: None,
+                reserved_at: 0,
+            },
+        );
+        reservations.insert(
+            reservations.next_id(),
+            Reservation {
+                local_id: 2,
+                products: vec![Product::new("product1".to_string(), 7)],
+                order_id: None,
+                reserved_at: 0,
+            },
+        );
+        assert_eq!(reservations.reserved_quantity(1, "product1"), 5);
+
+        reservations.remove(1);
+        assert_eq!(reservations.reserved_quantity(1, "product1"), 3);
+        assert_eq!(reservations.next_id(), 4);
+    }
+
+    #[test]
+    fn test_reservations_expire_after_the_ttl() {
+        let mut reservations = Reservations::new();
+        for (order_id, reserved_at) in [(7, 100), (8, 130)] {
+            reservations.insert(
+                reservations.next_id(),
+                Reservation {
+                    local_id: 1,
+                    products: vec![Product::new("product1".to_string(), 1)],
+                    order_id: Some(order_id),
+                    reserved_at,
+                },
+            );
+        }
+        assert_eq!(reservations.find_by_order(8), Some(2));
+        assert_eq!(reservations.find_by_order(9), None);
+
+        assert_eq!(reservations.expired(159, 60), Vec::<u64>::new());
+        assert_eq!(reservations.expired(160, 60), vec![1]);
+        assert_eq!(reservations.expired(190, 60), vec![1, 2]);
+    }
+}
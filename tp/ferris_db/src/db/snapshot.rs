@@ -0,0 +1,151 @@
+//! This module contains the snapshots of the database state.
+//!
+//! A snapshot holds the whole stock, the active reservations, the version of each local's stock,
+//! the order results already applied and the log of them, the history of stock changes and the
+//! registry of issued local ids, along with the LSN of the last write-ahead log record it includes. Once a snapshot
+//! is saved the log can be truncated, and on startup only the log records after that LSN need to
+//! be replayed.
+//!
+//! Each snapshot records the version of its format. Snapshots of an older format are migrated
+//! when they are loaded, and the ones of a newer format than this database knows are rejected.
+//! Snapshots saved before the format was versioned are of version 0, and may hold the stock of
+//! locals that are missing from the registry, since seeing a local used to register it; the
+//! migration registers them, so that their ids are not issued again.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::Write,
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+use shared::model::stock_product::Product;
+
+use super::{
+    applied_orders::AppliedOrders, constants::SNAPSHOT_FORMAT_VERSION, leader_epoch::LeaderEpoch,
+    local_registry::LocalRegistry, order_log::OrderLog, reservations::Reservations,
+    stock_history::StockHistory, stock_versions::StockVersions,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    #[serde(default)]
+    pub format_version: u32,
+    pub last_lsn: u64,
+    pub local_registry: LocalRegistry,
+    pub global_stock: HashMap<u16, HashMap<String, Product>>,
+    #[serde(default)]
+    pub reservations: Reservations,
+    #[serde(default)]
+    pub stock_versions: StockVersions,
+    #[serde(default)]
+    pub applied_orders: AppliedOrders,
+    #[serde(default)]
+    pub stock_history: StockHistory,
+    #[serde(default)]
+    pub leader_epoch: LeaderEpoch,
+    #[serde(default)]
+    pub order_log: OrderLog,
+}
+
+impl Snapshot {
+    /// Loads the snapshot at the given path, if there is one.
+    pub fn load(path: &Path) -> Result<Option<Self>, String> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        let mut snapshot: Snapshot =
+            serde_json::from_str(&content).map_err(|err| err.to_string())?;
+        snapshot.migrate()?;
+        Ok(Some(snapshot))
+    }
+
+    /// Brings a snapshot of an older format to the current one.
+    pub fn migrate(&mut self) -> Result<(), String> {
+        if self.format_version > SNAPSHOT_FORMAT_VERSION {
+            return Err(format!(
+                "Snapshot format version {} is newer than the supported one ({})",
+                self.format_version, SNAPSHOT_FORMAT_VERSION
+            ));
+        }
+        if self.format_version == 0 {
+            self.register_stocked_locals();
+        }
+        self.format_version = SNAPSHOT_FORMAT_VERSION;
+        Ok(())
+    }
+
+    fn register_stocked_locals(&mut self) {
+        for local_id in self.global_stock.keys() {
+            if self.local_registry.get(*local_id).is_none() {
+                self.local_registry.register(*local_id, 0);
+            }
+        }
+    }
+
+    /// Saves the snapshot to the given path.
+    ///
+    /// It is written to a temporary file first and then renamed, so a crash while saving
+    /// never leaves a partially written snapshot behind.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let tmp_path = path.with_extension("tmp");
+        let content = serde_json::to_string(self).map_err(|err| err.to_string())?;
+        let mut file = File::create(&tmp_path).map_err(|err| err.to_string())?;
+        file.write_all(content.as_bytes())
+            .map_err(|err| err.to_string())?;
+        file.sync_all().map_err(|err| err.to_string())?;
+        fs::rename(&tmp_path, path).map_err(|err| err.to_string())
+    }
+}
+
+// ====================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot_with_stock_of(local_ids: &[u16]) -> Snapshot {
+        Snapshot {
+            format_version: SNAPSHOT_FORMAT_VERSION,
+            last_lsn: 0,
+            local_registry: LocalRegistry::new(),
+            global_stock: local_ids
+                .iter()
+                .map(|local_id| (*local_id, HashMap::new()))
+                .collect(),
+            reservations: Reservations::default(),
+            stock_versions: StockVersions::default(),
+            applied_orders: AppliedOrders::default(),
+            stock_history: StockHistory::default(),
+            leader_epoch: LeaderEpoch::default(),
+            order_log: OrderLog::default(),
+        }
+    }
+
+    #[test]
+    fn test_unversioned_snapshots_register_the_locals_with_stock() {
+        let mut snapshot = snapshot_with_stock_of(&[2, 5]);
+        let mut content: serde_json::Value = serde_json::to_value(&snapshot).unwrap();
+        content.as_object_mut().unwrap().remove("format_version");
+        let mut unversioned: Snapshot = serde_json::from_value(content).unwrap();
+        assert_eq!(unversioned.format_version, 0);
+
+        unversioned.migrate().unwrap();
+        assert_eq!(unversioned.format_version, SNAPSHOT_FORMAT_VERSION);
+        assert!(unversioned.local_registry.get(2).is_some());
+        assert!(unversioned.local_registry.get(5).is_some());
+        assert_eq!(unversioned.local_registry.last_local_id(), 5);
+
+        snapshot.migrate().unwrap();
+        assert_eq!(snapshot.local_registry.get(2), None);
+    }
+
+    #[test]
+    fn test_snapshots_of_a_newer_format_are_rejected() {
+        let mut snapshot = snapshot_with_stock_of(&[]);
+        snapshot.format_version = SNAPSHOT_FORMAT_VERSION + 1;
+        assert!(snapshot.migrate().is_err());
+    }
+}
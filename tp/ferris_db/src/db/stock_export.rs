@@ -0,0 +1,137 @@
+//! This module contains the export and import of the global stock to and from a directory.
+//!
+//! The stock is exported both as a single JSON file, with the quantity of each product indexed by
+//! local id, and as one file per local in the `name:quantity` line format read by the `StockParser`.
+//!
+//! When importing, the JSON file is used if it is present, and the per-local files otherwise.
+//! Only the primary can be started pre-loaded with an imported stock, since the backups receive
+//! theirs from it.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+};
+
+use shared::{model::stock_product::Product, parsers::stock_parser::StockParser};
+
+use super::constants::{LOCAL_STOCK_FILE_EXTENSION, LOCAL_STOCK_FILE_PREFIX, STOCK_JSON_FILENAME};
+
+pub type GlobalStock = HashMap<u16, HashMap<String, Product>>;
+
+pub fn export_stock(global_stock: &GlobalStock, dir: &Path) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+
+    let quantities: BTreeMap<u16, BTreeMap<String, i32>> = global_stock
+        .iter()
+        .map(|(local_id, stock)| {
+            let quantity_by_name = stock
+                .values()
+                .map(|product| (product.get_name(), product.get_quantity()))
+                .collect();
+            (*local_id, quantity_by_name)
+        })
+        .collect();
+    let json = serde_json::to_string_pretty(&quantities).map_err(|err| err.to_string())?;
+    fs::write(dir.join(STOCK_JSON_FILENAME), json).map_err(|err| err.to_string())?;
+
+    for (local_id, quantity_by_name) in quantities {
+        let lines: String = quantity_by_name
+            .iter()
+            .map(|(name, quantity)| format!("{}:{}\n", name, quantity))
+            .collect();
+        fs::write(dir.join(local_stock_filename(local_id)), lines)
+            .map_err(|err| err.to_string())?;
+    }
+    Ok(())
+}
+
+pub fn import_stock(dir: &Path) -> Result<GlobalStock, String> {
+    let json_path = dir.join(STOCK_JSON_FILENAME);
+    if json_path.exists() {
+        let content = fs::read_to_string(json_path).map_err(|err| err.to_string())?;
+        let quantities: HashMap<u16, HashMap<String, i32>> =
+            serde_json::from_str(&content).map_err(|err| err.to_string())?;
+        return Ok(quantities
+            .into_iter()
+            .map(|(local_id, quantity_by_name)| {
+                let stock = quantity_by_name
+                    .into_iter()
+                    .map(|(name, quantity)| (name.clone(), Product::new(name, quantity)))
+                    .collect();
+                (local_id, stock)
+            })
+            .collect());
+    }
+
+    let mut global_stock = HashMap::new();
+    for entry in fs::read_dir(dir).map_err(|err| err.to_string())? {
+        let path = entry.map_err(|err| err.to_string())?.path();
+        let Some(local_id) = path
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .and_then(|file_name| file_name.strip_prefix(LOCAL_STOCK_FILE_PREFIX))
+            .and_then(|file_name| file_name.strip_suffix(LOCAL_STOCK_FILE_EXTENSION))
+            .and_then(|local_id| local_id.parse::<u16>().ok())
+        else {
+            continue;
+        };
+        let path = path.to_str().ok_or("Invalid stock file path")?;
+        let stock = StockParser::new(path).map_err(|err| format!("{}: {}", path, err))?;
+        global_stock.insert(local_id, stock.get_products());
+    }
+    Ok(global_stock)
+}
+
+fn local_stock_filename(local_id: u16) -> String {
+    format!(
+        "{}{}{}",
+        LOCAL_STOCK_FILE_PREFIX, local_id, LOCAL_STOCK_FILE_EXTENSION
+    )
+}
+
+// ====================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exported_stock_is_imported_back_from_both_formats() {
+        let dir = std::env::temp_dir().join(format!("ferris_db_{}_export", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let global_stock = HashMap::from([
+            (
+                1,
+                HashMap::from([
+                    (
+                        "product1".to_string(),
+                        Product::new("product1".to_string(), 10),
+                    ),
+                    (
+                        "product2".to_string(),
+                        Product::new("product2".to_string(), 0),
+                    ),
+                ]),
+            ),
+            (
+                7,
+                HashMap::from([(
+                    "product1".to_string(),
+                    Product::new("product1".to_string(), 3),
+                )]),
+            ),
+        ]);
+
+        export_stock(&global_stock, &dir).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("local_1.txt")).unwrap(),
+            "product1:10\nproduct2:0\n"
+        );
+        assert_eq!(import_stock(&dir), Ok(global_stock.clone()));
+
+        fs::remove_file(dir.join(STOCK_JSON_FILENAME)).unwrap();
+        assert_eq!(import_stock(&dir), Ok(global_stock));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,133 @@
+//! Leader epochs are issued to the e-commerce leaders and checked against the `LeaderEpoch` before
+//! every write. Epochs are only raised when issued here or replicated from the upstream, never by a write.
+
+use actix::prelude::*;
+use shared::communication::db_response::DBErrorKind;
+use tracing::{info, warn};
+
+use super::StockHandler;
+use crate::db::{db_error::DBError, wal::WalEntry};
+
+impl StockHandler {
+    /// Issues an epoch higher than every one issued before to a new e-commerce leader.
+    pub fn acquire_leader_epoch(&mut self) -> Result<u64, DBError> {
+        let epoch = self.leader_epoch.next();
+        self.append_to_wal(&WalEntry::LeaderEpochRaised { epoch })
+            .map_err(|err| DBError::new(DBErrorKind::Storage, err))?;
+        self.leader_epoch.raise(epoch);
+        info!("[StockHandler] Issued leader epoch {}", epoch);
+        Ok(epoch)
+    }
+
+    /// Rejects writes from a deposed leader, and writes whose epoch this database never issued
+    /// nor received, which the leader must acquire again.
+    pub(super) fn check_leader_epoch(&self, epoch: u64) -> Result<(), DBError> {
+        if self.leader_epoch.is_stale(epoch) {
+            warn!(
+                "[StockHandler] Rejecting write with leader epoch {}, the current one is {}",
+                epoch,
+                self.leader_epoch.highest()
+            );
+            return Err(DBError::new(
+                DBErrorKind::StaleEpoch,
+                format!(
+                    "Leader epoch {} is older than the current one ({})",
+                    epoch,
+                    self.leader_epoch.highest()
+                ),
+            ));
+        }
+        if !self.leader_epoch.is_current(epoch) {
+            warn!(
+                "[StockHandler] Rejecting write with unknown leader epoch {}, the current one is {}",
+                epoch,
+                self.leader_epoch.highest()
+            );
+            return Err(DBError::new(
+                DBErrorKind::UnknownEpoch,
+                format!(
+                    "Leader epoch {} was not issued by this database (the current one is {})",
+                    epoch,
+                    self.leader_epoch.highest()
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+// ====================================================================
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<u64, DBError>")]
+pub struct AcquireLeaderEpoch {}
+
+impl Handler<AcquireLeaderEpoch> for StockHandler {
+    type Result = Result<u64, DBError>;
+
+    fn handle(&mut self, _: AcquireLeaderEpoch, _: &mut Self::Context) -> Self::Result {
+        self.acquire_leader_epoch()
+    }
+}
+
+/// Checks the epoch of a write applied outside the `StockHandler`, e.g. to the locals registry.
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), DBError>")]
+pub struct CheckLeaderEpoch {
+    pub epoch: u64,
+}
+
+impl Handler<CheckLeaderEpoch> for StockHandler {
+    type Result = Result<(), DBError>;
+
+    fn handle(&mut self, msg: CheckLeaderEpoch, _: &mut Self::Context) -> Self::Result {
+        self.check_leader_epoch(msg.epoch)
+    }
+}
+
+// ====================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_fixture::PersistedStockHandler;
+    use super::*;
+
+    #[test]
+    fn test_writes_from_deposed_leaders_are_rejected_after_restart() {
+        let persisted = PersistedStockHandler::new("leader_epoch");
+
+        let (mut stock_handler, _) = persisted.recover();
+        assert_eq!(
+            stock_handler.check_leader_epoch(0).map_err(|err| err.kind),
+            Err(DBErrorKind::UnknownEpoch)
+        );
+        let first_epoch = stock_handler.acquire_leader_epoch().unwrap();
+        let second_epoch = stock_handler.acquire_leader_epoch().unwrap();
+        assert!(second_epoch > first_epoch);
+        assert!(stock_handler.check_leader_epoch(second_epoch).is_ok());
+        assert_eq!(
+            stock_handler
+                .check_leader_epoch(first_epoch)
+                .map_err(|err| err.kind),
+            Err(DBErrorKind::StaleEpoch)
+        );
+        assert_eq!(
+            stock_handler
+                .check_leader_epoch(second_epoch + 5)
+                .map_err(|err| err.kind),
+            Err(DBErrorKind::UnknownEpoch)
+        );
+        drop(stock_handler);
+
+        let (mut restarted, _) = persisted.recover();
+        assert!(restarted.check_leader_epoch(second_epoch).is_ok());
+        let third_epoch = restarted.acquire_leader_epoch().unwrap();
+        assert_eq!(third_epoch, second_epoch + 1);
+        assert_eq!(
+            restarted
+                .check_leader_epoch(second_epoch)
+                .map_err(|err| err.kind),
+            Err(DBErrorKind::StaleEpoch)
+        );
+    }
+}
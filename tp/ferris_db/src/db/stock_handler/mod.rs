@@ -0,0 +1,1775 @@
+//! This module contains the `StockHandler` actor, which is responsible for managing the stock.
+//!
+//! It keeps track of the stock of each local shop and handles them according to the queries from the e-commerce servers.
+//! Every mutation is appended to the write-ahead log and streamed to the backup databases before
+//! being applied, and the updates to the stock of a local are applied in the order of their versions.
+
+mod epochs;
+mod partitions;
+mod reconciliation;
+mod replication;
+mod reporting;
+mod reservations;
+mod subscriptions;
+#[cfg(test)]
+mod test_fixture;
+
+pub use epochs::{AcquireLeaderEpoch, CheckLeaderEpoch};
+pub use reconciliation::ReconcileStock;
+pub use replication::{AddBackup, ApplyReplicatedRecord, RestoreFromUpstream};
+pub use reporting::{GetLocalStock, GetLocals, GetTopProducts, GetTotalQuantityByProduct};
+pub use reservations::{ReleaseReservation, ReserveStock, StartExpiringReservations};
+pub use subscriptions::{SubscribeToStock, UnsubscribeFromStock};
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use actix::prelude::*;
+
+use shared::{
+    communication::db_response::DBErrorKind,
+    model::{
+        catalog_product::CatalogProduct,
+        db_order_result::OrderResult,
+        order::Order,
+        reconciliation::ReconciliationPolicy,
+        stock_change::{StockChange, StockChangeCause},
+        stock_product::Product,
+    },
+};
+use tracing::{debug, error, info, warn};
+
+use super::{
+    applied_orders::AppliedOrders,
+    catalog::Catalog,
+    clock::current_timestamp,
+    connection_handler::{self, ConnectionHandler},
+    constants::SNAPSHOT_FORMAT_VERSION,
+    db_error::DBError,
+    db_middleman::DBMiddleman,
+    leader_epoch::LeaderEpoch,
+    local_registry::LocalRegistry,
+    order_log::OrderLog,
+    reservations::{Reservation, Reservations},
+    snapshot::Snapshot,
+    stock_export::{self, GlobalStock},
+    stock_history::{ChangeOrigin, StockHistory},
+    stock_partition::StockPartitions,
+    stock_store::{MemoryStockStore, StockStore},
+    stock_versions::{StockUpdate, StockVersions},
+    subscriptions::Subscriptions,
+    wal::{WalEntry, WalRecord, WriteAheadLog},
+};
+
+/// What became of an order result that was not rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderResultOutcome {
+    /// The order result was applied, or buffered until the previous stock versions arrive.
+    Accepted,
+    /// The order result had already been applied, so it was ignored.
+    AlreadyApplied,
+}
+
+#[derive(Debug)]
+pub struct StockHandler {
+    /// The stock of each local, indexed by product name.
+    stock_store: Box<dyn StockStore>,
+    reservations: Reservations,
+    stock_versions: StockVersions,
+    applied_orders: AppliedOrders,
+    stock_history: StockHistory,
+    order_log: OrderLog,
+    leader_epoch: LeaderEpoch,
+    subscriptions: Subscriptions,
+    stock_partitions: StockPartitions,
+    reconciliation_policy: ReconciliationPolicy,
+    catalog: Catalog,
+    wal: Option<WriteAheadLog>,
+    snapshot_path: Option<PathBuf>,
+    last_snapshot_lsn: u64,
+    backups: Vec<Addr<DBMiddleman>>,
+}
+
+impl StockHandler {
+    pub fn new() -> Self {
+        StockHandler {
+            stock_store: Box::new(MemoryStockStore::new()),
+            reservations: Reservations::new(),
+            stock_versions: StockVersions::new(),
+            applied_orders: AppliedOrders::new(),
+            stock_history: StockHistory::new(),
+            order_log: OrderLog::new(),
+            leader_epoch: LeaderEpoch::new(),
+            subscriptions: Subscriptions::new(),
+            stock_partitions: StockPartitions::default(),
+            reconciliation_policy: ReconciliationPolicy::default(),
+            catalog: Catalog::new(),
+            wal: None,
+            snapshot_path: None,
+            last_snapshot_lsn: 0,
+            backups: Vec::new(),
+        }
+    }
+
+    /// Rebuilds the stock from the snapshot at the given path (if any) and the records of the log
+    /// that come after it. The returned `StockHandler` keeps appending every new mutation to the log.
+    ///
+    /// The registry of issued local ids is rebuilt and returned as well, since it is owned by the `ConnectionHandler`.
+    ///
+    /// The stock is kept in the given store, replacing whatever it held from a previous run.
+    pub fn recover(
+        mut wal: WriteAheadLog,
+        snapshot_path: &Path,
+        stock_store: Box<dyn StockStore>,
+    ) -> Result<(Self, LocalRegistry), String> {
+        let mut stock_handler = StockHandler::new();
+        let mut local_registry = LocalRegistry::new();
+        let mut global_stock = GlobalStock::new();
+        stock_handler.stock_store = stock_store;
+
+        if let Some(snapshot) = Snapshot::load(snapshot_path)? {
+            info!(
+                "[StockHandler] Loaded snapshot up to log record {}",
+                snapshot.last_lsn
+            );
+            global_stock = snapshot.global_stock;
+            stock_handler.reservations = snapshot.reservations;
+            stock_handler.stock_versions = snapshot.stock_versions;
+            stock_handler.applied_orders = snapshot.applied_orders;
+            stock_handler.stock_history = snapshot.stock_history;
+            stock_handler.order_log = snapshot.order_log;
+            stock_handler.leader_epoch = snapshot.leader_epoch;
+            stock_handler.last_snapshot_lsn = snapshot.last_lsn;
+            local_registry = snapshot.local_registry;
+            wal.continue_after(snapshot.last_lsn);
+        }
+        stock_handler.stock_store.replace_all(global_stock);
+
+        let records: Vec<_> = wal
+            .read_records()?
+            .into_iter()
+            .filter(|record| record.lsn > stock_handler.last_snapshot_lsn)
+            .collect();
+        info!(
+            "[StockHandler] Replaying {} records from the write-ahead log",
+            records.len()
+        );
+        for record in records {
+            if let WalEntry::LocalIdIssued {
+                local_id,
+                registered_at,
+            } = record.entry
+            {
+                local_registry.register(local_id, registered_at);
+            }
+            if let Err(err) = stock_handler.apply_entry(record.entry) {
+                warn!("[StockHandler] Skipping log record on replay: {}", err);
+            }
+        }
+
+        // Locals whose stock is known but whose id is not registered (e.g. issued before the
+        // registry existed) are registered now, so their ids are never issued again.
+        let now = current_timestamp();
+        for local_id in stock_handler.stock_store.local_ids() {
+            if local_registry.get(local_id).is_none() {
+                local_registry.register(local_id, now);
+            }
+        }
+
+        stock_handler.wal = Some(wal);
+        stock_handler.snapshot_path = Some(snapshot_path.to_path_buf());
+        Ok((stock_handler, local_registry))
+    }
+
+    fn append_to_wal(&mut self, entry: &WalEntry) -> Result<(), String> {
+        if let Some(wal) = self.wal.as_mut() {
+            let lsn = wal.append(entry)?;
+            self.replicate(WalRecord {
+                lsn,
+                entry: entry.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    fn current_snapshot(&self, local_registry: LocalRegistry) -> Snapshot {
+        Snapshot {
+            format_version: SNAPSHOT_FORMAT_VERSION,
+            last_lsn: self.wal.as_ref().map(|wal| wal.last_lsn()).unwrap_or(0),
+            local_registry,
+            global_stock: self.stock_store.all_stock(),
+            reservations: self.reservations.clone(),
+            stock_versions: self.stock_versions.clone(),
+            applied_orders: self.applied_orders.clone(),
+            stock_history: self.stock_history.clone(),
+            leader_epoch: self.leader_epoch,
+            order_log: self.order_log.clone(),
+        }
+    }
+
+    fn apply_entry(&mut self, entry: WalEntry) -> Result<(), String> {
+        match entry {
+            WalEntry::PostStockFromLocal {
+                local_id,
+                stock,
+                version,
+                timestamp,
+                ecommerce_id,
+            } => {
+                let origin = ChangeOrigin {
+                    cause: StockChangeCause::StockUpload { version },
+                    timestamp,
+                    ecommerce_id,
+                };
+                self.apply_stock_upload(local_id, stock, version, &origin);
+                Ok(())
+            }
+            WalEntry::PostOrderResult {
+                order,
+                stock_version,
+                timestamp,
+                ecommerce_id,
+            } => {
+                self.check_order_result_against_stock(&order)?;
+                let origin = ChangeOrigin {
+                    cause: StockChangeCause::OrderResult { stock_version },
+                    timestamp,
+                    ecommerce_id,
+                };
+                self.apply_order_result(&order, &origin);
+                if let Some(local_id) = order.get_local_id() {
+                    self.stock_versions.advance(local_id, stock_version);
+                }
+                if let Some(order_id) = order.get_id() {
+                    self.applied_orders.insert(order_id);
+                    self.consume_reservation_of(order_id);
+                }
+                Ok(())
+            }
+            WalEntry::StockDelta {
+                local_id,
+                product_name,
+                change,
+                version,
+                timestamp,
+                ecommerce_id,
+            } => {
+                let origin = ChangeOrigin {
+                    cause: StockChangeCause::StockDelta { version },
+                    timestamp,
+                    ecommerce_id,
+                };
+                self.apply_stock_delta(local_id, &product_name, change, &origin);
+                self.stock_versions.advance(local_id, version);
+                Ok(())
+            }
+            WalEntry::LocalIdIssued { .. } => Ok(()),
+            WalEntry::StockReserved {
+                reservation_id,
+                local_id,
+                products,
+                order_id,
+                reserved_at,
+            } => {
+                self.hold_reservation(
+                    reservation_id,
+                    Reservation {
+                        local_id,
+                        products,
+                        order_id,
+                        reserved_at,
+                    },
+                );
+                Ok(())
+            }
+            WalEntry::ReservationCommitted {
+                reservation_id,
+                timestamp,
+                ecommerce_id,
+            } => {
+                self.check_reservation_exists(reservation_id)?;
+                let origin = ChangeOrigin {
+                    cause: StockChangeCause::ReservationCommitted { reservation_id },
+                    timestamp,
+                    ecommerce_id,
+                };
+                self.apply_reservation_commit(reservation_id, &origin);
+                Ok(())
+            }
+            WalEntry::ReservationReleased { reservation_id } => {
+                self.check_reservation_exists(reservation_id)?;
+                self.drop_reservation(reservation_id);
+                Ok(())
+            }
+            WalEntry::LocalForgotten {
+                local_id,
+                timestamp,
+            } => {
+                let origin = ChangeOrigin {
+                    cause: StockChangeCause::LocalForgotten,
+                    timestamp,
+                    ecommerce_id: None,
+                };
+                self.apply_local_forgotten(local_id, &origin);
+                Ok(())
+            }
+            WalEntry::StockReconciled {
+                local_id,
+                stock,
+                version,
+                timestamp,
+                ecommerce_id,
+            } => {
+                let origin = ChangeOrigin {
+                    cause: StockChangeCause::Reconciliation { version },
+                    timestamp,
+                    ecommerce_id,
+                };
+                self.apply_stock_upload(local_id, stock, version, &origin);
+                Ok(())
+            }
+            WalEntry::VersionSkipped { local_id, version } => {
+                self.stock_versions.advance(local_id, version);
+                Ok(())
+            }
+            WalEntry::LeaderEpochRaised { epoch } => {
+                self.leader_epoch.raise(epoch);
+                Ok(())
+            }
+        }
+    }
+
+    /// Saves a snapshot of the current state and truncates the log, if anything was logged since the last one.
+    pub fn take_snapshot(&mut self, local_registry: LocalRegistry) -> Result<(), String> {
+        let (Some(wal), Some(snapshot_path)) = (self.wal.as_ref(), self.snapshot_path.as_ref())
+        else {
+            return Ok(());
+        };
+        let last_lsn = wal.last_lsn();
+        if last_lsn == self.last_snapshot_lsn {
+            return Ok(());
+        }
+
+        self.current_snapshot(local_registry).save(snapshot_path)?;
+        if let Some(wal) = self.wal.as_mut() {
+            wal.truncate()?;
+        }
+        self.last_snapshot_lsn = last_lsn;
+        info!(
+            "[StockHandler] Snapshot taken up to log record {}",
+            last_lsn
+        );
+        Ok(())
+    }
+
+    pub fn add_local_shop_stock(
+        &mut self,
+        local_shop_id: u16,
+        local_shop_stock: HashMap<String, Product>,
+    ) {
+        self.stock_store
+            .replace_local_stock(local_shop_id, local_shop_stock);
+    }
+
+    pub fn get_quantity_of_product_from_all_stocks(
+        &self,
+        product_name: String,
+    ) -> HashMap<u16, i32> {
+        let mut products_quantity_in_locals = HashMap::new();
+        for local_shop_id in self.stock_store.local_ids() {
+            products_quantity_in_locals.insert(
+                local_shop_id,
+                self.available_quantity(local_shop_id, &product_name),
+            );
+        }
+        products_quantity_in_locals
+    }
+
+    /// Returns the quantity of each product in each local, indexed by product name and then by local id,
+    /// going through the stock of each local only once.
+    pub fn get_quantity_of_products_from_all_stocks(
+        &self,
+        product_names: &[String],
+    ) -> HashMap<String, HashMap<u16, i32>> {
+        let mut products_quantity_in_locals: HashMap<String, HashMap<u16, i32>> = product_names
+            .iter()
+            .map(|product_name| (product_name.clone(), HashMap::new()))
+            .collect();
+        for local_shop_id in self.stock_store.local_ids() {
+            for (product_name, quantity_in_locals) in products_quantity_in_locals.iter_mut() {
+                quantity_in_locals.insert(
+                    local_shop_id,
+                    self.available_quantity(local_shop_id, product_name),
+                );
+            }
+        }
+        products_quantity_in_locals
+    }
+
+    /// Returns the quantity of a product in a local that is not held by any reservation.
+    fn available_quantity(&self, local_shop_id: u16, product_name: &str) -> i32 {
+        let Some(quantity) = self.stock_store.quantity(local_shop_id, product_name) else {
+            return 0;
+        };
+        let reserved_quantity = self
+            .reservations
+            .reserved_quantity(local_shop_id, product_name);
+        (quantity - reserved_quantity).max(0)
+    }
+
+    pub fn process_order_result_in_stock(
+        &mut self,
+        order: Order,
+        stock_version: u64,
+        ecommerce_id: Option<u16>,
+    ) -> Result<OrderResultOutcome, DBError> {
+        let local_shop_id = order.get_local_id().ok_or_else(|| {
+            DBError::new(
+                DBErrorKind::OrderResultRejected,
+                "Couldn't get local shop id from order",
+            )
+        })?;
+        let order_id = order.get_id().ok_or_else(|| {
+            DBError::new(
+                DBErrorKind::OrderResultRejected,
+                "Couldn't get the id of the order",
+            )
+        })?;
+        if self.applied_orders.contains(order_id) {
+            info!(
+                "[StockHandler] Result of order {} of local shop {} was already applied",
+                order_id, local_shop_id
+            );
+            // An order whose reservation was committed is applied before its result takes the
+            // local's stock to its version, which is then skipped instead.
+            if self
+                .check_version_is_not_stale(local_shop_id, stock_version)
+                .is_ok()
+            {
+                self.process_versioned_update(
+                    local_shop_id,
+                    stock_version,
+                    StockUpdate::OrderResult {
+                        order,
+                        ecommerce_id,
+                    },
+                )?;
+            }
+            return Ok(OrderResultOutcome::AlreadyApplied);
+        }
+        self.process_versioned_update(
+            local_shop_id,
+            stock_version,
+            StockUpdate::OrderResult {
+                order,
+                ecommerce_id,
+            },
+        )?;
+        Ok(OrderResultOutcome::Accepted)
+    }
+
+    pub fn process_stock_delta(
+        &mut self,
+        local_shop_id: u16,
+        product_name: String,
+        change: i32,
+        version: u64,
+        ecommerce_id: Option<u16>,
+    ) -> Result<(), DBError> {
+        self.process_versioned_update(
+            local_shop_id,
+            version,
+            StockUpdate::Delta {
+                product_name,
+                change,
+                ecommerce_id,
+            },
+        )
+    }
+
+    fn check_version_is_not_stale(&self, local_shop_id: u16, version: u64) -> Result<(), DBError> {
+        let current_version = self.stock_versions.current(local_shop_id);
+        if version <= current_version {
+            return Err(DBError::new(
+                DBErrorKind::StaleUpdate,
+                format!(
+                    "Update to version {} of local shop {}'s stock is stale, it is already at version {}",
+                    version, local_shop_id, current_version
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Applies an update to the stock of a local if it comes right after the current version, and
+    /// then the buffered updates that follow it. Updates to a later version are buffered instead.
+    fn process_versioned_update(
+        &mut self,
+        local_shop_id: u16,
+        version: u64,
+        update: StockUpdate,
+    ) -> Result<(), DBError> {
+        self.check_version_is_not_stale(local_shop_id, version)?;
+        let next_version = self.stock_versions.current(local_shop_id) + 1;
+        if version > next_version {
+            info!(
+                "[StockHandler] Buffering update to version {} of local shop {}'s stock until version {} arrives",
+                version, local_shop_id, next_version
+            );
+            self.stock_versions.buffer(local_shop_id, version, update);
+            return Ok(());
+        }
+        let result = self.apply_versioned_update(local_shop_id, version, update);
+        self.apply_buffered_updates(local_shop_id);
+        result
+    }
+
+    fn apply_buffered_updates(&mut self, local_shop_id: u16) {
+        while let Some((version, update)) = self.stock_versions.take_next(local_shop_id) {
+            if let Err(err) = self.apply_versioned_update(local_shop_id, version, update) {
+                warn!(
+                    "[StockHandler] Buffered update to version {} of local shop {}'s stock failed: {}",
+                    version, local_shop_id, err.detail
+                );
+            }
+        }
+    }
+
+    /// Takes the local's stock to a version it reached without changing, so the updates that
+    /// follow do not wait for it.
+    fn skip_version(&mut self, local_shop_id: u16, version: u64) -> Result<(), DBError> {
+        self.append_to_wal(&WalEntry::VersionSkipped {
+            local_id: local_shop_id,
+            version,
+        })
+        .map_err(|err| DBError::new(DBErrorKind::Storage, err))?;
+        self.stock_versions.advance(local_shop_id, version);
+        Ok(())
+    }
+
+    fn apply_versioned_update(
+        &mut self,
+        local_shop_id: u16,
+        version: u64,
+        update: StockUpdate,
+    ) -> Result<(), DBError> {
+        let timestamp = current_timestamp();
+        match update {
+            StockUpdate::OrderResult {
+                order,
+                ecommerce_id,
+            } => {
+                if order
+                    .get_id()
+                    .is_some_and(|order_id| self.applied_orders.contains(order_id))
+                {
+                    return self.skip_version(local_shop_id, version);
+                }
+                if let Err(err) = self
+                    .catalog
+                    .check_products_are_known(&order.get_products())
+                    .and_then(|_| self.check_order_result_against_stock(&order))
+                {
+                    self.skip_version(local_shop_id, version)?;
+                    return Err(DBError::new(DBErrorKind::OrderResultRejected, err));
+                }
+                self.append_to_wal(&WalEntry::PostOrderResult {
+                    order: order.clone(),
+                    stock_version: version,
+                    timestamp,
+                    ecommerce_id,
+                })
+                .map_err(|err| DBError::new(DBErrorKind::Storage, err))?;
+                let origin = ChangeOrigin {
+                    cause: StockChangeCause::OrderResult {
+                        stock_version: version,
+                    },
+                    timestamp,
+                    ecommerce_id,
+                };
+                self.apply_order_result(&order, &origin);
+                if let Some(order_id) = order.get_id() {
+                    self.applied_orders.insert(order_id);
+                    self.consume_reservation_of(order_id);
+                }
+            }
+            StockUpdate::Delta {
+                product_name,
+                change,
+                ecommerce_id,
+            } => {
+                self.append_to_wal(&WalEntry::StockDelta {
+                    local_id: local_shop_id,
+                    product_name: product_name.clone(),
+                    change,
+                    version,
+                    timestamp,
+                    ecommerce_id,
+                })
+                .map_err(|err| DBError::new(DBErrorKind::Storage, err))?;
+                let origin = ChangeOrigin {
+                    cause: StockChangeCause::StockDelta { version },
+                    timestamp,
+                    ecommerce_id,
+                };
+                self.apply_stock_delta(local_shop_id, &product_name, change, &origin);
+            }
+        }
+        self.stock_versions.advance(local_shop_id, version);
+        Ok(())
+    }
+
+    fn apply_stock_delta(
+        &mut self,
+        local_shop_id: u16,
+        product_name: &str,
+        change: i32,
+        origin: &ChangeOrigin,
+    ) {
+        self.stock_history
+            .record(local_shop_id, product_name, change, origin);
+        self.mark_changed(local_shop_id, product_name);
+        self.stock_store
+            .change_quantities(local_shop_id, &[(product_name.to_string(), change)]);
+    }
+
+    fn check_order_result_against_stock(&self, order: &Order) -> Result<(), String> {
+        let local_shop_id = order
+            .get_local_id()
+            .ok_or("Couldn't get local shop id from order")?;
+        if !self.stock_store.contains_local(local_shop_id) {
+            error!("Local shop {} not found in global stock", local_shop_id);
+            return Err("Local shop not found in global stock".to_string());
+        }
+        for product in order.get_products() {
+            let product_name = product.get_name();
+            let product_quantity = product.get_quantity();
+            if let Some(quantity_in_stock) = self.stock_store.quantity(local_shop_id, &product_name)
+            {
+                if quantity_in_stock < product_quantity {
+                    error!(
+                        "Product {} from order result has quantity {} but local shop {}'s stock has quantity {}",
+                        product_name,
+                        product_quantity,
+                        local_shop_id,
+                        quantity_in_stock
+                    );
+                    return Err("Product quantity in local shop stock is less than order result product quantity".to_string());
+                }
+            } else {
+                error!(
+                    "Product {} from order result not found in local shop {} stock",
+                    product_name, local_shop_id
+                );
+                return Err("Product not found in local shop stock".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_order_result(&mut self, order: &Order, origin: &ChangeOrigin) {
+        let Some(local_shop_id) = order.get_local_id() else {
+            return;
+        };
+        self.take_from_local_shop_stock(local_shop_id, &order.get_products(), origin);
+        self.order_log.record(OrderResult::new(
+            order.clone(),
+            origin.ecommerce_id,
+            local_shop_id,
+            origin.timestamp,
+        ));
+    }
+
+    fn take_from_local_shop_stock(
+        &mut self,
+        local_shop_id: u16,
+        products: &[Product],
+        origin: &ChangeOrigin,
+    ) {
+        let changes: Vec<(String, i32)> = products
+            .iter()
+            .filter(|product| {
+                self.stock_store
+                    .quantity(local_shop_id, &product.get_name())
+                    .is_some()
+            })
+            .map(|product| (product.get_name(), -product.get_quantity()))
+            .collect();
+        if changes.is_empty() {
+            return;
+        }
+        for (product_name, change) in &changes {
+            self.stock_history
+                .record(local_shop_id, product_name, *change, origin);
+            self.mark_changed(local_shop_id, product_name);
+        }
+        self.stock_store.change_quantities(local_shop_id, &changes);
+    }
+
+    /// Replaces the whole stock of a local, which supersedes every update up to its version.
+    pub fn process_post_to_stock_from_local(
+        &mut self,
+        local_id: u16,
+        stock: HashMap<String, Product>,
+        version: u64,
+        ecommerce_id: Option<u16>,
+    ) -> Result<(), DBError> {
+        self.check_version_is_not_stale(local_id, version)?;
+        let timestamp = current_timestamp();
+        self.append_to_wal(&WalEntry::PostStockFromLocal {
+            local_id,
+            stock: stock.clone(),
+            version,
+            timestamp,
+            ecommerce_id,
+        })
+        .map_err(|err| DBError::new(DBErrorKind::Storage, err))?;
+        let origin = ChangeOrigin {
+            cause: StockChangeCause::StockUpload { version },
+            timestamp,
+            ecommerce_id,
+        };
+        self.apply_stock_upload(local_id, stock, version, &origin);
+        self.apply_buffered_updates(local_id);
+        Ok(())
+    }
+
+    /// Replaces the whole stock of a local, recording how much each product changed.
+    fn apply_stock_upload(
+        &mut self,
+        local_id: u16,
+        stock: HashMap<String, Product>,
+        version: u64,
+        origin: &ChangeOrigin,
+    ) {
+        let previous_stock = self.stock_store.local_stock(local_id).unwrap_or_default();
+        for (product_name, product) in &stock {
+            let previous_quantity = previous_stock
+                .get(product_name)
+                .map(|product| product.get_quantity())
+                .unwrap_or(0);
+            self.stock_history.record(
+                local_id,
+                product_name,
+                product.get_quantity() - previous_quantity,
+                origin,
+            );
+            self.mark_changed(local_id, product_name);
+        }
+        for (product_name, product) in &previous_stock {
+            if !stock.contains_key(product_name) {
+                self.stock_history
+                    .record(local_id, product_name, -product.get_quantity(), origin);
+                self.mark_changed(local_id, product_name);
+            }
+        }
+        self.add_local_shop_stock(local_id, stock);
+        self.stock_versions.reset(local_id, version);
+    }
+
+    /// Marks a product of a local as changed, for its subscribers and its partition.
+    fn mark_changed(&mut self, local_id: u16, product_name: &str) {
+        self.subscriptions.mark_changed(local_id, product_name);
+        self.stock_partitions.mark_changed(local_id, product_name);
+    }
+
+    /// Sends every change since the last call to the subscribers and to the partitions.
+    fn publish_changes(&mut self) {
+        self.notify_subscribers();
+        self.publish_to_partitions();
+    }
+
+    /// Returns a line with the stock of each local, or only of the given one, sorted by local id and product name.
+    pub fn describe_stock(&self, local_shop_id: Option<u16>) -> Result<Vec<String>, String> {
+        let local_shop_ids: Vec<u16> = match local_shop_id {
+            Some(local_shop_id) if !self.stock_store.contains_local(local_shop_id) => {
+                return Err(format!(
+                    "Local shop {} not found in global stock",
+                    local_shop_id
+                ))
+            }
+            Some(local_shop_id) => vec![local_shop_id],
+            None => self.stock_store.local_ids(),
+        };
+        Ok(local_shop_ids
+            .into_iter()
+            .map(|local_shop_id| {
+                let mut products: Vec<String> = self
+                    .stock_store
+                    .local_stock(local_shop_id)
+                    .unwrap_or_default()
+                    .values()
+                    .map(|product| format!("{}: {}", product.get_name(), product.get_quantity()))
+                    .collect();
+                products.sort();
+                format!("Local {}: [{}]", local_shop_id, products.join(", "))
+            })
+            .collect())
+    }
+
+    pub fn export_stock(&self, dir: &Path) -> Result<(), String> {
+        stock_export::export_stock(&self.stock_store.all_stock(), dir)
+    }
+
+    /// Replaces the stock of the given locals, keeping the version of each one, so that the
+    /// stock the locals post afterwards supersedes it.
+    pub fn seed_stock(&mut self, global_stock: GlobalStock) -> Result<(), String> {
+        let timestamp = current_timestamp();
+        for (local_id, stock) in global_stock {
+            let version = self.stock_versions.current(local_id);
+            self.append_to_wal(&WalEntry::PostStockFromLocal {
+                local_id,
+                stock: stock.clone(),
+                version,
+                timestamp,
+                ecommerce_id: None,
+            })?;
+            let origin = ChangeOrigin {
+                cause: StockChangeCause::StockUpload { version },
+                timestamp,
+                ecommerce_id: None,
+            };
+            self.apply_stock_upload(local_id, stock, version, &origin);
+        }
+        Ok(())
+    }
+
+    /// Drops the stock and the reservations of a local, so it is no longer taken into account.
+    /// Its id is still never issued again, and its stock is known again if it posts it.
+    pub fn forget_local(&mut self, local_shop_id: u16) -> Result<(), String> {
+        if !self.stock_store.contains_local(local_shop_id) {
+            return Err(format!(
+                "Local shop {} not found in global stock",
+                local_shop_id
+            ));
+        }
+        let timestamp = current_timestamp();
+        self.append_to_wal(&WalEntry::LocalForgotten {
+            local_id: local_shop_id,
+            timestamp,
+        })?;
+        let origin = ChangeOrigin {
+            cause: StockChangeCause::LocalForgotten,
+            timestamp,
+            ecommerce_id: None,
+        };
+        self.apply_local_forgotten(local_shop_id, &origin);
+        Ok(())
+    }
+
+    fn apply_local_forgotten(&mut self, local_shop_id: u16, origin: &ChangeOrigin) {
+        for product in self
+            .stock_store
+            .remove_local(local_shop_id)
+            .unwrap_or_default()
+            .values()
+        {
+            self.stock_history.record(
+                local_shop_id,
+                &product.get_name(),
+                -product.get_quantity(),
+                origin,
+            );
+            self.mark_changed(local_shop_id, &product.get_name());
+        }
+        self.reservations.remove_local(local_shop_id);
+        self.stock_versions.discard_buffered(local_shop_id);
+    }
+
+    pub fn record_issued_local_id(
+        &mut self,
+        local_id: u16,
+        registered_at: u64,
+    ) -> Result<(), String> {
+        self.append_to_wal(&WalEntry::LocalIdIssued {
+            local_id,
+            registered_at,
+        })
+    }
+
+    /// Replaces the catalog, which is not persisted since it is loaded from a file on every start.
+    pub fn set_catalog(&mut self, catalog: Catalog) {
+        self.catalog = catalog;
+    }
+
+    pub fn get_catalog_product(&self, product_name: &str) -> Result<CatalogProduct, DBError> {
+        self.catalog.get(product_name).cloned().ok_or_else(|| {
+            DBError::new(
+                DBErrorKind::UnknownProduct,
+                format!("Product {} is not in the catalog", product_name),
+            )
+        })
+    }
+
+    pub fn get_catalog(&self, category: Option<&str>) -> Vec<CatalogProduct> {
+        self.catalog.products(category)
+    }
+
+    pub fn get_stock_history(
+        &self,
+        local_shop_id: Option<u16>,
+        product_name: Option<&str>,
+        from: u64,
+        to: u64,
+    ) -> Vec<StockChange> {
+        self.stock_history
+            .query(local_shop_id, product_name, from, to)
+    }
+
+    pub fn get_order_results(
+        &self,
+        local_shop_id: Option<u16>,
+        ecommerce_id: Option<u16>,
+        product_name: Option<&str>,
+        from: u64,
+        to: u64,
+    ) -> Vec<OrderResult> {
+        self.order_log
+            .query(local_shop_id, ecommerce_id, product_name, from, to)
+    }
+}
+
+impl Actor for StockHandler {
+    type Context = actix::Context<Self>;
+
+    fn started(&mut self, _ctx: &mut Self::Context) {
+        debug!("[StockHandler] Started");
+    }
+}
+
+// ====================================================================
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), DBError>")]
+pub struct PostStockFromLocal {
+    pub local_id: u16,
+    pub stock: HashMap<String, Product>,
+    pub version: u64,
+    pub ecommerce_id: Option<u16>,
+    pub epoch: u64,
+}
+
+impl Handler<PostStockFromLocal> for StockHandler {
+    type Result = Result<(), DBError>;
+
+    fn handle(&mut self, msg: PostStockFromLocal, _: &mut Self::Context) -> Self::Result {
+        self.check_leader_epoch(msg.epoch)?;
+        let result = self
+            .process_post_to_stock_from_local(
+                msg.local_id,
+                msg.stock,
+                msg.version,
+                msg.ecommerce_id,
+            )
+            .map_err(|err| {
+                error!(
+                    "[StockHandler] Error posting stock from local: {}",
+                    err.detail
+                );
+                err
+            });
+        self.publish_changes();
+        result
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<OrderResultOutcome, DBError>")]
+pub struct PostOrderResult {
+    pub order: Order,
+    pub stock_version: u64,
+    pub ecommerce_id: Option<u16>,
+    pub epoch: u64,
+}
+
+impl Handler<PostOrderResult> for StockHandler {
+    type Result = Result<OrderResultOutcome, DBError>;
+
+    fn handle(&mut self, msg: PostOrderResult, _: &mut Self::Context) -> Self::Result {
+        self.check_leader_epoch(msg.epoch)?;
+        let result =
+            self.process_order_result_in_stock(msg.order, msg.stock_version, msg.ecommerce_id);
+        self.publish_changes();
+        result
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), DBError>")]
+pub struct PostStockDelta {
+    pub local_id: u16,
+    pub product_name: String,
+    pub change: i32,
+    pub version: u64,
+    pub ecommerce_id: Option<u16>,
+    pub epoch: u64,
+}
+
+impl Handler<PostStockDelta> for StockHandler {
+    type Result = Result<(), DBError>;
+
+    fn handle(&mut self, msg: PostStockDelta, _: &mut Self::Context) -> Self::Result {
+        self.check_leader_epoch(msg.epoch)?;
+        let result = self.process_stock_delta(
+            msg.local_id,
+            msg.product_name,
+            msg.change,
+            msg.version,
+            msg.ecommerce_id,
+        );
+        self.publish_changes();
+        result
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<CatalogProduct, DBError>")]
+pub struct GetCatalogProduct {
+    pub product_name: String,
+}
+
+impl Handler<GetCatalogProduct> for StockHandler {
+    type Result = Result<CatalogProduct, DBError>;
+
+    fn handle(&mut self, msg: GetCatalogProduct, _: &mut Self::Context) -> Self::Result {
+        self.get_catalog_product(&msg.product_name)
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Vec<CatalogProduct>")]
+pub struct GetCatalog {
+    pub category: Option<String>,
+}
+
+impl Handler<GetCatalog> for StockHandler {
+    type Result = MessageResult<GetCatalog>;
+
+    fn handle(&mut self, msg: GetCatalog, _: &mut Self::Context) -> Self::Result {
+        MessageResult(self.get_catalog(msg.category.as_deref()))
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Vec<StockChange>")]
+pub struct GetStockHistory {
+    pub local_id: Option<u16>,
+    pub product_name: Option<String>,
+    pub from: u64,
+    pub to: u64,
+}
+
+impl Handler<GetStockHistory> for StockHandler {
+    type Result = MessageResult<GetStockHistory>;
+
+    fn handle(&mut self, msg: GetStockHistory, _: &mut Self::Context) -> Self::Result {
+        MessageResult(self.get_stock_history(
+            msg.local_id,
+            msg.product_name.as_deref(),
+            msg.from,
+            msg.to,
+        ))
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Vec<OrderResult>")]
+pub struct GetOrderResults {
+    pub local_id: Option<u16>,
+    pub ecommerce_id: Option<u16>,
+    pub product_name: Option<String>,
+    pub from: u64,
+    pub to: u64,
+}
+
+impl Handler<GetOrderResults> for StockHandler {
+    type Result = MessageResult<GetOrderResults>;
+
+    fn handle(&mut self, msg: GetOrderResults, _: &mut Self::Context) -> Self::Result {
+        MessageResult(self.get_order_results(
+            msg.local_id,
+            msg.ecommerce_id,
+            msg.product_name.as_deref(),
+            msg.from,
+            msg.to,
+        ))
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct ShowStock {
+    pub local_id: Option<u16>,
+}
+
+impl Handler<ShowStock> for StockHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: ShowStock, _: &mut Self::Context) -> Self::Result {
+        let lines = self.describe_stock(msg.local_id)?;
+        if lines.is_empty() {
+            info!("[StockHandler] No stock known yet");
+        }
+        for line in lines {
+            info!("[StockHandler] {}", line);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct ExportStock {
+    pub dir: PathBuf,
+}
+
+impl Handler<ExportStock> for StockHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: ExportStock, _: &mut Self::Context) -> Self::Result {
+        self.export_stock(&msg.dir)
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct ForgetLocal {
+    pub local_id: u16,
+}
+
+impl Handler<ForgetLocal> for StockHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: ForgetLocal, _: &mut Self::Context) -> Self::Result {
+        let result = self.forget_local(msg.local_id);
+        self.publish_changes();
+        result
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), DBError>")]
+pub struct RecordIssuedLocalId {
+    pub local_id: u16,
+    pub registered_at: u64,
+    pub epoch: u64,
+}
+
+impl Handler<RecordIssuedLocalId> for StockHandler {
+    type Result = Result<(), DBError>;
+
+    fn handle(&mut self, msg: RecordIssuedLocalId, _: &mut Self::Context) -> Self::Result {
+        self.check_leader_epoch(msg.epoch)?;
+        self.record_issued_local_id(msg.local_id, msg.registered_at)
+            .map_err(|err| DBError::new(DBErrorKind::Storage, err))
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct TakeSnapshot {
+    pub local_registry: LocalRegistry,
+}
+
+impl Handler<TakeSnapshot> for StockHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: TakeSnapshot, _: &mut Self::Context) -> Self::Result {
+        self.take_snapshot(msg.local_registry)
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), DBError>")]
+pub struct GetProductQuantityFromAllLocals {
+    pub requestor_db_middleman: Addr<DBMiddleman>,
+    pub request_id: u64,
+    pub connection_handler: Addr<ConnectionHandler>,
+    pub requestor_ss_id: u16,
+    pub requestor_worker_id: u16,
+    pub product_name: String,
+}
+
+impl Handler<GetProductQuantityFromAllLocals> for StockHandler {
+    type Result = Result<(), DBError>;
+
+    fn handle(
+        &mut self,
+        msg: GetProductQuantityFromAllLocals,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        let products_quantity_in_locals =
+            self.get_quantity_of_product_from_all_stocks(msg.product_name.clone());
+        // Sent even if the mailbox is full, since the requestor waits for the answer.
+        msg.connection_handler.do_send(
+            connection_handler::ReplyToRequestorWithProductQuantityFromAllLocals {
+                requestor_db_middleman: msg.requestor_db_middleman,
+                request_id: msg.request_id,
+                product_quantity_in_locals: products_quantity_in_locals,
+                requestor_ss_id: msg.requestor_ss_id,
+                requestor_worker_id: msg.requestor_worker_id,
+                product_name: msg.product_name,
+            },
+        );
+        Ok(())
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), DBError>")]
+pub struct GetProductsQuantityFromAllLocals {
+    pub requestor_db_middleman: Addr<DBMiddleman>,
+    pub request_id: u64,
+    pub connection_handler: Addr<ConnectionHandler>,
+    pub requestor_ss_id: u16,
+    pub requestor_worker_id: u16,
+    pub product_names: Vec<String>,
+}
+
+impl Handler<GetProductsQuantityFromAllLocals> for StockHandler {
+    type Result = Result<(), DBError>;
+
+    fn handle(
+        &mut self,
+        msg: GetProductsQuantityFromAllLocals,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        let products_quantity_in_locals =
+            self.get_quantity_of_products_from_all_stocks(&msg.product_names);
+        msg.connection_handler.do_send(
+            connection_handler::ReplyToRequestorWithProductsQuantityFromAllLocals {
+                requestor_db_middleman: msg.requestor_db_middleman,
+                request_id: msg.request_id,
+                products_quantity_in_locals,
+                requestor_ss_id: msg.requestor_ss_id,
+                requestor_worker_id: msg.requestor_worker_id,
+            },
+        );
+        Ok(())
+    }
+}
+
+// ====================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::test_fixture::{local_order_of, stock_of, PersistedStockHandler};
+    use super::*;
+    use shared::model::order::LocalOrder;
+
+    #[test]
+    fn test_get_products_quantity_in_locals() {
+        let mut global_stock = StockHandler::new();
+        let mut local_shop_stock1 = HashMap::new();
+        local_shop_stock1.insert(
+            "product1".to_string(),
+            Product::new("product1".to_string(), 10),
+        );
+        local_shop_stock1.insert(
+            "product2".to_string(),
+            Product::new("product2".to_string(), 20),
+        );
+        global_stock.add_local_shop_stock(1, local_shop_stock1.clone());
+        let mut local_shop_stock2 = HashMap::new();
+        local_shop_stock2.insert(
+            "product1".to_string(),
+            Product::new("product1".to_string(), 30),
+        );
+        local_shop_stock2.insert(
+            "product2".to_string(),
+            Product::new("product2".to_string(), 40),
+        );
+        global_stock.add_local_shop_stock(2, local_shop_stock2.clone());
+        let mut products_quantity_in_locals = HashMap::new();
+        products_quantity_in_locals.insert(1, 10);
+        products_quantity_in_locals.insert(2, 30);
+        assert_eq!(
+            global_stock.get_quantity_of_product_from_all_stocks("product1".to_string()),
+            products_quantity_in_locals
+        );
+    }
+
+    #[test]
+    fn test_get_several_products_quantity_in_locals_at_once() {
+        let mut global_stock = StockHandler::new();
+        let mut local_shop_stock1 = HashMap::new();
+        local_shop_stock1.insert(
+            "product1".to_string(),
+            Product::new("product1".to_string(), 10),
+        );
+        local_shop_stock1.insert(
+            "product2".to_string(),
+            Product::new("product2".to_string(), 20),
+        );
+        global_stock.add_local_shop_stock(1, local_shop_stock1);
+        let mut local_shop_stock2 = HashMap::new();
+        local_shop_stock2.insert(
+            "product1".to_string(),
+            Product::new("product1".to_string(), 30),
+        );
+        global_stock.add_local_shop_stock(2, local_shop_stock2);
+        assert_eq!(
+            global_stock.get_quantity_of_products_from_all_stocks(&[
+                "product1".to_string(),
+                "product2".to_string()
+            ]),
+            HashMap::from([
+                ("product1".to_string(), HashMap::from([(1, 10), (2, 30)])),
+                ("product2".to_string(), HashMap::from([(1, 20), (2, 0)])),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_stock_is_rebuilt_from_wal_after_restart() {
+        let persisted = PersistedStockHandler::new("restart");
+
+        let (mut stock_handler, _) = persisted.recover();
+        stock_handler
+            .process_post_to_stock_from_local(1, stock_of("product1", 10), 1, None)
+            .unwrap();
+        stock_handler
+            .process_order_result_in_stock(local_order_of(1, "product1", 4), 2, None)
+            .unwrap();
+        stock_handler.record_issued_local_id(1, 100).unwrap();
+        drop(stock_handler);
+
+        let (restarted, local_registry) = persisted.recover();
+        assert_eq!(local_registry.last_local_id(), 1);
+        assert_eq!(
+            local_registry.get(1).map(|local| local.registered_at),
+            Some(100)
+        );
+        assert_eq!(
+            restarted.get_quantity_of_product_from_all_stocks("product1".to_string()),
+            HashMap::from([(1, 6)])
+        );
+    }
+
+    #[test]
+    fn test_only_log_tail_after_snapshot_is_replayed() {
+        let persisted = PersistedStockHandler::new("snapshot");
+
+        let (mut stock_handler, _) = persisted.recover();
+        stock_handler
+            .process_post_to_stock_from_local(1, stock_of("product1", 10), 1, None)
+            .unwrap();
+        stock_handler.record_issued_local_id(1, 100).unwrap();
+        let mut local_registry = LocalRegistry::new();
+        local_registry.register(1, 100);
+        stock_handler.take_snapshot(local_registry).unwrap();
+        assert!(stock_handler
+            .wal
+            .as_ref()
+            .unwrap()
+            .read_records()
+            .unwrap()
+            .is_empty());
+        stock_handler
+            .process_order_result_in_stock(local_order_of(1, "product1", 3), 2, None)
+            .unwrap();
+        drop(stock_handler);
+
+        let (restarted, local_registry) = persisted.recover();
+        assert_eq!(local_registry.last_local_id(), 1);
+        assert_eq!(
+            restarted.get_quantity_of_product_from_all_stocks("product1".to_string()),
+            HashMap::from([(1, 7)])
+        );
+    }
+
+    #[test]
+    fn test_records_already_in_snapshot_are_not_replayed_twice() {
+        let persisted = PersistedStockHandler::new("crash_before_truncate");
+
+        let (mut stock_handler, _) = persisted.recover();
+        stock_handler
+            .process_post_to_stock_from_local(1, stock_of("product1", 10), 1, None)
+            .unwrap();
+        stock_handler
+            .process_order_result_in_stock(local_order_of(1, "product1", 2), 2, None)
+            .unwrap();
+        // Simulates a crash right after saving the snapshot, before the log is truncated.
+        stock_handler
+            .current_snapshot(LocalRegistry::new())
+            .save(&persisted.snapshot_path)
+            .unwrap();
+        drop(stock_handler);
+
+        let (restarted, local_registry) = persisted.recover();
+        assert_eq!(local_registry.last_local_id(), 1);
+        assert_eq!(
+            restarted.get_quantity_of_product_from_all_stocks("product1".to_string()),
+            HashMap::from([(1, 8)])
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_failed_order_results_report_why_they_were_rejected() {
+        let stock_handler = StockHandler::new().start();
+        let epoch = stock_handler
+            .send(AcquireLeaderEpoch {})
+            .await
+            .unwrap()
+            .unwrap();
+        let mut local_shop_stock = HashMap::new();
+        local_shop_stock.insert(
+            "product1".to_string(),
+            Product::new("product1".to_string(), 1),
+        );
+        stock_handler
+            .send(PostStockFromLocal {
+                local_id: 1,
+                stock: local_shop_stock,
+                version: 1,
+                ecommerce_id: None,
+                epoch,
+            })
+            .await
+            .unwrap()
+            .unwrap();
+
+        let mut order = Order::Local(LocalOrder::new(vec![Product::new(
+            "product1".to_string(),
+            2,
+        )]));
+        order.set_local_id(1);
+        order.set_id(1);
+        let result = stock_handler
+            .send(PostOrderResult {
+                order,
+                stock_version: 2,
+                ecommerce_id: None,
+                epoch,
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            result.map_err(|err| err.kind),
+            Err(DBErrorKind::OrderResultRejected)
+        );
+    }
+
+    #[test]
+    fn test_stock_updates_are_applied_in_version_order() {
+        let mut stock_handler = StockHandler::new();
+        let mut local_shop_stock = HashMap::new();
+        local_shop_stock.insert(
+            "product1".to_string(),
+            Product::new("product1".to_string(), 10),
+        );
+        stock_handler
+            .process_post_to_stock_from_local(1, local_shop_stock, 5, None)
+            .unwrap();
+        stock_handler
+            .process_stock_delta(1, "product1".to_string(), 4, 7, None)
+            .unwrap();
+        assert_eq!(
+            stock_handler.get_quantity_of_product_from_all_stocks("product1".to_string()),
+            HashMap::from([(1, 10)])
+        );
+        let mut order = Order::Local(LocalOrder::new(vec![Product::new(
+            "product1".to_string(),
+            3,
+        )]));
+        order.set_local_id(1);
+        order.set_id(1);
+        stock_handler
+            .process_order_result_in_stock(order, 6, None)
+            .unwrap();
+        assert_eq!(
+            stock_handler.get_quantity_of_product_from_all_stocks("product1".to_string()),
+            HashMap::from([(1, 11)])
+        );
+        assert_eq!(
+            stock_handler
+                .process_stock_delta(1, "product1".to_string(), -1, 7, None)
+                .map_err(|err| err.kind),
+            Err(DBErrorKind::StaleUpdate)
+        );
+
+        // A whole stock supersedes the updates waiting for the versions before it.
+        stock_handler
+            .process_stock_delta(1, "product1".to_string(), 1, 9, None)
+            .unwrap();
+        let mut local_shop_stock = HashMap::new();
+        local_shop_stock.insert(
+            "product1".to_string(),
+            Product::new("product1".to_string(), 20),
+        );
+        stock_handler
+            .process_post_to_stock_from_local(1, local_shop_stock, 9, None)
+            .unwrap();
+        stock_handler
+            .process_stock_delta(1, "product1".to_string(), -2, 10, None)
+            .unwrap();
+        assert_eq!(
+            stock_handler.get_quantity_of_product_from_all_stocks("product1".to_string()),
+            HashMap::from([(1, 18)])
+        );
+        assert_eq!(stock_handler.stock_versions.current(1), 10);
+    }
+
+    #[test]
+    fn test_versions_reached_by_rejected_order_results_survive_restart() {
+        let persisted = PersistedStockHandler::new("skipped_versions");
+
+        let (mut stock_handler, _) = persisted.recover();
+        stock_handler
+            .process_post_to_stock_from_local(1, stock_of("product1", 1), 1, None)
+            .unwrap();
+        assert_eq!(
+            stock_handler
+                .process_order_result_in_stock(local_order_of(1, "product1", 2), 2, None)
+                .map_err(|err| err.kind),
+            Err(DBErrorKind::OrderResultRejected)
+        );
+        drop(stock_handler);
+
+        let (mut restarted, _) = persisted.recover();
+        assert_eq!(restarted.stock_versions.current(1), 2);
+        restarted
+            .process_stock_delta(1, "product1".to_string(), 3, 3, None)
+            .unwrap();
+        assert_eq!(
+            restarted.get_quantity_of_product_from_all_stocks("product1".to_string()),
+            HashMap::from([(1, 4)])
+        );
+    }
+
+    #[test]
+    fn test_resent_order_results_are_not_applied_twice_even_after_restart() {
+        let persisted = PersistedStockHandler::new("duplicates");
+
+        let order = local_order_of(1, "product1", 4);
+
+        let (mut stock_handler, _) = persisted.recover();
+        stock_handler
+            .process_post_to_stock_from_local(1, stock_of("product1", 10), 1, None)
+            .unwrap();
+        assert_eq!(
+            stock_handler.process_order_result_in_stock(order.clone(), 2, None),
+            Ok(OrderResultOutcome::Accepted)
+        );
+        assert_eq!(
+            stock_handler.process_order_result_in_stock(order.clone(), 2, None),
+            Ok(OrderResultOutcome::AlreadyApplied)
+        );
+        // A whole stock that includes the order does not make a resend of it apply again.
+        stock_handler
+            .process_post_to_stock_from_local(1, stock_of("product1", 6), 3, None)
+            .unwrap();
+        drop(stock_handler);
+
+        let (mut restarted, _) = persisted.recover();
+        assert_eq!(
+            restarted.process_order_result_in_stock(order, 2, None),
+            Ok(OrderResultOutcome::AlreadyApplied)
+        );
+        assert_eq!(
+            restarted.get_quantity_of_product_from_all_stocks("product1".to_string()),
+            HashMap::from([(1, 6)])
+        );
+
+        let mut order_without_id = local_order_of(1, "product1", 1);
+        if let Order::Local(local_order) = &mut order_without_id {
+            local_order.id = None;
+        }
+        assert_eq!(
+            restarted
+                .process_order_result_in_stock(order_without_id, 4, None)
+                .map_err(|err| err.kind),
+            Err(DBErrorKind::OrderResultRejected)
+        );
+    }
+
+    #[test]
+    fn test_forgotten_locals_are_left_out_after_restart() {
+        let persisted = PersistedStockHandler::new("forget");
+
+        let (mut stock_handler, _) = persisted.recover();
+        for local_id in [2, 1] {
+            let mut local_shop_stock = stock_of("product2", 2);
+            local_shop_stock.extend(stock_of("product1", 1));
+            stock_handler
+                .process_post_to_stock_from_local(local_id, local_shop_stock, 1, None)
+                .unwrap();
+        }
+        stock_handler
+            .reserve_stock(2, 1, vec![Product::new("product1".to_string(), 1)])
+            .unwrap();
+        assert_eq!(
+            stock_handler.describe_stock(None),
+            Ok(vec![
+                "Local 1: [product1: 1, product2: 2]".to_string(),
+                "Local 2: [product1: 1, product2: 2]".to_string()
+            ])
+        );
+
+        stock_handler.forget_local(2).unwrap();
+        assert!(stock_handler.forget_local(2).is_err());
+        drop(stock_handler);
+
+        let (restarted, _) = persisted.recover();
+        assert!(restarted.describe_stock(Some(2)).is_err());
+        assert_eq!(
+            restarted.get_quantity_of_product_from_all_stocks("product1".to_string()),
+            HashMap::from([(1, 1)])
+        );
+        assert_eq!(restarted.reservations.reserved_quantity(2, "product1"), 0);
+    }
+
+    #[test]
+    fn test_stock_history_records_causes_and_survives_restart() {
+        let persisted = PersistedStockHandler::new("history");
+
+        let (mut stock_handler, _) = persisted.recover();
+        stock_handler
+            .process_post_to_stock_from_local(1, stock_of("product1", 10), 1, Some(3))
+            .unwrap();
+        stock_handler
+            .process_order_result_in_stock(local_order_of(1, "product1", 4), 2, Some(5))
+            .unwrap();
+        drop(stock_handler);
+
+        let (restarted, _) = persisted.recover();
+        let changes = restarted.get_stock_history(Some(1), Some("product1"), 0, u64::MAX);
+        assert_eq!(
+            changes
+                .iter()
+                .map(|change| (change.delta, change.cause.clone(), change.ecommerce_id))
+                .collect::<Vec<_>>(),
+            vec![
+                (10, StockChangeCause::StockUpload { version: 1 }, Some(3)),
+                (
+                    -4,
+                    StockChangeCause::OrderResult { stock_version: 2 },
+                    Some(5)
+                ),
+            ]
+        );
+        assert_eq!(
+            restarted.get_stock_history(Some(2), None, 0, u64::MAX),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_order_results_with_products_not_in_the_catalog_are_rejected() {
+        let mut stock_handler = StockHandler::new();
+        stock_handler.set_catalog(Catalog::load("./catalog/catalog.txt").unwrap());
+        let mut local_shop_stock = HashMap::new();
+        for product_name in ["Product1", "Unlisted"] {
+            local_shop_stock.insert(
+                product_name.to_string(),
+                Product::new(product_name.to_string(), 10),
+            );
+        }
+        stock_handler
+            .process_post_to_stock_from_local(1, local_shop_stock, 1, None)
+            .unwrap();
+
+        let mut unlisted_order = Order::Local(LocalOrder::new(vec![Product::new(
+            "Unlisted".to_string(),
+            1,
+        )]));
+        unlisted_order.set_local_id(1);
+        unlisted_order.set_id(1);
+        assert_eq!(
+            stock_handler
+                .process_order_result_in_stock(unlisted_order, 2, None)
+                .map_err(|err| err.kind),
+            Err(DBErrorKind::OrderResultRejected)
+        );
+        let mut listed_order = Order::Local(LocalOrder::new(vec![Product::new(
+            "Product1".to_string(),
+            1,
+        )]));
+        listed_order.set_local_id(1);
+        listed_order.set_id(2);
+        assert_eq!(
+            stock_handler.process_order_result_in_stock(listed_order, 3, None),
+            Ok(OrderResultOutcome::Accepted)
+        );
+        assert_eq!(
+            stock_handler
+                .get_catalog_product("Unlisted")
+                .map_err(|err| err.kind),
+            Err(DBErrorKind::UnknownProduct)
+        );
+        assert_eq!(stock_handler.get_catalog(Some("clothing")).len(), 2);
+    }
+
+    #[test]
+    fn test_applied_order_results_are_kept_and_survive_restart() {
+        let persisted = PersistedStockHandler::new("order_log");
+        let first_order = local_order_of(3, "product1", 4);
+        let second_order = local_order_of(3, "product1", 2);
+
+        let (mut stock_handler, _) = persisted.recover();
+        stock_handler
+            .process_post_to_stock_from_local(3, stock_of("product1", 10), 1, Some(1))
+            .unwrap();
+        stock_handler
+            .process_order_result_in_stock(first_order.clone(), 2, Some(1))
+            .unwrap();
+        stock_handler.take_snapshot(LocalRegistry::new()).unwrap();
+        stock_handler
+            .process_order_result_in_stock(second_order.clone(), 3, Some(2))
+            .unwrap();
+        // A resent order result is not kept twice.
+        stock_handler
+            .process_order_result_in_stock(second_order.clone(), 3, Some(2))
+            .unwrap();
+        drop(stock_handler);
+
+        let (restarted, _) = persisted.recover();
+        let order_results = restarted.get_order_results(Some(3), None, None, 0, u64::MAX);
+        assert_eq!(
+            order_results
+                .iter()
+                .map(|order_result| order_result.get_product())
+                .collect::<Vec<_>>(),
+            vec![first_order, second_order]
+        );
+        assert_eq!(
+            restarted
+                .get_order_results(None, Some(2), Some("product1"), 0, u64::MAX)
+                .len(),
+            1
+        );
+        assert_eq!(
+            restarted
+                .get_order_results(Some(4), None, None, 0, u64::MAX)
+                .len(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_stock_is_rebuilt_into_a_file_store_after_restart() {
+        let persisted = PersistedStockHandler::new("file_store");
+
+        let mut stock_handler = persisted.recover_into_file_store();
+        stock_handler
+            .process_post_to_stock_from_local(1, stock_of("product1", 10), 1, None)
+            .unwrap();
+        stock_handler
+            .process_stock_delta(1, "product1".to_string(), 3, 2, None)
+            .unwrap();
+        stock_handler
+            .process_order_result_in_stock(local_order_of(1, "product1", 4), 3, None)
+            .unwrap();
+        drop(stock_handler);
+        assert_eq!(persisted.file_store().quantity(1, "product1"), Some(9));
+
+        // The changes on the log are not applied twice on top of the stock already in the store.
+        let restarted = persisted.recover_into_file_store();
+        assert_eq!(
+            restarted.get_local_stock(1),
+            Ok(HashMap::from([("product1".to_string(), 9)]))
+        );
+    }
+}
@@ -0,0 +1,148 @@
+//! The available quantities of the products are kept by several `StockPartition` actors, which
+//! answer the queries for them. Every change is sent to the partition of its product once the
+//! message that caused it is handled.
+
+use actix::prelude::*;
+
+use super::StockHandler;
+use crate::db::stock_partition::{StockPartition, StockPartitions};
+
+impl StockHandler {
+    /// Sets the partitions that answer the queries for the quantity of products in every local,
+    /// sending them the whole stock.
+    pub fn set_stock_partitions(&mut self, partitions: Vec<Addr<StockPartition>>) {
+        self.stock_partitions = StockPartitions::new(partitions);
+        self.publish_all_to_partitions();
+    }
+
+    /// Sends the available quantity of each product changed since the last call to its partition.
+    pub(super) fn publish_to_partitions(&mut self) {
+        if self.stock_partitions.is_empty() {
+            return;
+        }
+        let quantities = self
+            .stock_partitions
+            .take_changed()
+            .into_iter()
+            .map(|(local_id, product_name)| {
+                let quantity = self.available_quantity(local_id, &product_name);
+                (local_id, product_name, quantity)
+            })
+            .collect();
+        self.stock_partitions
+            .publish(self.stock_store.local_ids(), quantities, false);
+    }
+
+    /// Sends the available quantity of every product to its partition, replacing what they held.
+    pub(super) fn publish_all_to_partitions(&mut self) {
+        if self.stock_partitions.is_empty() {
+            return;
+        }
+        self.stock_partitions.take_changed();
+        let quantities = self
+            .stock_store
+            .all_stock()
+            .into_iter()
+            .flat_map(|(local_id, local_stock)| {
+                local_stock
+                    .into_keys()
+                    .map(move |product_name| (local_id, product_name))
+            })
+            .map(|(local_id, product_name)| {
+                let quantity = self.available_quantity(local_id, &product_name);
+                (local_id, product_name, quantity)
+            })
+            .collect();
+        self.stock_partitions
+            .publish(self.stock_store.local_ids(), quantities, true);
+    }
+}
+
+// ====================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::super::{AcquireLeaderEpoch, ForgetLocal, PostStockFromLocal, ReserveStock};
+    use super::*;
+    use crate::db::stock_partition::{partition_of, GetAvailableQuantities};
+    use shared::model::stock_product::Product;
+    use std::collections::HashMap;
+
+    #[actix_rt::test]
+    async fn test_stock_partitions_are_kept_up_to_date_with_the_available_quantities() {
+        let partitions: Vec<Addr<StockPartition>> =
+            (0..2).map(|_| StockPartition::new().start()).collect();
+        let mut stock_handler = StockHandler::new();
+        stock_handler
+            .seed_stock(HashMap::from([(
+                2,
+                HashMap::from([(
+                    "product2".to_string(),
+                    Product::new("product2".to_string(), 3),
+                )]),
+            )]))
+            .unwrap();
+        stock_handler.set_stock_partitions(partitions.clone());
+        let stock_handler = stock_handler.start();
+        let available_quantities = |product_name: &str| {
+            let partition = &partitions[partition_of(product_name, partitions.len())];
+            let request = partition.send(GetAvailableQuantities {
+                product_names: vec![product_name.to_string()],
+            });
+            let product_name = product_name.to_string();
+            async move { request.await.unwrap().remove(&product_name).unwrap() }
+        };
+        assert_eq!(
+            available_quantities("product2").await,
+            HashMap::from([(2, 3)])
+        );
+
+        let epoch = stock_handler
+            .send(AcquireLeaderEpoch {})
+            .await
+            .unwrap()
+            .unwrap();
+        stock_handler
+            .send(PostStockFromLocal {
+                local_id: 1,
+                stock: HashMap::from([(
+                    "product1".to_string(),
+                    Product::new("product1".to_string(), 5),
+                )]),
+                version: 1,
+                ecommerce_id: None,
+                epoch,
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        stock_handler
+            .send(ReserveStock {
+                local_id: 1,
+                order_id: 1,
+                products: vec![Product::new("product1".to_string(), 2)],
+                epoch,
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            available_quantities("product1").await,
+            HashMap::from([(1, 3), (2, 0)])
+        );
+        assert_eq!(
+            available_quantities("product2").await,
+            HashMap::from([(1, 0), (2, 3)])
+        );
+
+        stock_handler
+            .send(ForgetLocal { local_id: 2 })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            available_quantities("product2").await,
+            HashMap::from([(1, 0)])
+        );
+    }
+}
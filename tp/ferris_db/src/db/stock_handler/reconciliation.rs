@@ -0,0 +1,212 @@
+//! The stock of a local can be reconciled with the copy of the database: the products whose
+//! quantities differ are reported, and depending on the configured policy the copy is replaced
+//! with the local's stock, the local is told how to correct its own, or nothing else is done.
+
+use std::collections::HashMap;
+
+use actix::prelude::*;
+use shared::{
+    communication::db_response::DBErrorKind,
+    model::{
+        reconciliation::ReconciliationPolicy, stock_change::StockChangeCause,
+        stock_product::Product,
+    },
+};
+use tracing::{info, warn};
+
+use super::StockHandler;
+use crate::db::{
+    clock::current_timestamp,
+    db_error::DBError,
+    reconciliation::{corrections_for, find_discrepancies, StockReconciliation},
+    stock_history::ChangeOrigin,
+    wal::WalEntry,
+};
+
+impl StockHandler {
+    pub fn set_reconciliation_policy(&mut self, reconciliation_policy: ReconciliationPolicy) {
+        self.reconciliation_policy = reconciliation_policy;
+    }
+
+    /// Compares the stock of a local as of the given version with the copy of the database, and
+    /// applies the reconciliation policy to the products whose quantities differ.
+    ///
+    /// The copy is only replaced if it is not ahead of the local's stock, and corrections are
+    /// only given if both are at the same version, since otherwise the differences may be changes
+    /// that are still on their way.
+    pub fn reconcile_stock(
+        &mut self,
+        local_id: u16,
+        stock: HashMap<String, Product>,
+        version: u64,
+        ecommerce_id: Option<u16>,
+    ) -> Result<StockReconciliation, DBError> {
+        let db_version = self.stock_versions.current(local_id);
+        let discrepancies = find_discrepancies(
+            &stock,
+            &self.stock_store.local_stock(local_id).unwrap_or_default(),
+        );
+        let mut reconciliation = StockReconciliation {
+            policy: self.reconciliation_policy,
+            discrepancies,
+            corrections: None,
+        };
+        if reconciliation.discrepancies.is_empty() {
+            return Ok(reconciliation);
+        }
+        for discrepancy in &reconciliation.discrepancies {
+            warn!(
+                "[StockHandler] Local {} has {} of product {} but the database has {}",
+                local_id,
+                discrepancy.local_quantity,
+                discrepancy.product_name,
+                discrepancy.db_quantity
+            );
+        }
+        match self.reconciliation_policy {
+            ReconciliationPolicy::TrustLocal if version >= db_version => {
+                let timestamp = current_timestamp();
+                self.append_to_wal(&WalEntry::StockReconciled {
+                    local_id,
+                    stock: stock.clone(),
+                    version,
+                    timestamp,
+                    ecommerce_id,
+                })
+                .map_err(|err| DBError::new(DBErrorKind::Storage, err))?;
+                let origin = ChangeOrigin {
+                    cause: StockChangeCause::Reconciliation { version },
+                    timestamp,
+                    ecommerce_id,
+                };
+                self.apply_stock_upload(local_id, stock, version, &origin);
+                self.apply_buffered_updates(local_id);
+            }
+            ReconciliationPolicy::TrustDatabase if version == db_version => {
+                reconciliation.corrections = Some(corrections_for(&reconciliation.discrepancies));
+            }
+            ReconciliationPolicy::FlagOnly => {}
+            _ => info!(
+                "[StockHandler] Stock of local {} is at version {} but the database's is at {}, only reporting the differences",
+                local_id, version, db_version
+            ),
+        }
+        Ok(reconciliation)
+    }
+}
+
+// ====================================================================
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<StockReconciliation, DBError>")]
+pub struct ReconcileStock {
+    pub local_id: u16,
+    pub stock: HashMap<String, Product>,
+    pub version: u64,
+    pub ecommerce_id: Option<u16>,
+    pub epoch: u64,
+}
+
+impl Handler<ReconcileStock> for StockHandler {
+    type Result = Result<StockReconciliation, DBError>;
+
+    fn handle(&mut self, msg: ReconcileStock, _: &mut Self::Context) -> Self::Result {
+        self.check_leader_epoch(msg.epoch)?;
+        let result = self.reconcile_stock(msg.local_id, msg.stock, msg.version, msg.ecommerce_id);
+        self.publish_changes();
+        result
+    }
+}
+
+// ====================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_fixture::{stock_of, PersistedStockHandler};
+    use super::*;
+    use shared::model::{reconciliation::StockDiscrepancy, stock_product::Product};
+
+    fn local_stock_with(quantity: i32) -> HashMap<String, Product> {
+        let mut local_shop_stock = HashMap::new();
+        local_shop_stock.insert(
+            "product1".to_string(),
+            Product::new("product1".to_string(), quantity),
+        );
+        local_shop_stock
+    }
+
+    #[test]
+    fn test_flagged_discrepancies_are_only_reported() {
+        let mut stock_handler = StockHandler::new();
+        stock_handler
+            .process_post_to_stock_from_local(1, local_stock_with(6), 2, None)
+            .unwrap();
+
+        let reconciliation = stock_handler
+            .reconcile_stock(1, local_stock_with(5), 2, None)
+            .unwrap();
+        assert_eq!(reconciliation.policy, ReconciliationPolicy::FlagOnly);
+        assert_eq!(
+            reconciliation.discrepancies,
+            vec![StockDiscrepancy {
+                product_name: "product1".to_string(),
+                local_quantity: 5,
+                db_quantity: 6,
+            }]
+        );
+        assert_eq!(reconciliation.corrections, None);
+        assert_eq!(
+            stock_handler.get_local_stock(1),
+            Ok(HashMap::from([("product1".to_string(), 6)]))
+        );
+    }
+
+    #[test]
+    fn test_corrections_are_only_given_to_locals_at_the_version_of_the_db() {
+        let mut stock_handler = StockHandler::new();
+        stock_handler.set_reconciliation_policy(ReconciliationPolicy::TrustDatabase);
+        stock_handler
+            .process_post_to_stock_from_local(1, local_stock_with(6), 2, None)
+            .unwrap();
+
+        let reconciliation = stock_handler
+            .reconcile_stock(1, local_stock_with(5), 2, None)
+            .unwrap();
+        assert_eq!(
+            reconciliation.corrections,
+            Some(HashMap::from([("product1".to_string(), 1)]))
+        );
+        // The local is behind the database, so the difference may be a change on its way.
+        let reconciliation = stock_handler
+            .reconcile_stock(1, local_stock_with(5), 1, None)
+            .unwrap();
+        assert_eq!(reconciliation.corrections, None);
+    }
+
+    #[test]
+    fn test_stock_trusted_from_the_local_survives_restart() {
+        let persisted = PersistedStockHandler::new("reconciliation");
+
+        let (mut stock_handler, _) = persisted.recover();
+        stock_handler.set_reconciliation_policy(ReconciliationPolicy::TrustLocal);
+        stock_handler
+            .process_post_to_stock_from_local(1, stock_of("product1", 6), 2, None)
+            .unwrap();
+        let reconciliation = stock_handler
+            .reconcile_stock(1, stock_of("product1", 5), 2, None)
+            .unwrap();
+        assert_eq!(reconciliation.discrepancies.len(), 1);
+        assert!(stock_handler
+            .reconcile_stock(1, stock_of("product1", 5), 2, None)
+            .unwrap()
+            .discrepancies
+            .is_empty());
+        drop(stock_handler);
+
+        let (restarted, _) = persisted.recover();
+        assert_eq!(
+            restarted.get_local_stock(1),
+            Ok(HashMap::from([("product1".to_string(), 5)]))
+        );
+    }
+}
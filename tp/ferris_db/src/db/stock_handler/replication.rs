@@ -0,0 +1,218 @@
+//! Every record appended to the write-ahead log is also streamed to the registered backup
+//! databases, which append and apply it in turn.
+
+use actix::prelude::*;
+use tracing::{error, info, warn};
+
+use super::StockHandler;
+use crate::db::{
+    db_middleman::{DBMiddleman, SendOnlineMsg},
+    local_registry::LocalRegistry,
+    replication::ReplicationMessage,
+    snapshot::Snapshot,
+    wal::WalRecord,
+};
+
+impl StockHandler {
+    /// Streams a record to every backup, forgetting the ones that are no longer reachable.
+    pub(super) fn replicate(&mut self, record: WalRecord) {
+        if self.backups.is_empty() {
+            return;
+        }
+        let msg_to_send = match (ReplicationMessage::Record { record }).to_string() {
+            Ok(msg_to_send) => msg_to_send,
+            Err(err) => {
+                error!(
+                    "[StockHandler] Error serializing replicated record: {}",
+                    err
+                );
+                return;
+            }
+        };
+        self.backups.retain(|backup| {
+            if !backup.connected() {
+                warn!("[StockHandler] Backup database lost, it will no longer be replicated to");
+                return false;
+            }
+            // Sent even if the mailbox is full, since a lost record would leave the backup behind for good.
+            backup.do_send(SendOnlineMsg {
+                msg_to_send: msg_to_send.clone(),
+            });
+            true
+        });
+    }
+
+    /// Sends the whole current state to a new backup, and streams every following record to it.
+    pub fn add_backup(
+        &mut self,
+        backup: Addr<DBMiddleman>,
+        local_registry: LocalRegistry,
+    ) -> Result<(), String> {
+        let msg_to_send = ReplicationMessage::FullState {
+            snapshot: Box::new(self.current_snapshot(local_registry)),
+        }
+        .to_string()?;
+        backup.do_send(SendOnlineMsg { msg_to_send });
+        self.backups.push(backup);
+        info!("[StockHandler] New backup database registered");
+        Ok(())
+    }
+
+    /// Replaces the whole state with the one received from the upstream database.
+    pub fn restore_from_upstream(&mut self, snapshot: Snapshot) -> Result<(), String> {
+        if let (Some(wal), Some(snapshot_path)) = (self.wal.as_mut(), self.snapshot_path.as_ref()) {
+            snapshot.save(snapshot_path)?;
+            wal.reset(snapshot.last_lsn)?;
+        }
+        self.last_snapshot_lsn = snapshot.last_lsn;
+        self.stock_store.replace_all(snapshot.global_stock);
+        self.reservations = snapshot.reservations;
+        self.stock_versions = snapshot.stock_versions;
+        self.applied_orders = snapshot.applied_orders;
+        self.stock_history = snapshot.stock_history;
+        self.order_log = snapshot.order_log;
+        self.leader_epoch = snapshot.leader_epoch;
+        self.publish_all_to_partitions();
+        Ok(())
+    }
+
+    /// Appends and applies a record received from the upstream database, which must come right
+    /// after the last one appended so that both logs stay identical.
+    pub fn apply_replicated_record(&mut self, record: WalRecord) -> Result<(), String> {
+        if let Some(wal) = self.wal.as_ref() {
+            if record.lsn != wal.last_lsn() + 1 {
+                return Err(format!(
+                    "Replicated record {} does not follow the last one appended ({})",
+                    record.lsn,
+                    wal.last_lsn()
+                ));
+            }
+        }
+        self.append_to_wal(&record.entry)?;
+        self.apply_entry(record.entry)
+    }
+}
+
+// ====================================================================
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct AddBackup {
+    pub backup_db_middleman: Addr<DBMiddleman>,
+    pub local_registry: LocalRegistry,
+}
+
+impl Handler<AddBackup> for StockHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: AddBackup, _: &mut Self::Context) -> Self::Result {
+        self.add_backup(msg.backup_db_middleman, msg.local_registry)
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct RestoreFromUpstream {
+    pub snapshot: Snapshot,
+}
+
+impl Handler<RestoreFromUpstream> for StockHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: RestoreFromUpstream, _: &mut Self::Context) -> Self::Result {
+        self.restore_from_upstream(msg.snapshot)
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct ApplyReplicatedRecord {
+    pub record: WalRecord,
+}
+
+impl Handler<ApplyReplicatedRecord> for StockHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: ApplyReplicatedRecord, _: &mut Self::Context) -> Self::Result {
+        let result = self.apply_replicated_record(msg.record);
+        self.publish_changes();
+        result
+    }
+}
+
+// ====================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_fixture::{stock_of, PersistedStockHandler};
+    use super::*;
+    use crate::db::wal::WalEntry;
+    use shared::model::stock_product::Product;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_replicated_records_must_follow_the_last_one() {
+        let persisted = PersistedStockHandler::new("replication");
+
+        let (mut backup, _) = persisted.recover();
+        let entry = WalEntry::PostStockFromLocal {
+            local_id: 1,
+            stock: stock_of("product1", 10),
+            version: 1,
+            timestamp: 0,
+            ecommerce_id: None,
+        };
+        assert!(backup
+            .apply_replicated_record(WalRecord {
+                lsn: 2,
+                entry: entry.clone()
+            })
+            .is_err());
+        backup
+            .apply_replicated_record(WalRecord { lsn: 1, entry })
+            .unwrap();
+        assert_eq!(
+            backup.get_quantity_of_product_from_all_stocks("product1".to_string()),
+            HashMap::from([(1, 10)])
+        );
+        assert_eq!(backup.wal.as_ref().unwrap().last_lsn(), 1);
+    }
+
+    #[test]
+    fn test_stock_changed_by_records_lost_before_a_takeover_is_restored_by_the_local() {
+        let mut backup = StockHandler::new();
+        backup
+            .apply_replicated_record(WalRecord {
+                lsn: 1,
+                entry: WalEntry::PostStockFromLocal {
+                    local_id: 1,
+                    stock: HashMap::from([(
+                        "product1".to_string(),
+                        Product::new("product1".to_string(), 10),
+                    )]),
+                    version: 1,
+                    timestamp: 0,
+                    ecommerce_id: None,
+                },
+            })
+            .unwrap();
+        // The record of version 2, taking the stock to 7, never reached the backup before it took over.
+        backup
+            .process_stock_delta(1, "product1".to_string(), -1, 3, None)
+            .unwrap();
+        assert_eq!(backup.available_quantity(1, "product1"), 10);
+
+        backup
+            .process_post_to_stock_from_local(
+                1,
+                HashMap::from([(
+                    "product1".to_string(),
+                    Product::new("product1".to_string(), 6),
+                )]),
+                3,
+                None,
+            )
+            .unwrap();
+        assert_eq!(backup.available_quantity(1, "product1"), 6);
+    }
+}
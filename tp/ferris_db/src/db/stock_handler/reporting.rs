@@ -0,0 +1,178 @@
+//! Reporting queries (the known locals, the stock of a local, the total of each product and the
+//! products with the most units available) are answered from memory, without logging anything.
+
+use std::collections::HashMap;
+
+use actix::prelude::*;
+use shared::communication::db_response::DBErrorKind;
+
+use super::StockHandler;
+use crate::db::db_error::DBError;
+
+impl StockHandler {
+    pub fn get_local_ids(&self) -> Vec<u16> {
+        self.stock_store.local_ids()
+    }
+
+    pub fn get_local_stock(&self, local_shop_id: u16) -> Result<HashMap<String, i32>, DBError> {
+        let local_shop_stock = self.stock_store.local_stock(local_shop_id).ok_or_else(|| {
+            DBError::new(
+                DBErrorKind::UnknownLocal,
+                format!("Local shop {} not found in global stock", local_shop_id),
+            )
+        })?;
+        Ok(local_shop_stock
+            .into_values()
+            .map(|product| (product.get_name(), product.get_quantity()))
+            .collect())
+    }
+
+    pub fn get_total_quantity_by_product(&self) -> HashMap<String, i32> {
+        let mut total_quantity_by_product = HashMap::new();
+        for product in self
+            .stock_store
+            .all_stock()
+            .into_values()
+            .flat_map(|stock| stock.into_values())
+        {
+            *total_quantity_by_product
+                .entry(product.get_name())
+                .or_insert(0) += product.get_quantity();
+        }
+        total_quantity_by_product
+    }
+
+    /// Returns the `limit` products with the most units not held by any reservation across all
+    /// locals, from most to fewest, and by name when they have the same units.
+    pub fn get_top_products(&self, limit: usize) -> Vec<(String, i32)> {
+        let mut available_by_product: HashMap<String, i32> = HashMap::new();
+        for (local_shop_id, local_shop_stock) in self.stock_store.all_stock() {
+            for product_name in local_shop_stock.keys() {
+                *available_by_product
+                    .entry(product_name.clone())
+                    .or_insert(0) += self.available_quantity(local_shop_id, product_name);
+            }
+        }
+        let mut top_products: Vec<(String, i32)> = available_by_product.into_iter().collect();
+        top_products.sort_by(|(name_a, units_a), (name_b, units_b)| {
+            units_b.cmp(units_a).then_with(|| name_a.cmp(name_b))
+        });
+        top_products.truncate(limit);
+        top_products
+    }
+}
+
+// ====================================================================
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Vec<u16>")]
+pub struct GetLocals {}
+
+impl Handler<GetLocals> for StockHandler {
+    type Result = MessageResult<GetLocals>;
+
+    fn handle(&mut self, _: GetLocals, _: &mut Self::Context) -> Self::Result {
+        MessageResult(self.get_local_ids())
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<HashMap<String, i32>, DBError>")]
+pub struct GetLocalStock {
+    pub local_id: u16,
+}
+
+impl Handler<GetLocalStock> for StockHandler {
+    type Result = Result<HashMap<String, i32>, DBError>;
+
+    fn handle(&mut self, msg: GetLocalStock, _: &mut Self::Context) -> Self::Result {
+        self.get_local_stock(msg.local_id)
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "HashMap<String, i32>")]
+pub struct GetTotalQuantityByProduct {}
+
+impl Handler<GetTotalQuantityByProduct> for StockHandler {
+    type Result = MessageResult<GetTotalQuantityByProduct>;
+
+    fn handle(&mut self, _: GetTotalQuantityByProduct, _: &mut Self::Context) -> Self::Result {
+        MessageResult(self.get_total_quantity_by_product())
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Vec<(String, i32)>")]
+pub struct GetTopProducts {
+    pub limit: usize,
+}
+
+impl Handler<GetTopProducts> for StockHandler {
+    type Result = MessageResult<GetTopProducts>;
+
+    fn handle(&mut self, msg: GetTopProducts, _: &mut Self::Context) -> Self::Result {
+        MessageResult(self.get_top_products(msg.limit))
+    }
+}
+
+// ====================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::model::stock_product::Product;
+
+    #[test]
+    fn test_reporting_queries_summarize_the_stock_of_all_locals() {
+        let mut stock_handler = StockHandler::new();
+        let mut local_shop_stock2 = HashMap::new();
+        local_shop_stock2.insert(
+            "product1".to_string(),
+            Product::new("product1".to_string(), 5),
+        );
+        local_shop_stock2.insert(
+            "product2".to_string(),
+            Product::new("product2".to_string(), 4),
+        );
+        stock_handler.add_local_shop_stock(2, local_shop_stock2);
+        let mut local_shop_stock1 = HashMap::new();
+        local_shop_stock1.insert(
+            "product2".to_string(),
+            Product::new("product2".to_string(), 3),
+        );
+        local_shop_stock1.insert(
+            "product3".to_string(),
+            Product::new("product3".to_string(), 6),
+        );
+        stock_handler.add_local_shop_stock(1, local_shop_stock1);
+        stock_handler
+            .reserve_stock(1, 1, vec![Product::new("product2".to_string(), 2)])
+            .unwrap();
+
+        assert_eq!(stock_handler.get_local_ids(), vec![1, 2]);
+        assert_eq!(
+            stock_handler.get_local_stock(1),
+            Ok(HashMap::from([
+                ("product2".to_string(), 3),
+                ("product3".to_string(), 6)
+            ]))
+        );
+        assert_eq!(
+            stock_handler.get_local_stock(3).map_err(|err| err.kind),
+            Err(DBErrorKind::UnknownLocal)
+        );
+        assert_eq!(
+            stock_handler.get_total_quantity_by_product(),
+            HashMap::from([
+                ("product1".to_string(), 5),
+                ("product2".to_string(), 7),
+                ("product3".to_string(), 6)
+            ])
+        );
+        assert_eq!(
+            stock_handler.get_top_products(2),
+            vec![("product3".to_string(), 6), ("product1".to_string(), 5)]
+        );
+    }
+}
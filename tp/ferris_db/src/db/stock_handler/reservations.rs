@@ -0,0 +1,361 @@
+//! Reservations hold quantities of the stock of a local for an order while it is being handled,
+//! so that they are not available to other orders until it is completed or cancelled.
+//!
+//! Reservations are not committed by themselves: the result of the order consumes its reservation
+//! instead of taking the quantities again, and reservations whose order never completes (e.g. because its e-commerce server was lost) are
+//! released once they are older than `RESERVATION_TTL_SECS`. Only the primary releases them, and
+//! backups follow through the log.
+
+use std::time::Duration;
+
+use actix::prelude::*;
+use shared::{communication::db_response::DBErrorKind, model::stock_product::Product};
+use tracing::{info, warn};
+
+use super::StockHandler;
+use crate::db::{
+    clock::current_timestamp,
+    constants::{RESERVATION_EXPIRY_CHECK_INTERVAL_SECS, RESERVATION_TTL_SECS},
+    db_error::DBError,
+    reservations::Reservation,
+    stock_history::ChangeOrigin,
+    wal::WalEntry,
+};
+
+impl StockHandler {
+    fn check_order_is_not_applied(&self, order_id: u64) -> Result<(), String> {
+        if self.applied_orders.contains(order_id) {
+            return Err(format!("Order {} was already completed", order_id));
+        }
+        Ok(())
+    }
+
+    /// Checks there is enough stock available for an order, besides what its previous
+    /// reservation holds, since that one is released by the new one.
+    fn check_reservation_against_stock(
+        &self,
+        local_shop_id: u16,
+        order_id: u64,
+        products: &[Product],
+    ) -> Result<(), String> {
+        if !self.stock_store.contains_local(local_shop_id) {
+            return Err("Local shop not found in global stock".to_string());
+        }
+        let previous_reservation = self
+            .reservations
+            .find_by_order(order_id)
+            .and_then(|reservation_id| self.reservations.get(reservation_id))
+            .filter(|reservation| reservation.local_id == local_shop_id);
+        for product in products {
+            let product_name = product.get_name();
+            let quantity_in_stock = self
+                .stock_store
+                .quantity(local_shop_id, &product_name)
+                .unwrap_or(0);
+            let held_for_order: i32 = previous_reservation
+                .iter()
+                .flat_map(|reservation| reservation.products.iter())
+                .filter(|held_product| held_product.get_name() == product_name)
+                .map(|held_product| held_product.get_quantity())
+                .sum();
+            let available_quantity = quantity_in_stock
+                - self
+                    .reservations
+                    .reserved_quantity(local_shop_id, &product_name)
+                + held_for_order;
+            if product.get_quantity() <= 0 || available_quantity < product.get_quantity() {
+                return Err(format!(
+                    "Cannot reserve {} of product {} in local shop {}, only {} available",
+                    product.get_quantity(),
+                    product_name,
+                    local_shop_id,
+                    available_quantity
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Holds the given quantities of a local's stock for an order, so they are no longer available
+    /// to other orders. A previous reservation for the same order (at a local that did not take
+    /// it) is released.
+    pub fn reserve_stock(
+        &mut self,
+        local_shop_id: u16,
+        order_id: u64,
+        products: Vec<Product>,
+    ) -> Result<u64, DBError> {
+        self.check_order_is_not_applied(order_id)
+            .map_err(|err| DBError::new(DBErrorKind::OrderAlreadyApplied, err))?;
+        self.check_reservation_against_stock(local_shop_id, order_id, &products)
+            .map_err(|err| DBError::new(DBErrorKind::InsufficientStock, err))?;
+        if let Some(previous_reservation_id) = self.reservations.find_by_order(order_id) {
+            self.release_reservation(previous_reservation_id)
+                .map_err(|err| DBError::new(DBErrorKind::Storage, err))?;
+        }
+        let reservation_id = self.reservations.next_id();
+        let reserved_at = current_timestamp();
+        self.append_to_wal(&WalEntry::StockReserved {
+            reservation_id,
+            local_id: local_shop_id,
+            products: products.clone(),
+            order_id: Some(order_id),
+            reserved_at,
+        })
+        .map_err(|err| DBError::new(DBErrorKind::Storage, err))?;
+        self.hold_reservation(
+            reservation_id,
+            Reservation {
+                local_id: local_shop_id,
+                products,
+                order_id: Some(order_id),
+                reserved_at,
+            },
+        );
+        Ok(reservation_id)
+    }
+
+    /// Holds a reservation, which changes the quantities available of its products.
+    pub(super) fn hold_reservation(&mut self, reservation_id: u64, reservation: Reservation) {
+        self.mark_reserved_products(&reservation);
+        self.reservations.insert(reservation_id, reservation);
+    }
+
+    pub(super) fn drop_reservation(&mut self, reservation_id: u64) -> Option<Reservation> {
+        let reservation = self.reservations.remove(reservation_id)?;
+        self.mark_reserved_products(&reservation);
+        Some(reservation)
+    }
+
+    /// Subscribers are told about the quantity in stock, which reservations do not change, so
+    /// only the partitions need to know about them.
+    fn mark_reserved_products(&mut self, reservation: &Reservation) {
+        for product in &reservation.products {
+            self.stock_partitions
+                .mark_changed(reservation.local_id, &product.get_name());
+        }
+    }
+
+    pub(super) fn check_reservation_exists(&self, reservation_id: u64) -> Result<(), String> {
+        self.reservations
+            .get(reservation_id)
+            .map(|_| ())
+            .ok_or_else(|| format!("Reservation {} not found", reservation_id))
+    }
+
+    /// Takes the quantities held by a reservation from the local's stock. Reservations can no longer
+    /// be committed, but logs written when they could are still replayed. The order of a committed
+    /// reservation counts as applied, so that its result does not take the quantities again.
+    pub(super) fn apply_reservation_commit(&mut self, reservation_id: u64, origin: &ChangeOrigin) {
+        if let Some(reservation) = self.drop_reservation(reservation_id) {
+            self.take_from_local_shop_stock(reservation.local_id, &reservation.products, origin);
+            if let Some(order_id) = reservation.order_id {
+                self.applied_orders.insert(order_id);
+            }
+        }
+    }
+
+    /// Drops the reservation held for an order once its result is applied, which already took
+    /// the quantities from the local's stock. It follows from the logged order result, so it is
+    /// not logged by itself.
+    pub(super) fn consume_reservation_of(&mut self, order_id: u64) {
+        if let Some(reservation_id) = self.reservations.find_by_order(order_id) {
+            self.drop_reservation(reservation_id);
+        }
+    }
+
+    /// Makes the quantities held by a reservation available again, once its order is cancelled.
+    pub fn release_reservation(&mut self, reservation_id: u64) -> Result<(), String> {
+        self.check_reservation_exists(reservation_id)?;
+        self.append_to_wal(&WalEntry::ReservationReleased { reservation_id })?;
+        self.drop_reservation(reservation_id);
+        Ok(())
+    }
+
+    /// Releases the reservations made at least `RESERVATION_TTL_SECS` before `now`.
+    pub fn expire_reservations(&mut self, now: u64) -> Result<(), String> {
+        for reservation_id in self.reservations.expired(now, RESERVATION_TTL_SECS) {
+            info!(
+                "[StockHandler] Reservation {} expired, releasing it",
+                reservation_id
+            );
+            self.release_reservation(reservation_id)?;
+        }
+        Ok(())
+    }
+}
+
+// ====================================================================
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<u64, DBError>")]
+pub struct ReserveStock {
+    pub local_id: u16,
+    pub order_id: u64,
+    pub products: Vec<Product>,
+    pub epoch: u64,
+}
+
+impl Handler<ReserveStock> for StockHandler {
+    type Result = Result<u64, DBError>;
+
+    fn handle(&mut self, msg: ReserveStock, _: &mut Self::Context) -> Self::Result {
+        self.check_leader_epoch(msg.epoch)?;
+        let result = self.reserve_stock(msg.local_id, msg.order_id, msg.products);
+        self.publish_changes();
+        result
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), DBError>")]
+pub struct ReleaseReservation {
+    pub reservation_id: u64,
+    pub epoch: u64,
+}
+
+impl Handler<ReleaseReservation> for StockHandler {
+    type Result = Result<(), DBError>;
+
+    fn handle(&mut self, msg: ReleaseReservation, _: &mut Self::Context) -> Self::Result {
+        self.check_leader_epoch(msg.epoch)?;
+        self.check_reservation_exists(msg.reservation_id)
+            .map_err(|err| DBError::new(DBErrorKind::UnknownReservation, err))?;
+        let result = self
+            .release_reservation(msg.reservation_id)
+            .map_err(|err| DBError::new(DBErrorKind::Storage, err));
+        self.publish_changes();
+        result
+    }
+}
+
+/// Starts releasing the expired reservations periodically, once this database is the primary.
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "()")]
+pub struct StartExpiringReservations {}
+
+impl Handler<StartExpiringReservations> for StockHandler {
+    type Result = ();
+
+    fn handle(&mut self, _: StartExpiringReservations, ctx: &mut Self::Context) -> Self::Result {
+        ctx.run_interval(
+            Duration::from_secs(RESERVATION_EXPIRY_CHECK_INTERVAL_SECS),
+            |stock_handler, _| {
+                if let Err(err) = stock_handler.expire_reservations(current_timestamp()) {
+                    warn!(
+                        "[StockHandler] Error releasing expired reservations: {}",
+                        err
+                    );
+                }
+                stock_handler.publish_changes();
+            },
+        );
+    }
+}
+
+// ====================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        test_fixture::{local_order_of, stock_of, PersistedStockHandler},
+        OrderResultOutcome,
+    };
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_reserved_stock_is_not_available_until_released() {
+        let mut stock_handler = StockHandler::new();
+        let mut local_shop_stock = HashMap::new();
+        local_shop_stock.insert(
+            "product1".to_string(),
+            Product::new("product1".to_string(), 5),
+        );
+        stock_handler.add_local_shop_stock(1, local_shop_stock);
+        stock_handler
+            .reserve_stock(1, 10, vec![Product::new("product1".to_string(), 3)])
+            .unwrap();
+        assert_eq!(
+            stock_handler
+                .reserve_stock(1, 11, vec![Product::new("product1".to_string(), 3)])
+                .map_err(|error| error.kind),
+            Err(DBErrorKind::InsufficientStock)
+        );
+        let second = stock_handler
+            .reserve_stock(1, 12, vec![Product::new("product1".to_string(), 2)])
+            .unwrap();
+        assert_eq!(
+            stock_handler.get_quantity_of_product_from_all_stocks("product1".to_string()),
+            HashMap::from([(1, 0)])
+        );
+
+        stock_handler.release_reservation(second).unwrap();
+        assert!(stock_handler.release_reservation(second).is_err());
+        assert_eq!(
+            stock_handler.get_quantity_of_product_from_all_stocks("product1".to_string()),
+            HashMap::from([(1, 2)])
+        );
+    }
+
+    #[test]
+    fn test_reservations_consumed_by_order_results_stay_consumed_after_restart() {
+        let persisted = PersistedStockHandler::new("consumed_reservations");
+        let order = local_order_of(1, "product1", 3);
+        let order_id = order.get_id().unwrap();
+
+        let (mut stock_handler, _) = persisted.recover();
+        stock_handler
+            .process_post_to_stock_from_local(1, stock_of("product1", 5), 1, None)
+            .unwrap();
+        stock_handler
+            .reserve_stock(1, order_id, vec![Product::new("product1".to_string(), 3)])
+            .unwrap();
+        assert_eq!(
+            stock_handler.process_order_result_in_stock(order, 2, None),
+            Ok(OrderResultOutcome::Accepted)
+        );
+        assert_eq!(stock_handler.available_quantity(1, "product1"), 2);
+        // The order is completed, so it cannot be reserved again.
+        assert_eq!(
+            stock_handler
+                .reserve_stock(1, order_id, vec![Product::new("product1".to_string(), 1)])
+                .map_err(|error| error.kind),
+            Err(DBErrorKind::OrderAlreadyApplied)
+        );
+        drop(stock_handler);
+
+        let (restarted, _) = persisted.recover();
+        assert_eq!(restarted.available_quantity(1, "product1"), 2);
+        assert_eq!(
+            restarted.get_quantity_of_product_from_all_stocks("product1".to_string()),
+            HashMap::from([(1, 2)])
+        );
+    }
+
+    #[test]
+    fn test_expired_reservations_are_released_after_restart() {
+        let persisted = PersistedStockHandler::new("expired_reservations");
+
+        let (mut stock_handler, _) = persisted.recover();
+        stock_handler
+            .process_post_to_stock_from_local(1, stock_of("product1", 5), 1, None)
+            .unwrap();
+        let reserved_at = current_timestamp();
+        stock_handler
+            .reserve_stock(1, 10, vec![Product::new("product1".to_string(), 3)])
+            .unwrap();
+        stock_handler
+            .expire_reservations(reserved_at + RESERVATION_TTL_SECS - 1)
+            .unwrap();
+        assert_eq!(stock_handler.available_quantity(1, "product1"), 2);
+        stock_handler
+            .expire_reservations(current_timestamp() + RESERVATION_TTL_SECS)
+            .unwrap();
+        assert_eq!(stock_handler.available_quantity(1, "product1"), 5);
+        drop(stock_handler);
+
+        let (restarted, _) = persisted.recover();
+        assert_eq!(restarted.available_quantity(1, "product1"), 5);
+    }
+}
@@ -0,0 +1,160 @@
+//! E-commerce servers can subscribe to changes of some products (or of any product), optionally
+//! only below a threshold, and are pushed the new quantity of each product changed by a message
+//! once it is handled.
+
+use actix::prelude::*;
+use shared::communication::db_response::{DBErrorKind, DBResponse};
+use tracing::{error, info, warn};
+
+use super::StockHandler;
+use crate::db::{
+    db_error::DBError,
+    db_middleman::{DBMiddleman, SendOnlineMsg},
+    subscriptions::StockFilter,
+};
+
+impl StockHandler {
+    pub fn subscribe_to_stock(
+        &mut self,
+        subscriber: Addr<DBMiddleman>,
+        filter: StockFilter,
+    ) -> u64 {
+        let subscription_id = self.subscriptions.subscribe(subscriber, filter);
+        info!("[StockHandler] New stock subscription {}", subscription_id);
+        subscription_id
+    }
+
+    pub fn unsubscribe_from_stock(
+        &mut self,
+        subscription_id: u64,
+        subscriber: &Addr<DBMiddleman>,
+    ) -> Result<(), DBError> {
+        if !self.subscriptions.unsubscribe(subscription_id, subscriber) {
+            return Err(DBError::new(
+                DBErrorKind::InvalidRequest,
+                format!("Subscription {} not found", subscription_id),
+            ));
+        }
+        info!(
+            "[StockHandler] Stock subscription {} cancelled",
+            subscription_id
+        );
+        Ok(())
+    }
+
+    /// Sends the new quantity of each product changed since the last call to its subscribers.
+    ///
+    /// A subscriber that missed notifications because it was not keeping up is first told how
+    /// many, so it knows the quantities it has may be outdated.
+    pub(super) fn notify_subscribers(&mut self) {
+        for (local_id, product_name) in self.subscriptions.take_changed() {
+            let quantity = self
+                .stock_store
+                .quantity(local_id, &product_name)
+                .unwrap_or(0);
+            let subscribers: Vec<(u64, Addr<DBMiddleman>)> = self
+                .subscriptions
+                .matching(&product_name, quantity)
+                .into_iter()
+                .map(|(subscription_id, subscription)| {
+                    (subscription_id, subscription.subscriber.clone())
+                })
+                .collect();
+            for (subscription_id, subscriber) in subscribers {
+                let dropped = self.subscriptions.dropped(subscription_id);
+                if dropped > 0 {
+                    let notification = DBResponse::StockNotificationsDropped {
+                        subscription_id,
+                        dropped,
+                    };
+                    // If it does not fit either, this change is counted as dropped too.
+                    if !self.notify(subscription_id, &subscriber, notification) {
+                        continue;
+                    }
+                    self.subscriptions.reset_dropped(subscription_id);
+                }
+                let notification = DBResponse::StockChanged {
+                    subscription_id,
+                    local_id,
+                    product_name: product_name.clone(),
+                    quantity,
+                };
+                self.notify(subscription_id, &subscriber, notification);
+            }
+        }
+    }
+
+    /// Sends a notification to the subscriber of a subscription, returning whether it was sent.
+    ///
+    /// Only the subscriptions of subscribers that disconnected are cancelled. If the mailbox of
+    /// the subscriber is full, the notification is counted as dropped instead.
+    fn notify(
+        &mut self,
+        subscription_id: u64,
+        subscriber: &Addr<DBMiddleman>,
+        notification: DBResponse,
+    ) -> bool {
+        let msg_to_send = match notification.to_string() {
+            Ok(msg_to_send) => msg_to_send,
+            Err(err) => {
+                error!(
+                    "[StockHandler] Error serializing notification of subscription {}: {}",
+                    subscription_id, err
+                );
+                self.subscriptions.record_dropped(subscription_id);
+                return false;
+            }
+        };
+        match subscriber.try_send(SendOnlineMsg { msg_to_send }) {
+            Ok(()) => true,
+            Err(SendError::Full(_)) => {
+                warn!(
+                    "[StockHandler] Subscriber of subscription {} is not keeping up, dropping a notification",
+                    subscription_id
+                );
+                self.subscriptions.record_dropped(subscription_id);
+                false
+            }
+            Err(SendError::Closed(_)) => {
+                warn!(
+                    "[StockHandler] Subscriber of subscription {} disconnected, cancelling it",
+                    subscription_id
+                );
+                self.subscriptions.remove(subscription_id);
+                false
+            }
+        }
+    }
+}
+
+// ====================================================================
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "u64")]
+pub struct SubscribeToStock {
+    pub subscriber: Addr<DBMiddleman>,
+    pub filter: StockFilter,
+}
+
+impl Handler<SubscribeToStock> for StockHandler {
+    type Result = u64;
+
+    fn handle(&mut self, msg: SubscribeToStock, _: &mut Self::Context) -> Self::Result {
+        self.subscribe_to_stock(msg.subscriber, msg.filter)
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), DBError>")]
+pub struct UnsubscribeFromStock {
+    pub subscriber: Addr<DBMiddleman>,
+    pub subscription_id: u64,
+}
+
+impl Handler<UnsubscribeFromStock> for StockHandler {
+    type Result = Result<(), DBError>;
+
+    fn handle(&mut self, msg: UnsubscribeFromStock, _: &mut Self::Context) -> Self::Result {
+        self.unsubscribe_from_stock(msg.subscription_id, &msg.subscriber)
+    }
+}
@@ -0,0 +1,95 @@
+//! The setup shared by the tests that restart the `StockHandler` from its files.
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use shared::model::{
+    order::{LocalOrder, Order},
+    stock_product::Product,
+};
+
+use super::StockHandler;
+use crate::db::{
+    local_registry::LocalRegistry,
+    stock_store::{MemoryStockStore, StockStore, StockStoreKind},
+    wal::WriteAheadLog,
+};
+
+/// The files a `StockHandler` is persisted to, in the temporary directory, which are removed
+/// when it is dropped.
+pub struct PersistedStockHandler {
+    pub wal_path: PathBuf,
+    pub snapshot_path: PathBuf,
+    pub store_dir: PathBuf,
+}
+
+impl PersistedStockHandler {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("ferris_db_{}_{}", std::process::id(), name));
+        let persisted = Self {
+            wal_path: path.with_extension("wal"),
+            snapshot_path: path.with_extension("snapshot"),
+            store_dir: path.with_extension("store"),
+        };
+        persisted.remove_files();
+        persisted
+    }
+
+    /// Recovers the `StockHandler` from its files, with its stock kept in memory.
+    pub fn recover(&self) -> (StockHandler, LocalRegistry) {
+        self.recover_into(Box::new(MemoryStockStore::new()))
+    }
+
+    /// Recovers the `StockHandler` from its files, with its stock kept in its store directory.
+    pub fn recover_into_file_store(&self) -> StockHandler {
+        self.recover_into(self.file_store()).0
+    }
+
+    pub fn file_store(&self) -> Box<dyn StockStore> {
+        StockStoreKind::File.open(&self.store_dir).unwrap()
+    }
+
+    fn recover_into(&self, stock_store: Box<dyn StockStore>) -> (StockHandler, LocalRegistry) {
+        StockHandler::recover(
+            WriteAheadLog::open(&self.wal_path).unwrap(),
+            &self.snapshot_path,
+            stock_store,
+        )
+        .unwrap()
+    }
+
+    fn remove_files(&self) {
+        let _ = std::fs::remove_file(&self.wal_path);
+        let _ = std::fs::remove_file(&self.snapshot_path);
+        let _ = std::fs::remove_dir_all(&self.store_dir);
+    }
+}
+
+impl Drop for PersistedStockHandler {
+    fn drop(&mut self) {
+        self.remove_files();
+    }
+}
+
+/// The stock of a local with only the given quantity of a product.
+pub fn stock_of(product_name: &str, quantity: i32) -> HashMap<String, Product> {
+    HashMap::from([(
+        product_name.to_string(),
+        Product::new(product_name.to_string(), quantity),
+    )])
+}
+
+/// A local order with an id of its own, as it is given when the order is taken.
+pub fn local_order_of(local_id: u16, product_name: &str, quantity: i32) -> Order {
+    static NEXT_ORDER_ID: AtomicU64 = AtomicU64::new(1);
+    let mut order = Order::Local(LocalOrder::new(vec![Product::new(
+        product_name.to_string(),
+        quantity,
+    )]));
+    order.set_local_id(local_id);
+    order.set_id(NEXT_ORDER_ID.fetch_add(1, Ordering::Relaxed));
+    order
+}
@@ -0,0 +1,107 @@
+//! This module contains the `StockHistory`, which records every change to the stock along with
+//! what caused it, so that unexpected quantities can be traced back to their origin.
+//!
+//! Only the latest `MAX_STOCK_HISTORY_LEN` changes are kept, the oldest ones being dropped first.
+
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+use shared::model::stock_change::{StockChange, StockChangeCause};
+
+use super::constants::MAX_STOCK_HISTORY_LEN;
+
+/// Where a set of changes to the stock of a local came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeOrigin {
+    pub cause: StockChangeCause,
+    pub timestamp: u64,
+    pub ecommerce_id: Option<u16>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct StockHistory {
+    changes: VecDeque<StockChange>,
+}
+
+impl StockHistory {
+    pub fn new() -> Self {
+        StockHistory::default()
+    }
+
+    /// Records a change to the quantity of a product in a local. Changes of 0 are not recorded.
+    pub fn record(&mut self, local_id: u16, product_name: &str, delta: i32, origin: &ChangeOrigin) {
+        if delta == 0 {
+            return;
+        }
+        if self.changes.len() == MAX_STOCK_HISTORY_LEN {
+            self.changes.pop_front();
+        }
+        self.changes.push_back(StockChange {
+            timestamp: origin.timestamp,
+            local_id,
+            product_name: product_name.to_string(),
+            delta,
+            cause: origin.cause.clone(),
+            ecommerce_id: origin.ecommerce_id,
+        });
+    }
+
+    /// Returns the changes recorded between `from` and `to` (both included), of the given local
+    /// and/or product if any, from oldest to newest.
+    pub fn query(
+        &self,
+        local_id: Option<u16>,
+        product_name: Option<&str>,
+        from: u64,
+        to: u64,
+    ) -> Vec<StockChange> {
+        self.changes
+            .iter()
+            .filter(|change| (from..=to).contains(&change.timestamp))
+            .filter(|change| local_id.map_or(true, |local_id| change.local_id == local_id))
+            .filter(|change| {
+                product_name.map_or(true, |product_name| change.product_name == product_name)
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+// ====================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn origin(cause: StockChangeCause, timestamp: u64) -> ChangeOrigin {
+        ChangeOrigin {
+            cause,
+            timestamp,
+            ecommerce_id: Some(1),
+        }
+    }
+
+    #[test]
+    fn test_history_is_filtered_by_local_product_and_time_range() {
+        let mut history = StockHistory::new();
+        let upload = origin(StockChangeCause::StockUpload { version: 1 }, 10);
+        let order_result = origin(StockChangeCause::OrderResult { stock_version: 2 }, 20);
+        history.record(1, "product1", 5, &upload);
+        history.record(1, "product2", 0, &upload);
+        history.record(2, "product1", 3, &upload);
+        history.record(1, "product1", -2, &order_result);
+
+        let changes = history.query(Some(1), Some("product1"), 0, u64::MAX);
+        assert_eq!(
+            changes
+                .iter()
+                .map(|change| change.delta)
+                .collect::<Vec<_>>(),
+            vec![5, -2]
+        );
+        assert_eq!(changes[1].cause, order_result.cause);
+        assert_eq!(history.query(None, Some("product1"), 0, 10).len(), 2);
+        assert_eq!(history.query(None, Some("product2"), 0, u64::MAX), vec![]);
+        assert_eq!(history.query(Some(2), None, 11, 30), vec![]);
+    }
+}
@@ -0,0 +1,255 @@
+//! This module contains the `StockPartition` actors, which answer the queries for the quantity of
+//! products in every local, so that those queries do not wait behind the changes to the stock
+//! queued in the `StockHandler`.
+//!
+//! Products are split among the partitions by the hash of their name. The `StockHandler` is still
+//! the only one that changes the stock: after handling each message it sends every partition the
+//! quantities not held by reservations of its products that changed.
+//!
+//! The partitions do not acknowledge those updates, so they are only eventually consistent with the
+//! `StockHandler`: a query may not see a change yet, such as a reservation or an order result, even
+//! if it was already acknowledged to whoever made it. That is why there are none by default, and
+//! the queries are answered by the `StockHandler` itself unless partitions are asked for at startup.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    hash::{DefaultHasher, Hash, Hasher},
+};
+
+use actix::{Actor, Addr, Context, Handler, Message, MessageResult};
+use tracing::debug;
+
+/// The partition that holds the quantities of a product.
+pub fn partition_of(product_name: &str, partition_count: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    product_name.hash(&mut hasher);
+    (hasher.finish() % partition_count as u64) as usize
+}
+
+/// Groups the given products by the partition that holds them.
+pub fn group_by_partition(
+    product_names: &[String],
+    partition_count: usize,
+) -> HashMap<usize, Vec<String>> {
+    let mut product_names_by_partition: HashMap<usize, Vec<String>> = HashMap::new();
+    for product_name in product_names {
+        product_names_by_partition
+            .entry(partition_of(product_name, partition_count))
+            .or_default()
+            .push(product_name.clone());
+    }
+    product_names_by_partition
+}
+
+#[derive(Debug, Default)]
+pub struct StockPartition {
+    local_ids: BTreeSet<u16>,
+    /// The quantity of each product not held by reservations, by local id.
+    available: HashMap<String, HashMap<u16, i32>>,
+}
+
+impl StockPartition {
+    pub fn new() -> Self {
+        StockPartition::default()
+    }
+
+    fn set_local_ids(&mut self, local_ids: Vec<u16>) {
+        self.local_ids = local_ids.into_iter().collect();
+        for quantity_by_local_id in self.available.values_mut() {
+            quantity_by_local_id.retain(|local_id, _| self.local_ids.contains(local_id));
+        }
+    }
+
+    /// The quantity of each product in every known local, which is 0 in the locals without it.
+    fn available_quantities(&self, product_names: &[String]) -> HashMap<String, HashMap<u16, i32>> {
+        product_names
+            .iter()
+            .map(|product_name| {
+                let quantity_by_local_id = self.available.get(product_name);
+                let quantities = self
+                    .local_ids
+                    .iter()
+                    .map(|local_id| {
+                        let quantity = quantity_by_local_id
+                            .and_then(|quantity_by_local_id| quantity_by_local_id.get(local_id))
+                            .copied()
+                            .unwrap_or(0);
+                        (*local_id, quantity)
+                    })
+                    .collect();
+                (product_name.clone(), quantities)
+            })
+            .collect()
+    }
+}
+
+impl Actor for StockPartition {
+    type Context = Context<Self>;
+
+    fn started(&mut self, _ctx: &mut Self::Context) {
+        debug!("[StockPartition] Started");
+    }
+}
+
+/// The changes to the quantities of the products of a partition.
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "()")]
+pub struct UpdatePartition {
+    /// Whether every quantity held so far must be dropped first, e.g. when the whole state was
+    /// received from the upstream database.
+    pub replace_all: bool,
+    /// The ids of the locals whose stock is known, if they changed.
+    pub local_ids: Option<Vec<u16>>,
+    pub quantities: Vec<(u16, String, i32)>,
+}
+
+impl Handler<UpdatePartition> for StockPartition {
+    type Result = ();
+
+    fn handle(&mut self, msg: UpdatePartition, _: &mut Self::Context) -> Self::Result {
+        if msg.replace_all {
+            self.available.clear();
+        }
+        if let Some(local_ids) = msg.local_ids {
+            self.set_local_ids(local_ids);
+        }
+        for (local_id, product_name, quantity) in msg.quantities {
+            if self.local_ids.contains(&local_id) {
+                self.available
+                    .entry(product_name)
+                    .or_default()
+                    .insert(local_id, quantity);
+            }
+        }
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "HashMap<String, HashMap<u16, i32>>")]
+pub struct GetAvailableQuantities {
+    pub product_names: Vec<String>,
+}
+
+impl Handler<GetAvailableQuantities> for StockPartition {
+    type Result = MessageResult<GetAvailableQuantities>;
+
+    fn handle(&mut self, msg: GetAvailableQuantities, _: &mut Self::Context) -> Self::Result {
+        MessageResult(self.available_quantities(&msg.product_names))
+    }
+}
+
+/// The partitions the `StockHandler` sends the changes to, and the changes not sent yet.
+#[derive(Debug, Default)]
+pub struct StockPartitions {
+    partitions: Vec<Addr<StockPartition>>,
+    published_local_ids: Vec<u16>,
+    changed: BTreeSet<(u16, String)>,
+}
+
+impl StockPartitions {
+    pub fn new(partitions: Vec<Addr<StockPartition>>) -> Self {
+        StockPartitions {
+            partitions,
+            ..StockPartitions::default()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.partitions.is_empty()
+    }
+
+    /// Marks the available quantity of a product in a local as changed, so it is sent to its partition.
+    pub fn mark_changed(&mut self, local_id: u16, product_name: &str) {
+        if self.partitions.is_empty() {
+            return;
+        }
+        self.changed.insert((local_id, product_name.to_string()));
+    }
+
+    /// Returns the products changed since the last call, by local.
+    pub fn take_changed(&mut self) -> BTreeSet<(u16, String)> {
+        std::mem::take(&mut self.changed)
+    }
+
+    /// Sends each partition the given quantities of its products, along with the ids of the
+    /// known locals if they changed since the last time, or unconditionally if `replace_all`.
+    pub fn publish(
+        &mut self,
+        local_ids: Vec<u16>,
+        quantities: Vec<(u16, String, i32)>,
+        replace_all: bool,
+    ) {
+        if self.partitions.is_empty() {
+            return;
+        }
+        let local_ids_changed = replace_all || local_ids != self.published_local_ids;
+        let mut quantities_by_partition: HashMap<usize, Vec<(u16, String, i32)>> = HashMap::new();
+        for (local_id, product_name, quantity) in quantities {
+            quantities_by_partition
+                .entry(partition_of(&product_name, self.partitions.len()))
+                .or_default()
+                .push((local_id, product_name, quantity));
+        }
+        for (partition_index, partition) in self.partitions.iter().enumerate() {
+            let quantities = quantities_by_partition
+                .remove(&partition_index)
+                .unwrap_or_default();
+            if !local_ids_changed && quantities.is_empty() {
+                continue;
+            }
+            // Sent even if the mailbox is full, since a lost update would leave the partition behind for good.
+            partition.do_send(UpdatePartition {
+                replace_all,
+                local_ids: local_ids_changed.then(|| local_ids.clone()),
+                quantities,
+            });
+        }
+        self.published_local_ids = local_ids;
+    }
+}
+
+// ====================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_product_belongs_to_a_single_partition() {
+        let product_names: Vec<String> = (0..20).map(|index| format!("product{}", index)).collect();
+        let product_names_by_partition = group_by_partition(&product_names, 3);
+        assert!(product_names_by_partition
+            .keys()
+            .all(|partition_index| *partition_index < 3));
+        assert_eq!(
+            product_names_by_partition
+                .values()
+                .map(|product_names| product_names.len())
+                .sum::<usize>(),
+            20
+        );
+    }
+
+    #[test]
+    fn test_known_locals_without_a_product_have_none_of_it() {
+        let mut partition = StockPartition::new();
+        partition.set_local_ids(vec![1, 2]);
+        partition
+            .available
+            .insert("product1".to_string(), HashMap::from([(1, 4), (3, 9)]));
+
+        assert_eq!(
+            partition.available_quantities(&["product1".to_string(), "product2".to_string()]),
+            HashMap::from([
+                ("product1".to_string(), HashMap::from([(1, 4), (2, 0)])),
+                ("product2".to_string(), HashMap::from([(1, 0), (2, 0)])),
+            ])
+        );
+
+        partition.set_local_ids(vec![2]);
+        assert_eq!(
+            partition.available_quantities(&["product1".to_string()]),
+            HashMap::from([("product1".to_string(), HashMap::from([(2, 0)]))])
+        );
+    }
+}
@@ -0,0 +1,351 @@
+//! This module contains the `StockStore` trait, behind which the stock of every local is kept, and
+//! its implementations, one of which is chosen when the database starts:
+//!
+//! - `MemoryStockStore` keeps the stock only in memory.
+//! - `FileStockStore` also writes the stock of a local to its own file every time it changes, so
+//!   the stock on disk is always up to date.
+//!
+//! Either way, the write-ahead log and the snapshots remain the source of truth, and the store is
+//! rebuilt from them on every start.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Debug,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use shared::model::stock_product::Product;
+use tracing::error;
+
+use super::{
+    constants::{LOCAL_STOCK_FILE_PREFIX, STOCK_STORE_FILE_EXTENSION},
+    stock_export::GlobalStock,
+};
+
+pub trait StockStore: Debug {
+    /// The ids of the locals whose stock is known, sorted.
+    fn local_ids(&self) -> Vec<u16>;
+
+    fn contains_local(&self, local_id: u16) -> bool;
+
+    fn local_stock(&self, local_id: u16) -> Option<HashMap<String, Product>>;
+
+    /// The quantity of a product in a local, if the local has it.
+    fn quantity(&self, local_id: u16, product_name: &str) -> Option<i32>;
+
+    fn all_stock(&self) -> GlobalStock;
+
+    /// Replaces the stock of a local, returning the previous one.
+    fn replace_local_stock(
+        &mut self,
+        local_id: u16,
+        stock: HashMap<String, Product>,
+    ) -> Option<HashMap<String, Product>>;
+
+    /// Removes the stock of a local, returning it.
+    fn remove_local(&mut self, local_id: u16) -> Option<HashMap<String, Product>>;
+
+    /// Changes the quantity of some products of a local, adding the local and the products it
+    /// does not have yet. Quantities never go below 0.
+    fn change_quantities(&mut self, local_id: u16, changes: &[(String, i32)]);
+
+    /// Replaces the stock of every local.
+    fn replace_all(&mut self, global_stock: GlobalStock);
+}
+
+/// Which `StockStore` the database keeps the stock in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StockStoreKind {
+    #[default]
+    Memory,
+    File,
+}
+
+impl StockStoreKind {
+    /// Opens a store of this kind, keeping its files (if any) in the given directory.
+    pub fn open(self, dir: &Path) -> Result<Box<dyn StockStore>, String> {
+        match self {
+            StockStoreKind::Memory => Ok(Box::new(MemoryStockStore::new())),
+            StockStoreKind::File => Ok(Box::new(FileStockStore::open(dir)?)),
+        }
+    }
+}
+
+impl FromStr for StockStoreKind {
+    type Err = String;
+
+    fn from_str(kind: &str) -> Result<Self, Self::Err> {
+        match kind {
+            "memory" => Ok(StockStoreKind::Memory),
+            "file" => Ok(StockStoreKind::File),
+            _ => Err(format!(
+                "Invalid stock store: {} (must be memory or file)",
+                kind
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryStockStore {
+    global_stock: GlobalStock,
+}
+
+impl MemoryStockStore {
+    pub fn new() -> Self {
+        MemoryStockStore::default()
+    }
+}
+
+impl StockStore for MemoryStockStore {
+    fn local_ids(&self) -> Vec<u16> {
+        let mut local_ids: Vec<u16> = self.global_stock.keys().copied().collect();
+        local_ids.sort();
+        local_ids
+    }
+
+    fn contains_local(&self, local_id: u16) -> bool {
+        self.global_stock.contains_key(&local_id)
+    }
+
+    fn local_stock(&self, local_id: u16) -> Option<HashMap<String, Product>> {
+        self.global_stock.get(&local_id).cloned()
+    }
+
+    fn quantity(&self, local_id: u16, product_name: &str) -> Option<i32> {
+        self.global_stock
+            .get(&local_id)
+            .and_then(|local_stock| local_stock.get(product_name))
+            .map(|product| product.get_quantity())
+    }
+
+    fn all_stock(&self) -> GlobalStock {
+        self.global_stock.clone()
+    }
+
+    fn replace_local_stock(
+        &mut self,
+        local_id: u16,
+        stock: HashMap<String, Product>,
+    ) -> Option<HashMap<String, Product>> {
+        self.global_stock.insert(local_id, stock)
+    }
+
+    fn remove_local(&mut self, local_id: u16) -> Option<HashMap<String, Product>> {
+        self.global_stock.remove(&local_id)
+    }
+
+    fn change_quantities(&mut self, local_id: u16, changes: &[(String, i32)]) {
+        let local_stock = self.global_stock.entry(local_id).or_default();
+        for (product_name, change) in changes {
+            local_stock
+                .entry(product_name.clone())
+                .or_insert_with(|| Product::new(product_name.clone(), 0))
+                .affect_quantity_with_value(*change);
+        }
+    }
+
+    fn replace_all(&mut self, global_stock: GlobalStock) {
+        self.global_stock = global_stock;
+    }
+}
+
+/// Keeps the stock in memory, and the stock of each local in a file of the given directory with
+/// the quantity of each product indexed by name.
+///
+/// A file that cannot be written is only logged, since the stock can always be rebuilt from the
+/// write-ahead log, and it is written again with the next change to that local.
+#[derive(Debug)]
+pub struct FileStockStore {
+    dir: PathBuf,
+    memory: MemoryStockStore,
+}
+
+impl FileStockStore {
+    /// Opens the store in the given directory, loading the stock of the locals already in it.
+    pub fn open(dir: &Path) -> Result<Self, String> {
+        fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+        let mut memory = MemoryStockStore::new();
+        for entry in fs::read_dir(dir).map_err(|err| err.to_string())? {
+            let path = entry.map_err(|err| err.to_string())?.path();
+            let Some(local_id) = path
+                .file_name()
+                .and_then(|file_name| file_name.to_str())
+                .and_then(|file_name| file_name.strip_prefix(LOCAL_STOCK_FILE_PREFIX))
+                .and_then(|file_name| file_name.strip_suffix(STOCK_STORE_FILE_EXTENSION))
+                .and_then(|local_id| local_id.parse::<u16>().ok())
+            else {
+                continue;
+            };
+            let content = fs::read_to_string(&path).map_err(|err| err.to_string())?;
+            let quantities: HashMap<String, i32> =
+                serde_json::from_str(&content).map_err(|err| format!("{:?}: {}", path, err))?;
+            let stock = quantities
+                .into_iter()
+                .map(|(name, quantity)| (name.clone(), Product::new(name, quantity)))
+                .collect();
+            memory.replace_local_stock(local_id, stock);
+        }
+        Ok(FileStockStore {
+            dir: dir.to_path_buf(),
+            memory,
+        })
+    }
+
+    fn local_path(&self, local_id: u16) -> PathBuf {
+        self.dir.join(format!(
+            "{}{}{}",
+            LOCAL_STOCK_FILE_PREFIX, local_id, STOCK_STORE_FILE_EXTENSION
+        ))
+    }
+
+    /// Writes the stock of a local to its file, or removes the file if the local is not known.
+    fn save_local(&self, local_id: u16) {
+        let path = self.local_path(local_id);
+        let saved = match self.memory.global_stock.get(&local_id) {
+            Some(stock) => {
+                let quantities: BTreeMap<String, i32> = stock
+                    .values()
+                    .map(|product| (product.get_name(), product.get_quantity()))
+                    .collect();
+                let tmp_path = path.with_extension("tmp");
+                serde_json::to_string(&quantities)
+                    .map_err(|err| err.to_string())
+                    .and_then(|content| {
+                        fs::write(&tmp_path, content).map_err(|err| err.to_string())
+                    })
+                    .and_then(|_| fs::rename(&tmp_path, &path).map_err(|err| err.to_string()))
+            }
+            None if path.exists() => fs::remove_file(&path).map_err(|err| err.to_string()),
+            None => Ok(()),
+        };
+        if let Err(err) = saved {
+            error!(
+                "[FileStockStore] Could not save the stock of local {}: {}",
+                local_id, err
+            );
+        }
+    }
+}
+
+impl StockStore for FileStockStore {
+    fn local_ids(&self) -> Vec<u16> {
+        self.memory.local_ids()
+    }
+
+    fn contains_local(&self, local_id: u16) -> bool {
+        self.memory.contains_local(local_id)
+    }
+
+    fn local_stock(&self, local_id: u16) -> Option<HashMap<String, Product>> {
+        self.memory.local_stock(local_id)
+    }
+
+    fn quantity(&self, local_id: u16, product_name: &str) -> Option<i32> {
+        self.memory.quantity(local_id, product_name)
+    }
+
+    fn all_stock(&self) -> GlobalStock {
+        self.memory.all_stock()
+    }
+
+    fn replace_local_stock(
+        &mut self,
+        local_id: u16,
+        stock: HashMap<String, Product>,
+    ) -> Option<HashMap<String, Product>> {
+        let previous_stock = self.memory.replace_local_stock(local_id, stock);
+        self.save_local(local_id);
+        previous_stock
+    }
+
+    fn remove_local(&mut self, local_id: u16) -> Option<HashMap<String, Product>> {
+        let stock = self.memory.remove_local(local_id);
+        self.save_local(local_id);
+        stock
+    }
+
+    fn change_quantities(&mut self, local_id: u16, changes: &[(String, i32)]) {
+        self.memory.change_quantities(local_id, changes);
+        self.save_local(local_id);
+    }
+
+    fn replace_all(&mut self, global_stock: GlobalStock) {
+        let previous_local_ids = self.memory.local_ids();
+        self.memory.replace_all(global_stock);
+        for local_id in previous_local_ids {
+            if !self.memory.contains_local(local_id) {
+                self.save_local(local_id);
+            }
+        }
+        for local_id in self.memory.local_ids() {
+            self.save_local(local_id);
+        }
+    }
+}
+
+// ====================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_store_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("ferris_db_{}_{}_store", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn stock_of(products: &[(&str, i32)]) -> HashMap<String, Product> {
+        products
+            .iter()
+            .map(|(name, quantity)| (name.to_string(), Product::new(name.to_string(), *quantity)))
+            .collect()
+    }
+
+    #[test]
+    fn test_quantities_are_changed_without_going_below_zero() {
+        let mut store = MemoryStockStore::new();
+        store.replace_local_stock(1, stock_of(&[("product1", 5)]));
+        store.change_quantities(
+            1,
+            &[("product1".to_string(), -7), ("product2".to_string(), 3)],
+        );
+        store.change_quantities(2, &[("product1".to_string(), 1)]);
+
+        assert_eq!(store.quantity(1, "product1"), Some(0));
+        assert_eq!(store.quantity(1, "product2"), Some(3));
+        assert_eq!(store.quantity(1, "product3"), None);
+        assert_eq!(store.local_ids(), vec![1, 2]);
+        assert_eq!(store.remove_local(2), Some(stock_of(&[("product1", 1)])));
+        assert!(!store.contains_local(2));
+    }
+
+    #[test]
+    fn test_file_store_keeps_the_stock_of_each_local_on_disk() {
+        let dir = test_store_dir("file");
+
+        let mut store = FileStockStore::open(&dir).unwrap();
+        store.replace_local_stock(1, stock_of(&[("product1", 5)]));
+        store.replace_local_stock(2, stock_of(&[("product2", 2)]));
+        store.change_quantities(1, &[("product1".to_string(), -2)]);
+        store.remove_local(2);
+        drop(store);
+
+        let reopened = FileStockStore::open(&dir).unwrap();
+        assert_eq!(reopened.local_ids(), vec![1]);
+        assert_eq!(reopened.quantity(1, "product1"), Some(3));
+
+        let mut reopened = reopened;
+        reopened.replace_all(HashMap::from([(3, stock_of(&[("product3", 1)]))]));
+        drop(reopened);
+        assert_eq!(
+            FileStockStore::open(&dir).unwrap().all_stock(),
+            HashMap::from([(3, stock_of(&[("product3", 1)]))])
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
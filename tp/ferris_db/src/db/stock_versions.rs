@@ -0,0 +1,113 @@
+//! This module contains the `StockVersions`, which keep track of the version each local's stock is at.
+//!
+//! Every change to the stock of a local carries the version it takes that stock to, so updates
+//! with a version the stock already reached are stale, and updates that arrive before the
+//! previous ones are buffered until those arrive.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+use shared::model::order::Order;
+
+/// An update to the stock of a local that is waiting for the previous versions to be applied,
+/// along with the e-commerce server it came through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StockUpdate {
+    OrderResult {
+        order: Order,
+        ecommerce_id: Option<u16>,
+    },
+    Delta {
+        product_name: String,
+        change: i32,
+        ecommerce_id: Option<u16>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct StockVersions {
+    current: HashMap<u16, u64>,
+    // Buffered updates are only kept in memory: the local sends its whole stock again when it
+    // logs in, which supersedes them.
+    #[serde(skip)]
+    buffered: HashMap<u16, BTreeMap<u64, StockUpdate>>,
+}
+
+impl StockVersions {
+    pub fn new() -> Self {
+        StockVersions::default()
+    }
+
+    /// Returns the version the stock of a local is at, which is 0 if nothing was received from it.
+    pub fn current(&self, local_id: u16) -> u64 {
+        self.current.get(&local_id).copied().unwrap_or(0)
+    }
+
+    /// Moves the stock of a local to the given version, if it is newer than the current one.
+    pub fn advance(&mut self, local_id: u16, version: u64) {
+        let current = self.current.entry(local_id).or_insert(0);
+        *current = (*current).max(version);
+    }
+
+    /// Moves the stock of a local to the given version, discarding the buffered updates it already includes.
+    pub fn reset(&mut self, local_id: u16, version: u64) {
+        self.advance(local_id, version);
+        if let Some(buffered) = self.buffered.get_mut(&local_id) {
+            *buffered = buffered.split_off(&(version + 1));
+        }
+    }
+
+    pub fn discard_buffered(&mut self, local_id: u16) {
+        self.buffered.remove(&local_id);
+    }
+
+    pub fn buffer(&mut self, local_id: u16, version: u64, update: StockUpdate) {
+        self.buffered
+            .entry(local_id)
+            .or_default()
+            .insert(version, update);
+    }
+
+    /// Takes the buffered update that comes right after the current version of a local's stock, if it arrived.
+    pub fn take_next(&mut self, local_id: u16) -> Option<(u64, StockUpdate)> {
+        let next_version = self.current(local_id) + 1;
+        let update = self.buffered.get_mut(&local_id)?.remove(&next_version)?;
+        Some((next_version, update))
+    }
+}
+
+// ====================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delta(change: i32) -> StockUpdate {
+        StockUpdate::Delta {
+            product_name: "product1".to_string(),
+            change,
+            ecommerce_id: None,
+        }
+    }
+
+    #[test]
+    fn test_buffered_updates_are_taken_in_order_and_discarded_on_reset() {
+        let mut stock_versions = StockVersions::new();
+        stock_versions.advance(1, 3);
+        stock_versions.buffer(1, 5, delta(5));
+        stock_versions.buffer(1, 4, delta(4));
+        stock_versions.buffer(1, 8, delta(8));
+
+        assert_eq!(stock_versions.take_next(1), Some((4, delta(4))));
+        stock_versions.advance(1, 4);
+        assert_eq!(stock_versions.take_next(1), Some((5, delta(5))));
+        stock_versions.advance(1, 5);
+        assert_eq!(stock_versions.take_next(1), None);
+
+        stock_versions.reset(1, 9);
+        assert_eq!(stock_versions.current(1), 9);
+        stock_versions.advance(1, 7);
+        assert_eq!(stock_versions.current(1), 9);
+        assert_eq!(stock_versions.take_next(1), None);
+    }
+}
@@ -0,0 +1,152 @@
+//! This module contains the `Subscriptions` to changes in the stock, which let the e-commerce
+//! servers be notified of the changes instead of having to ask for them.
+//!
+//! The products changed while handling a message are marked, and once it is handled, every
+//! subscriber interested in them is sent their new quantity. The notifications that do not fit in
+//! the mailbox of a subscriber are counted, and it is told how many it missed before the next one.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use actix::Addr;
+
+use super::db_middleman::DBMiddleman;
+
+/// Which changes a subscriber wants to be notified of.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StockFilter {
+    /// The products to notify changes of, or every product if empty.
+    pub product_names: HashSet<String>,
+    /// If any, only the changes that leave less than this quantity are notified.
+    pub below: Option<i32>,
+}
+
+impl StockFilter {
+    pub fn matches(&self, product_name: &str, quantity: i32) -> bool {
+        (self.product_names.is_empty() || self.product_names.contains(product_name))
+            && self.below.map_or(true, |below| quantity < below)
+    }
+}
+
+#[derive(Debug)]
+pub struct Subscription {
+    pub subscriber: Addr<DBMiddleman>,
+    pub filter: StockFilter,
+}
+
+#[derive(Debug, Default)]
+pub struct Subscriptions {
+    next_id: u64,
+    by_id: HashMap<u64, Subscription>,
+    changed: BTreeSet<(u16, String)>,
+    /// The notifications of each subscription dropped since its subscriber was last told about them.
+    dropped: HashMap<u64, u64>,
+}
+
+impl Subscriptions {
+    pub fn new() -> Self {
+        Subscriptions::default()
+    }
+
+    pub fn subscribe(&mut self, subscriber: Addr<DBMiddleman>, filter: StockFilter) -> u64 {
+        self.next_id += 1;
+        self.by_id
+            .insert(self.next_id, Subscription { subscriber, filter });
+        self.next_id
+    }
+
+    /// Cancels a subscription of the given subscriber, returning whether it had such a subscription.
+    pub fn unsubscribe(&mut self, subscription_id: u64, subscriber: &Addr<DBMiddleman>) -> bool {
+        if self
+            .by_id
+            .get(&subscription_id)
+            .map_or(true, |subscription| &subscription.subscriber != subscriber)
+        {
+            return false;
+        }
+        self.remove(subscription_id);
+        true
+    }
+
+    /// Cancels a subscription whose subscriber can no longer be reached.
+    pub fn remove(&mut self, subscription_id: u64) {
+        self.by_id.remove(&subscription_id);
+        self.dropped.remove(&subscription_id);
+    }
+
+    /// Counts a notification that could not be sent to the subscriber of a subscription.
+    pub fn record_dropped(&mut self, subscription_id: u64) {
+        *self.dropped.entry(subscription_id).or_insert(0) += 1;
+    }
+
+    /// The notifications of a subscription dropped since the last call to `reset_dropped`.
+    pub fn dropped(&self, subscription_id: u64) -> u64 {
+        self.dropped.get(&subscription_id).copied().unwrap_or(0)
+    }
+
+    pub fn reset_dropped(&mut self, subscription_id: u64) {
+        self.dropped.remove(&subscription_id);
+    }
+
+    /// Marks the quantity of a product in a local as changed, so subscribers are notified of it.
+    pub fn mark_changed(&mut self, local_id: u16, product_name: &str) {
+        if self.by_id.is_empty() {
+            return;
+        }
+        self.changed.insert((local_id, product_name.to_string()));
+    }
+
+    /// Returns the products changed since the last call, by local.
+    pub fn take_changed(&mut self) -> BTreeSet<(u16, String)> {
+        std::mem::take(&mut self.changed)
+    }
+
+    /// The subscriptions that want to be notified of the given quantity of a product.
+    pub fn matching(&self, product_name: &str, quantity: i32) -> Vec<(u64, &Subscription)> {
+        let mut matching: Vec<(u64, &Subscription)> = self
+            .by_id
+            .iter()
+            .filter(|(_, subscription)| subscription.filter.matches(product_name, quantity))
+            .map(|(subscription_id, subscription)| (*subscription_id, subscription))
+            .collect();
+        matching.sort_by_key(|(subscription_id, _)| *subscription_id);
+        matching
+    }
+}
+
+// ====================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filters_match_their_products_below_their_threshold() {
+        let every_product = StockFilter {
+            product_names: HashSet::new(),
+            below: None,
+        };
+        assert!(every_product.matches("product1", 10));
+
+        let low_stock_of_product1 = StockFilter {
+            product_names: HashSet::from(["product1".to_string()]),
+            below: Some(5),
+        };
+        assert!(low_stock_of_product1.matches("product1", 4));
+        assert!(!low_stock_of_product1.matches("product1", 5));
+        assert!(!low_stock_of_product1.matches("product2", 0));
+    }
+
+    #[test]
+    fn test_dropped_notifications_are_counted_until_reset() {
+        let mut subscriptions = Subscriptions::new();
+        subscriptions.record_dropped(1);
+        subscriptions.record_dropped(1);
+        subscriptions.record_dropped(2);
+        assert_eq!(subscriptions.dropped(1), 2);
+
+        subscriptions.reset_dropped(1);
+        assert_eq!(subscriptions.dropped(1), 0);
+        subscriptions.remove(2);
+        assert_eq!(subscriptions.dropped(2), 0);
+    }
+}
@@ -0,0 +1,346 @@
+//! This module contains the write-ahead log (WAL) of the database.
+//!
+//! Every mutation of the stock is appended to an on-disk file as a JSON line before being applied
+//! in memory, so that the state can be rebuilt by replaying the log when the database starts again.
+//!
+//! Each record carries a log sequence number (LSN), which lets the replay skip the records that
+//! are already included in a snapshot.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use shared::model::{order::Order, stock_product::Product};
+use tracing::warn;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum WalEntry {
+    PostStockFromLocal {
+        local_id: u16,
+        stock: HashMap<String, Product>,
+        #[serde(default)]
+        version: u64,
+        #[serde(default)]
+        timestamp: u64,
+        #[serde(default)]
+        ecommerce_id: Option<u16>,
+    },
+    PostOrderResult {
+        order: Order,
+        #[serde(default)]
+        stock_version: u64,
+        #[serde(default)]
+        timestamp: u64,
+        #[serde(default)]
+        ecommerce_id: Option<u16>,
+    },
+    StockDelta {
+        local_id: u16,
+        product_name: String,
+        change: i32,
+        version: u64,
+        #[serde(default)]
+        timestamp: u64,
+        #[serde(default)]
+        ecommerce_id: Option<u16>,
+    },
+    LocalIdIssued {
+        local_id: u16,
+        registered_at: u64,
+    },
+    StockReserved {
+        reservation_id: u64,
+        local_id: u16,
+        products: Vec<Product>,
+        #[serde(default)]
+        order_id: Option<u64>,
+        #[serde(default)]
+        reserved_at: u64,
+    },
+    ReservationCommitted {
+        reservation_id: u64,
+        #[serde(default)]
+        timestamp: u64,
+        #[serde(default)]
+        ecommerce_id: Option<u16>,
+    },
+    ReservationReleased {
+        reservation_id: u64,
+    },
+    LocalForgotten {
+        local_id: u16,
+        #[serde(default)]
+        timestamp: u64,
+    },
+    /// The stock of a local replaced the copy of the database after they were found to differ.
+    StockReconciled {
+        local_id: u16,
+        stock: HashMap<String, Product>,
+        version: u64,
+        timestamp: u64,
+        ecommerce_id: Option<u16>,
+    },
+    /// The stock of a local reached a version without changing, because the order result that
+    /// took it there was rejected, so the updates that follow do not wait for it.
+    VersionSkipped {
+        local_id: u16,
+        version: u64,
+    },
+    /// The highest leader epoch was raised by issuing it to a new leader.
+    LeaderEpochRaised {
+        epoch: u64,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WalRecord {
+    pub lsn: u64,
+    pub entry: WalEntry,
+}
+
+impl WalRecord {
+    pub fn from_string(line: &str) -> Result<Self, String> {
+        serde_json::from_str(line).map_err(|err| err.to_string())
+    }
+
+    pub fn to_string(&self) -> Result<String, String> {
+        serde_json::to_string(self).map_err(|err| err.to_string())
+    }
+}
+
+#[derive(Debug)]
+pub struct WriteAheadLog {
+    path: PathBuf,
+    file: File,
+    last_lsn: u64,
+}
+
+impl WriteAheadLog {
+    /// Opens the log at the given path in append mode, creating it (and its parent directory) if needed.
+    ///
+    /// If the last line of the log was left incomplete by a crash in the middle of a write, it is
+    /// truncated so that new entries are not appended after it.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)
+            .map_err(|err| err.to_string())?;
+
+        let (valid_len, last_lsn) = Self::scan_valid_prefix(&file)?;
+        let file_len = file.metadata().map_err(|err| err.to_string())?.len();
+        if valid_len < file_len {
+            warn!(
+                "[WriteAheadLog] Discarding {} bytes of corrupted tail of the log",
+                file_len - valid_len
+            );
+            file.set_len(valid_len).map_err(|err| err.to_string())?;
+        }
+
+        Ok(WriteAheadLog {
+            path: path.to_path_buf(),
+            file,
+            last_lsn,
+        })
+    }
+
+    /// Returns the length in bytes of the valid prefix of the log and the LSN of its last record.
+    fn scan_valid_prefix(file: &File) -> Result<(u64, u64), String> {
+        let mut reader = BufReader::new(file);
+        let mut valid_len = 0;
+        let mut last_lsn = 0;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let read = reader.read_line(&mut line).map_err(|err| err.to_string())?;
+            if read == 0 || !line.ends_with('\n') {
+                break;
+            }
+            if !line.trim().is_empty() {
+                match WalRecord::from_string(line.trim()) {
+                    Ok(record) => last_lsn = record.lsn,
+                    Err(_) => break,
+                }
+            }
+            valid_len += read as u64;
+        }
+        Ok((valid_len, last_lsn))
+    }
+
+    pub fn last_lsn(&self) -> u64 {
+        self.last_lsn
+    }
+
+    /// Makes the next appended record start after the given LSN, so that sequence numbers keep
+    /// increasing after the log has been truncated by a snapshot.
+    pub fn continue_after(&mut self, lsn: u64) {
+        self.last_lsn = self.last_lsn.max(lsn);
+    }
+
+    /// Appends an entry to the log, and only returns once it has been flushed to disk.
+    pub fn append(&mut self, entry: &WalEntry) -> Result<u64, String> {
+        let record = WalRecord {
+            lsn: self.last_lsn + 1,
+            entry: entry.clone(),
+        };
+        let line = record.to_string()? + "\n";
+        self.file
+            .write_all(line.as_bytes())
+            .map_err(|err| err.to_string())?;
+        self.file.sync_data().map_err(|err| err.to_string())?;
+        self.last_lsn = record.lsn;
+        Ok(record.lsn)
+    }
+
+    /// Reads all the records of the log in the order they were appended.
+    pub fn read_records(&self) -> Result<Vec<WalRecord>, String> {
+        let file = File::open(&self.path).map_err(|err| err.to_string())?;
+        let mut records = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|err| err.to_string())?;
+            if line.is_empty() {
+                continue;
+            }
+            match WalRecord::from_string(&line) {
+                Ok(record) => records.push(record),
+                Err(err) => {
+                    warn!("[WriteAheadLog] Stopping at corrupted entry: {}", err);
+                    break;
+                }
+            }
+        }
+        Ok(records)
+    }
+
+    /// Discards every record of the log. Only meant to be used once they are covered by a snapshot.
+    pub fn truncate(&mut self) -> Result<(), String> {
+        self.file.set_len(0).map_err(|err| err.to_string())?;
+        self.file.sync_all().map_err(|err| err.to_string())
+    }
+
+    /// Discards every record of the log and makes the next appended record start after the given LSN,
+    /// e.g. when the whole state is replaced by the one received from the primary database.
+    pub fn reset(&mut self, lsn: u64) -> Result<(), String> {
+        self.truncate()?;
+        self.last_lsn = lsn;
+        Ok(())
+    }
+}
+
+// ====================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_log_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("ferris_db_{}_{}", std::process::id(), name));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_appended_entries_are_read_back_in_order() {
+        let path = test_log_path("wal_in_order.wal");
+        let mut wal = WriteAheadLog::open(&path).unwrap();
+        let first = WalEntry::PostStockFromLocal {
+            local_id: 1,
+            stock: HashMap::from([(
+                "product1".to_string(),
+                Product::new("product1".to_string(), 10),
+            )]),
+            version: 1,
+            timestamp: 0,
+            ecommerce_id: None,
+        };
+        let second = WalEntry::LocalIdIssued {
+            local_id: 2,
+            registered_at: 0,
+        };
+        wal.append(&first).unwrap();
+        wal.append(&second).unwrap();
+
+        let reopened = WriteAheadLog::open(&path).unwrap();
+        assert_eq!(reopened.last_lsn(), 2);
+        assert_eq!(
+            reopened.read_records().unwrap(),
+            vec![
+                WalRecord {
+                    lsn: 1,
+                    entry: first
+                },
+                WalRecord {
+                    lsn: 2,
+                    entry: second
+                }
+            ]
+        );
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_corrupted_tail_is_discarded() {
+        let path = test_log_path("wal_corrupted_tail.wal");
+        let mut wal = WriteAheadLog::open(&path).unwrap();
+        let entry = WalEntry::LocalIdIssued {
+            local_id: 1,
+            registered_at: 0,
+        };
+        wal.append(&entry).unwrap();
+        wal.file
+            .write_all(b"{\"lsn\":2,\"entry\":{\"LocalId")
+            .unwrap();
+        drop(wal);
+
+        let mut reopened = WriteAheadLog::open(&path).unwrap();
+        reopened.append(&entry).unwrap();
+        let lsns: Vec<u64> = reopened
+            .read_records()
+            .unwrap()
+            .iter()
+            .map(|record| record.lsn)
+            .collect();
+        assert_eq!(lsns, vec![1, 2]);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_lsn_keeps_increasing_after_truncate() {
+        let path = test_log_path("wal_truncate.wal");
+        let mut wal = WriteAheadLog::open(&path).unwrap();
+        wal.append(&WalEntry::LocalIdIssued {
+            local_id: 1,
+            registered_at: 0,
+        })
+        .unwrap();
+        wal.append(&WalEntry::LocalIdIssued {
+            local_id: 2,
+            registered_at: 0,
+        })
+        .unwrap();
+        wal.truncate().unwrap();
+        drop(wal);
+
+        let mut reopened = WriteAheadLog::open(&path).unwrap();
+        assert!(reopened.read_records().unwrap().is_empty());
+        reopened.continue_after(2);
+        assert_eq!(
+            reopened
+                .append(&WalEntry::LocalIdIssued {
+                    local_id: 3,
+                    registered_at: 0,
+                })
+                .unwrap(),
+            3
+        );
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -1,10 +1,29 @@
-//! Ferris DB is a simple key-value store that uses a HashMap in memory to store data.
+//! Ferris DB keeps the stock of every local shop and answers the queries and order results of the
+//! e-commerce servers about it.
 //!
 //! It uses the `actix` framework for actor creation upon connection establishment, and `tokio` for async I/O and task spawning.
 //!
+//! The state is kept by the `StockHandler`, in the store chosen at startup, and is made durable
+//! through a write-ahead log and periodic snapshots. The database runs as the primary, or as a
+//! numbered backup that follows it and takes over when it is lost.
+//!
+//! Each component documents its own behavior in its module; `db::handler` starts them all from
+//! the configuration given through the command line arguments and the environment.
 
 mod db;
 
+use db::{
+    constants::{DEFAULT_STALE_LOCAL_THRESHOLD_SECS, DEFAULT_STOCK_PARTITIONS},
+    handler::DbConfig,
+    stock_store::StockStoreKind,
+};
+use shared::{
+    communication::db_auth::db_token_from_env,
+    model::{constants::DATABASE_TOKEN_ENV, reconciliation::ReconciliationPolicy},
+    parsers::db_addrs_parser::{db_addrs_from_env, parse_db_addrs},
+};
+use std::path::PathBuf;
+
 fn init_logger() {
     let subscriber = tracing_subscriber::FmtSubscriber::builder()
         .with_max_level(tracing::Level::TRACE)
@@ -12,7 +31,114 @@ fn init_logger() {
     let _ = tracing::subscriber::set_global_default(subscriber);
 }
 
+fn parse_args() -> Result<DbConfig, String> {
+    let mut args: Vec<String> = std::env::args().collect();
+    args.remove(0);
+    // The only flag without a value, so it is taken out before reading the others in pairs.
+    let insecure = args.iter().any(|arg| arg == "--insecure");
+    args.retain(|arg| arg != "--insecure");
+
+    let mut db_addrs = db_addrs_from_env()?;
+    let mut backup_number = None;
+    let mut stale_local_threshold_secs = DEFAULT_STALE_LOCAL_THRESHOLD_SECS;
+    let mut import_dir = None;
+    let mut catalog_path = None;
+    let mut reconciliation_policy = ReconciliationPolicy::default();
+    let mut stock_store_kind = StockStoreKind::default();
+    let mut stock_partition_count = DEFAULT_STOCK_PARTITIONS;
+
+    if args.len() % 2 != 0 {
+        println!("[Database] Invalid arguments");
+        println!("Usage: cargo run -p ferris_db -- [-a <db_addrs>] [-b <backup_number>] [-s <stale_threshold_secs>] [-i <import_dir>] [-c <catalog_file>] [-r <local|db|flag>] [-m <memory|file>] [-p <stock_partitions>] [--insecure]");
+        return Err(String::from("Invalid argument."));
+    }
+
+    for arg in args.chunks_exact(2) {
+        if arg[0] == "-b" {
+            println!("[Database] Backup number given: {}", arg[1].to_owned());
+            let number = arg[1]
+                .parse::<u16>()
+                .map_err(|_| String::from("Invalid backup number"))?;
+            backup_number = Some(number);
+        } else if arg[0] == "-a" {
+            println!("[Database] Database addresses given: {}", arg[1].to_owned());
+            db_addrs = parse_db_addrs(&arg[1])?;
+        } else if arg[0] == "-s" {
+            println!(
+                "[Database] Stale local threshold given: {} seconds",
+                arg[1].to_owned()
+            );
+            stale_local_threshold_secs = arg[1]
+                .parse::<u64>()
+                .map_err(|_| String::from("Invalid stale local threshold"))?;
+        } else if arg[0] == "-i" {
+            println!("[Database] Importing stock from: {}", arg[1].to_owned());
+            import_dir = Some(PathBuf::from(&arg[1]));
+        } else if arg[0] == "-c" {
+            println!("[Database] Catalog file given: {}", arg[1].to_owned());
+            catalog_path = Some(arg[1].to_owned());
+        } else if arg[0] == "-r" {
+            println!(
+                "[Database] Reconciliation policy given: {}",
+                arg[1].to_owned()
+            );
+            reconciliation_policy = arg[1].parse::<ReconciliationPolicy>()?;
+        } else if arg[0] == "-m" {
+            println!("[Database] Stock store given: {}", arg[1].to_owned());
+            stock_store_kind = arg[1].parse::<StockStoreKind>()?;
+        } else if arg[0] == "-p" {
+            println!("[Database] Stock partitions given: {}", arg[1].to_owned());
+            stock_partition_count = arg[1]
+                .parse::<usize>()
+                .map_err(|_| String::from("Invalid number of stock partitions"))?;
+        } else {
+            println!("[Database] Invalid argument: {}", arg[0].to_owned());
+            println!("Usage: cargo run -p ferris_db -- [-a <db_addrs>] [-b <backup_number>] [-s <stale_threshold_secs>] [-i <import_dir>] [-c <catalog_file>] [-r <local|db|flag>] [-m <memory|file>] [-p <stock_partitions>] [--insecure]");
+            return Err(String::from("Invalid argument."));
+        }
+    }
+
+    // The first address is the primary's, so backups are numbered from 1.
+    if let Some(number) = backup_number {
+        if number == 0 || number as usize >= db_addrs.len() {
+            println!(
+                "[Database] Backup number must be between 1 and {}",
+                db_addrs.len() - 1
+            );
+            return Err(String::from("Invalid backup number"));
+        }
+    }
+
+    // Backups take their state from the primary, so only the primary can import stock.
+    if backup_number.is_some() && import_dir.is_some() {
+        println!("[Database] Stock can only be imported by the primary database");
+        return Err(String::from("Invalid argument."));
+    }
+
+    let expected_token = db_token_from_env();
+    if expected_token.is_none() && !insecure {
+        println!(
+            "[Database] No token configured in {}, start with --insecure to accept connections without one",
+            DATABASE_TOKEN_ENV
+        );
+        return Err(String::from("No database token configured"));
+    }
+
+    Ok(DbConfig {
+        db_addrs,
+        backup_number,
+        stale_local_threshold_secs,
+        import_dir,
+        catalog_path,
+        reconciliation_policy,
+        stock_store_kind,
+        stock_partition_count,
+        expected_token,
+    })
+}
+
 pub fn run() -> Result<(), String> {
+    let config = parse_args()?;
     init_logger();
-    db::handler::start()
+    db::handler::start(config)
 }
@@ -6,19 +6,35 @@
 //! and delegation of order processing to the corresponding e-commerce node.
 //! It is the main entity of e-commerce and remains active even when connections are closed.
 //!
+//! When it becomes leader, it obtains a leader epoch from the database and attaches it to every
+//! write, so that the database can reject the writes of a leader that was already replaced.
+//! The database only accepts writes with an epoch it knows, so they are held until it arrives.
+//! It also subscribes to the products whose stock falls below `LOW_STOCK_THRESHOLD` in a local,
+//! which the database then notifies without being asked.
+//! Before sending an order to a local, the leader reserves its stock there in the database, and only
+//! sends it once the reservation is made; otherwise the order is given back to try another local.
+//! The result of the order consumes the reservation, and a cancelled order releases it.
+//! Every `RECONCILIATION_INTERVAL_SECS` it asks the connected locals for their stock, and has the
+//! database reconcile it with its copy, forwarding to the locals the corrections it returns.
+//!
 //! # Note
 //!
 //! The message handling that is done in this actor differs greatly from the one of the actor with the same name
 //! defined in the local shop. Refer to the arquitecture documentation to see the differences.
 
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
 
-use actix::{Actor, Addr, AsyncContext, Context, Handler, Message};
+use actix::{
+    fut::wrap_future, Actor, ActorFutureExt, Addr, AsyncContext, Context, Handler, Message,
+};
 use actix_rt::System;
 use shared::{
     communication::{db_request::DBRequest, sl_message::SLMessage, ss_message::SSMessage},
     model::{
-        constants::{EXIT_COMMAND, RECONNECT_COMMAND},
+        constants::{EXIT_COMMAND, RECONNECT_COMMAND, UNKNOWN_REQUEST_ID},
         order::Order,
         stock_product::Product,
     },
@@ -32,6 +48,11 @@ use crate::e_commerce::{
 
 use crate::e_commerce::ss_middleman;
 
+use super::constants::{
+    LOCALS_HEARTBEAT_INTERVAL_SECS, LOW_STOCK_THRESHOLD, MAX_WRITES_WAITING_FOR_EPOCH,
+    RECONCILIATION_INTERVAL_SECS,
+};
+use super::db_communicator;
 use super::db_middleman::{self, DBMiddleman};
 use super::{
     order_handler::{self, OrderHandler},
@@ -44,10 +65,10 @@ type ServerId = u16;
 type WasCompleted = bool;
 type WorkerId = u16;
 type ProductName = String;
-type SolvedStockQuery = HashMap<u16, i32>;
+type SolvedStockQuery = HashMap<ProductName, HashMap<u16, i32>>;
 
 type OrderResultBackUp = (Order, WasCompleted);
-type SolvedQueryBackUp = (WorkerId, ProductName, SolvedStockQuery);
+type SolvedQueryBackUp = (WorkerId, SolvedStockQuery);
 
 struct ConnectionHandlerBackUp {
     solved_query_request_pending_to_redirect: HashMap<ServerId, Vec<SolvedQueryBackUp>>,
@@ -72,6 +93,18 @@ pub struct ConnectionHandler {
     my_sl_id: u16,
     leader_ss_id: Option<u16>,
     leader_sl_id: Option<u16>,
+    /// The epoch issued by the database while this server is leader, once it arrives.
+    leader_epoch: Option<u64>,
+    /// The writes to send to the database once the leader epoch arrives.
+    writes_waiting_for_epoch: Vec<DBRequest>,
+    /// The subscriptions to stock changes made through the current connection to the database.
+    stock_subscription_ids: Vec<u64>,
+    /// The locals asked for their stock to reconcile it, whose stock has not arrived yet.
+    locals_being_reconciled: HashSet<u16>,
+    /// The orders waiting for their stock to be reserved in the local chosen for them, by order id.
+    orders_waiting_for_reservation: HashMap<u64, Order>,
+    /// The reservations of the orders sent to a local that has not answered yet, by order id.
+    reservations_by_order: HashMap<u64, u64>,
 
     order_workers: HashMap<u16, Addr<OrderWorker>>,
 
@@ -79,6 +112,8 @@ pub struct ConnectionHandler {
     ss_middlemen: HashMap<u16, Addr<SSMiddleman>>,
 
     db_middleman: Option<Addr<DBMiddleman>>,
+    db_addrs: Vec<String>,
+    next_db_request_id: u64,
 
     back_up: ConnectionHandlerBackUp,
 
@@ -87,7 +122,12 @@ pub struct ConnectionHandler {
 }
 
 impl ConnectionHandler {
-    pub fn new(orders_handler: Addr<OrderHandler>, ss_id: u16, sl_id: u16) -> Self {
+    pub fn new(
+        orders_handler: Addr<OrderHandler>,
+        ss_id: u16,
+        sl_id: u16,
+        db_addrs: Vec<String>,
+    ) -> Self {
         Self {
             order_handler: orders_handler,
 
@@ -96,6 +136,12 @@ impl ConnectionHandler {
 
             leader_ss_id: None,
             leader_sl_id: None,
+            leader_epoch: None,
+            writes_waiting_for_epoch: Vec::new(),
+            stock_subscription_ids: Vec::new(),
+            locals_being_reconciled: HashSet::new(),
+            orders_waiting_for_reservation: HashMap::new(),
+            reservations_by_order: HashMap::new(),
 
             order_workers: HashMap::new(),
 
@@ -103,6 +149,8 @@ impl ConnectionHandler {
             ss_middlemen: HashMap::new(),
 
             db_middleman: None,
+            db_addrs,
+            next_db_request_id: UNKNOWN_REQUEST_ID + 1,
 
             back_up: ConnectionHandlerBackUp::new(),
 
@@ -110,13 +158,260 @@ impl ConnectionHandler {
             tx_sl_console: None,
         }
     }
+
+    fn new_db_request_id(&mut self) -> u64 {
+        let request_id = self.next_db_request_id;
+        self.next_db_request_id += 1;
+        request_id
+    }
+
+    /// Keeps the connection to the database and tells it the id of this server, so that the
+    /// changes to the stock sent through it can be traced back to this server.
+    fn set_db_middleman(&mut self, db_middleman: Addr<DBMiddleman>) {
+        let request_id = self.new_db_request_id();
+        if let Err(err) = db_middleman.try_send(db_middleman::SendDBRequest {
+            request: DBRequest::TakeMyEcommerceId {
+                request_id,
+                ecommerce_id: self.my_ss_id,
+            },
+        }) {
+            warn!("[ConnectionHandler] Error sending id to db: {}", err);
+        }
+        self.db_middleman = Some(db_middleman);
+        // The subscriptions made through the previous connection ended with it.
+        self.stock_subscription_ids.clear();
+        if self.leader_ss_id == Some(self.my_ss_id) {
+            if self.leader_epoch.is_none() {
+                self.acquire_leader_epoch();
+            }
+            self.subscribe_to_low_stock();
+        }
+    }
+
+    /// Takes the leadership, and asks the database for an epoch to attach to the writes from now on.
+    fn become_leader(&mut self) {
+        info!("[ConnectionHandler] I'm the new leader [{}]", self.my_ss_id);
+        self.leader_ss_id = Some(self.my_ss_id);
+        self.leader_sl_id = Some(self.my_sl_id);
+        self.leader_epoch = None;
+        self.acquire_leader_epoch();
+        self.cancel_stock_subscriptions();
+        self.subscribe_to_low_stock();
+    }
+
+    /// Steps down as leader, if it was, leaving the epoch and the subscriptions to the new one.
+    /// The orders waiting for a reservation are given back, to be delegated to the new leader.
+    fn stop_leading(&mut self, ctx: &mut Context<Self>) {
+        self.leader_epoch = None;
+        self.reservations_by_order.clear();
+        self.give_back_orders_waiting_for_reservation(ctx);
+        if !self.writes_waiting_for_epoch.is_empty() {
+            warn!(
+                "[ConnectionHandler] Discarding {} writes that were waiting for the leader epoch",
+                self.writes_waiting_for_epoch.len()
+            );
+            self.writes_waiting_for_epoch.clear();
+        }
+        self.cancel_stock_subscriptions();
+    }
+
+    /// Asks the database to notify the products whose stock falls below the threshold in a local.
+    fn subscribe_to_low_stock(&mut self) {
+        let request_id = self.new_db_request_id();
+        if let Some(db_middleman) = &self.db_middleman {
+            if let Err(err) = db_middleman.try_send(db_middleman::SendDBRequest {
+                request: DBRequest::SubscribeToStock {
+                    request_id,
+                    product_names: Vec::new(),
+                    below: Some(LOW_STOCK_THRESHOLD),
+                },
+            }) {
+                warn!(
+                    "[ConnectionHandler] Error subscribing to low stock: {}",
+                    err
+                );
+            }
+        }
+    }
+
+    fn cancel_stock_subscriptions(&mut self) {
+        for subscription_id in std::mem::take(&mut self.stock_subscription_ids) {
+            self.cancel_stock_subscription(subscription_id);
+        }
+    }
+
+    fn cancel_stock_subscription(&mut self, subscription_id: u64) {
+        let request_id = self.new_db_request_id();
+        if let Some(db_middleman) = &self.db_middleman {
+            if let Err(err) = db_middleman.try_send(db_middleman::SendDBRequest {
+                request: DBRequest::UnsubscribeFromStock {
+                    request_id,
+                    subscription_id,
+                },
+            }) {
+                warn!(
+                    "[ConnectionHandler] Error cancelling stock subscription: {}",
+                    err
+                );
+            }
+        }
+    }
+
+    fn acquire_leader_epoch(&mut self) {
+        let request_id = self.new_db_request_id();
+        if let Some(db_middleman) = &self.db_middleman {
+            if let Err(err) = db_middleman.try_send(db_middleman::SendDBRequest {
+                request: DBRequest::AcquireLeaderEpoch { request_id },
+            }) {
+                warn!(
+                    "[ConnectionHandler] Error asking db for a leader epoch: {}",
+                    err
+                );
+            }
+        }
+    }
+
+    /// Sends a write to the database with the leader epoch instead of the one it was built with,
+    /// or holds it until the epoch arrives.
+    fn send_write_to_db(&mut self, mut request: DBRequest) -> Result<(), String> {
+        let Some(epoch) = self.leader_epoch else {
+            if self.writes_waiting_for_epoch.len() >= MAX_WRITES_WAITING_FOR_EPOCH {
+                return Err("Too many writes waiting for the leader epoch".to_string());
+            }
+            self.writes_waiting_for_epoch.push(request);
+            return Ok(());
+        };
+        request.set_epoch(epoch);
+        let Some(db_middleman) = &self.db_middleman else {
+            error!("[ConnectionHandler] DBMiddleman not found.");
+            return Err("DBMiddleman not found.".to_string());
+        };
+        db_middleman
+            .try_send(db_middleman::SendDBRequest { request })
+            .map_err(|err| err.to_string())
+    }
+
+    /// Asks the database to reserve the stock of an order in the local chosen for it, which is
+    /// sent the order once the reservation is made.
+    fn reserve_stock_for_order(&mut self, order: Order) -> Result<(), String> {
+        let order_id = order.get_id().ok_or("No id set on order")?;
+        let local_id = order.get_local_id().ok_or("No local id set")?;
+        let request_id = self.new_db_request_id();
+        self.send_write_to_db(DBRequest::ReserveStock {
+            request_id,
+            local_id,
+            order_id,
+            products: order.get_products(),
+            epoch: 0,
+        })?;
+        self.orders_waiting_for_reservation.insert(order_id, order);
+        Ok(())
+    }
+
+    /// Gives back the orders whose reservation will not be answered, so they are sent elsewhere.
+    /// A reservation made for one of them anyway expires in the database.
+    fn give_back_orders_waiting_for_reservation(&mut self, ctx: &mut Context<Self>) {
+        for (_, order) in self.orders_waiting_for_reservation.drain() {
+            if let Err(err) = ctx
+                .address()
+                .try_send(HandlingCannotDispatchOrder { order })
+            {
+                warn!(
+                    "[ConnectionHandler] Error giving back order waiting for a reservation: {}",
+                    err
+                );
+            }
+        }
+    }
+
+    fn release_reservation(&mut self, reservation_id: u64) {
+        let request_id = self.new_db_request_id();
+        if let Err(err) = self.send_write_to_db(DBRequest::ReleaseReservation {
+            request_id,
+            reservation_id,
+            epoch: 0,
+        }) {
+            warn!(
+                "[ConnectionHandler] Error releasing reservation [{}]: {}",
+                reservation_id, err
+            );
+        }
+    }
+
+    fn send_writes_waiting_for_epoch(&mut self) {
+        for request in std::mem::take(&mut self.writes_waiting_for_epoch) {
+            if let Err(err) = self.send_write_to_db(request) {
+                warn!(
+                    "[ConnectionHandler] Error sending write waiting for the leader epoch: {}",
+                    err
+                );
+            }
+        }
+    }
+
+    /// Tells the database which locals are connected to this server, so it keeps considering them online.
+    /// Only the leader can, since the database checks the leader epoch of the heartbeat.
+    fn send_locals_heartbeat(&mut self) {
+        if self.leader_ss_id != Some(self.my_ss_id) || self.sl_middlemen.is_empty() {
+            return;
+        }
+        let request_id = self.new_db_request_id();
+        if let Err(err) = self.send_write_to_db(DBRequest::LocalsHeartbeat {
+            request_id,
+            local_ids: self.sl_middlemen.keys().copied().collect(),
+            epoch: 0,
+        }) {
+            warn!(
+                "[ConnectionHandler] Error sending locals heartbeat: {}",
+                err
+            );
+        }
+    }
+
+    /// Asks every connected local for its stock, to reconcile it with the copy of the database.
+    fn ask_locals_for_stock_to_reconcile(&mut self) {
+        if self.leader_ss_id != Some(self.my_ss_id) {
+            return;
+        }
+        for (local_id, sl_middleman) in self.sl_middlemen.iter() {
+            let ask_all_stock = match (SLMessage::AskAllStock {}).to_string() {
+                Ok(ask_all_stock) => ask_all_stock,
+                Err(err) => {
+                    error!(
+                        "[ConnectionHandler] Error serializing stock request: {}",
+                        err
+                    );
+                    return;
+                }
+            };
+            match sl_middleman.try_send(sl_middleman::SendOnlineMsg {
+                msg_to_send: ask_all_stock,
+            }) {
+                Ok(()) => {
+                    self.locals_being_reconciled.insert(*local_id);
+                }
+                Err(err) => warn!(
+                    "[ConnectionHandler] Error asking local [{}] for its stock: {}",
+                    local_id, err
+                ),
+            }
+        }
+    }
 }
 
 impl Actor for ConnectionHandler {
     type Context = Context<Self>;
 
-    fn started(&mut self, _ctx: &mut Self::Context) {
+    fn started(&mut self, ctx: &mut Self::Context) {
         debug!("[ConnectionHandler] Started");
+        ctx.run_interval(
+            Duration::from_secs(LOCALS_HEARTBEAT_INTERVAL_SECS),
+            |connection_handler, _| connection_handler.send_locals_heartbeat(),
+        );
+        ctx.run_interval(
+            Duration::from_secs(RECONCILIATION_INTERVAL_SECS),
+            |connection_handler, _| connection_handler.ask_locals_for_stock_to_reconcile(),
+        );
     }
 }
 
@@ -135,7 +430,7 @@ impl Handler<AddDBMiddlemanAddr> for ConnectionHandler {
 
     fn handle(&mut self, msg: AddDBMiddlemanAddr, _: &mut Self::Context) -> Self::Result {
         info!("[ConnectionHandler] Adding DB middleman.");
-        self.db_middleman = Some(msg.db_middleman.clone());
+        self.set_db_middleman(msg.db_middleman);
         Ok(())
     }
 }
@@ -147,9 +442,20 @@ pub struct RemoveDBMiddleman {}
 impl Handler<RemoveDBMiddleman> for ConnectionHandler {
     type Result = Result<(), String>;
 
-    fn handle(&mut self, _: RemoveDBMiddleman, _: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, _: RemoveDBMiddleman, ctx: &mut Self::Context) -> Self::Result {
         info!("[ConnectionHandler] Removing DBMiddleman.");
         self.db_middleman = None;
+        self.give_back_orders_waiting_for_reservation(ctx);
+        ctx.spawn(
+            wrap_future::<_, Self>(db_communicator::reconnect_db_connection(
+                ctx.address(),
+                self.db_addrs.clone(),
+            ))
+            .map(|db_middleman, act, _| {
+                info!("[ConnectionHandler] Reconnected to the database.");
+                act.set_db_middleman(db_middleman);
+            }),
+        );
         Ok(())
     }
 }
@@ -258,6 +564,7 @@ impl Handler<RemoveSLMiddleman> for ConnectionHandler {
             msg.local_id
         );
         self.sl_middlemen.remove(&msg.local_id);
+        self.locals_being_reconciled.remove(&msg.local_id);
         Ok(())
     }
 }
@@ -457,17 +764,22 @@ impl Handler<RegisterLocal> for ConnectionHandler {
     type Result = Result<(), String>;
 
     fn handle(&mut self, msg: RegisterLocal, _: &mut Self::Context) -> Self::Result {
-        if let Some(db_middleman) = &self.db_middleman {
-            info!("[ConnectionHandler] Registering new local.");
-            return db_middleman
-                .try_send(db_middleman::RequestGetNewLocalId {
-                    requestor_sl_middleman: msg.sl_middleman_addr,
-                })
-                .map_err(|err| err.to_string());
-        }
-
-        error!("[ConnectionHandler] DBMiddleman not found.");
-        Err("DBMiddleman not found.".to_string())
+        let request_id = self.new_db_request_id();
+        let Some(db_middleman) = &self.db_middleman else {
+            error!("[ConnectionHandler] DBMiddleman not found.");
+            return Err("DBMiddleman not found.".to_string());
+        };
+        info!("[ConnectionHandler] Registering new local.");
+        db_middleman
+            .try_send(db_middleman::AwaitNewLocalId {
+                requestor_sl_middleman: msg.sl_middleman_addr,
+            })
+            .map_err(|err| err.to_string())?;
+        // Issuing a local id is a write, so it waits for the leader epoch like the others.
+        self.send_write_to_db(DBRequest::GetNewLocalId {
+            request_id,
+            epoch: 0,
+        })
     }
 }
 
@@ -520,6 +832,7 @@ pub struct StockFromLocal {
     pub sl_middleman_addr: Addr<SLMiddleman>,
     pub local_id: u16,
     pub stock: HashMap<String, Product>,
+    pub version: u64,
 }
 
 impl Handler<StockFromLocal> for ConnectionHandler {
@@ -531,21 +844,59 @@ impl Handler<StockFromLocal> for ConnectionHandler {
             msg.local_id
         );
 
-        if let Some(db_middleman) = &self.db_middleman {
-            return db_middleman
-                .try_send(db_middleman::SendOnlineMsg {
-                    msg_to_send: DBRequest::PostStockFromLocal {
-                        local_id: msg.local_id,
-                        stock: msg.stock,
-                    }
-                    .to_string()
-                    .map_err(|err| err.to_string())?,
-                })
-                .map_err(|err| err.to_string());
-        }
+        let request_id = self.new_db_request_id();
+        let request = if self.locals_being_reconciled.remove(&msg.local_id) {
+            DBRequest::ReconcileStock {
+                request_id,
+                local_id: msg.local_id,
+                stock: msg.stock,
+                version: msg.version,
+                epoch: 0,
+            }
+        } else {
+            DBRequest::PostStockFromLocal {
+                request_id,
+                local_id: msg.local_id,
+                stock: msg.stock,
+                version: msg.version,
+                epoch: 0,
+            }
+        };
+        self.send_write_to_db(request)
+    }
+}
 
-        error!("[ConnectionHandler] DBMiddleman not found.");
-        Err("DBMiddleman not found.".to_string())
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct CorrectLocalStock {
+    pub local_id: u16,
+    pub changes: HashMap<String, i32>,
+}
+
+impl Handler<CorrectLocalStock> for ConnectionHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: CorrectLocalStock, _: &mut Self::Context) -> Self::Result {
+        info!(
+            "[ConnectionHandler] Correcting the stock of local: [{}].",
+            msg.local_id
+        );
+        let Some(sl_middleman) = self.sl_middlemen.get(&msg.local_id) else {
+            warn!(
+                "[ConnectionHandler] Local [{}] is no longer connected, not correcting its stock.",
+                msg.local_id
+            );
+            return Ok(());
+        };
+        sl_middleman
+            .try_send(sl_middleman::SendOnlineMsg {
+                msg_to_send: SLMessage::CorrectStock {
+                    changes: msg.changes,
+                }
+                .to_string()
+                .map_err(|err| err.to_string())?,
+            })
+            .map_err(|err| err.to_string())
     }
 }
 
@@ -587,6 +938,7 @@ impl Handler<LoginLocalMessage> for ConnectionHandler {
 #[rtype(result = "Result<(), String>")]
 pub struct OrderCompletedFromLocal {
     pub order: Order,
+    pub stock_version: u64,
 }
 
 impl Handler<OrderCompletedFromLocal> for ConnectionHandler {
@@ -601,7 +953,10 @@ impl Handler<OrderCompletedFromLocal> for ConnectionHandler {
                 .map_err(|err| err.to_string())?;
         }
         ctx.address()
-            .try_send(SendOrderResultToDataBase { order: msg.order })
+            .try_send(SendOrderResultToDataBase {
+                order: msg.order,
+                stock_version: msg.stock_version,
+            })
             .map_err(|err| err.to_string())
     }
 }
@@ -620,6 +975,10 @@ impl Handler<WebOrderCompletedFromLocal> for ConnectionHandler {
             warn!("[ConnectionHandler] Order is not web.");
             return Err("Order is not web.".to_string());
         }
+        // The result of the order sent by the local consumes its reservation.
+        if let Some(order_id) = msg.order.get_id() {
+            self.reservations_by_order.remove(&order_id);
+        }
 
         if msg.order.get_ss_id_web() == Some(self.my_ss_id) {
             info!(
@@ -661,6 +1020,13 @@ impl Handler<WebOrderCancelledFromLocal> for ConnectionHandler {
             warn!("[ConnectionHandler] Order is not web.");
             return Err("Order is not web.".to_string());
         }
+        if let Some(reservation_id) = msg
+            .order
+            .get_id()
+            .and_then(|order_id| self.reservations_by_order.remove(&order_id))
+        {
+            self.release_reservation(reservation_id);
+        }
 
         if msg.order.get_ss_id_web() == Some(self.my_ss_id) {
             info!(
@@ -695,7 +1061,7 @@ impl Handler<WebOrderCancelledFromLocal> for ConnectionHandler {
 #[derive(Message, Debug, PartialEq, Eq)]
 #[rtype(result = "Result<(), String>")]
 pub struct AskForStockProductByOrderWorker {
-    pub product_name: String,
+    pub product_names: Vec<String>,
     pub worker_id: u16,
 }
 
@@ -711,7 +1077,7 @@ impl Handler<AskForStockProductByOrderWorker> for ConnectionHandler {
             .try_send(AskForStockProduct {
                 requestor_ss_id: self.my_ss_id,
                 requestor_worker_id: msg.worker_id,
-                product_name: msg.product_name.clone(),
+                product_names: msg.product_names,
             })
             .map_err(|err| err.to_string())
     }
@@ -795,16 +1161,14 @@ impl Handler<HandlingOrderDispatch> for ConnectionHandler {
     fn handle(&mut self, msg: HandlingOrderDispatch, ctx: &mut Self::Context) -> Self::Result {
         if self.leader_ss_id == Some(self.my_ss_id) {
             let local_id = msg.order.get_local_id().ok_or("No local id set")?;
-            if let Some(sl_middleman) = self.sl_middlemen.get(&local_id) {
-                return sl_middleman
-                    .try_send(sl_middleman::SendOnlineMsg {
-                        msg_to_send: SLMessage::WorkNewOrder {
-                            order: msg.order.clone(),
-                        }
-                        .to_string()
-                        .map_err(|err| err.to_string())?,
-                    })
-                    .map_err(|err| err.to_string());
+            if self.sl_middlemen.contains_key(&local_id) {
+                match self.reserve_stock_for_order(msg.order.clone()) {
+                    Ok(()) => return Ok(()),
+                    Err(err) => warn!(
+                        "[ConnectionHandler] Error reserving stock in local [{}]: {}",
+                        local_id, err
+                    ),
+                }
             }
             return ctx
                 .address()
@@ -822,6 +1186,70 @@ impl Handler<HandlingOrderDispatch> for ConnectionHandler {
     }
 }
 
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct StockReservedForOrder {
+    pub order_id: u64,
+    pub reservation_id: u64,
+}
+
+impl Handler<StockReservedForOrder> for ConnectionHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: StockReservedForOrder, ctx: &mut Self::Context) -> Self::Result {
+        let Some(order) = self.orders_waiting_for_reservation.remove(&msg.order_id) else {
+            warn!(
+                "[ConnectionHandler] Reservation [{}] made for unknown order [{}], releasing it.",
+                msg.reservation_id, msg.order_id
+            );
+            self.release_reservation(msg.reservation_id);
+            return Ok(());
+        };
+        let local_id = order.get_local_id().ok_or("No local id set")?;
+        if let Some(sl_middleman) = self.sl_middlemen.get(&local_id) {
+            sl_middleman
+                .try_send(sl_middleman::SendOnlineMsg {
+                    msg_to_send: SLMessage::WorkNewOrder { order }
+                        .to_string()
+                        .map_err(|err| err.to_string())?,
+                })
+                .map_err(|err| err.to_string())?;
+            self.reservations_by_order
+                .insert(msg.order_id, msg.reservation_id);
+            return Ok(());
+        }
+        self.release_reservation(msg.reservation_id);
+        ctx.address()
+            .try_send(HandlingCannotDispatchOrder { order })
+            .map_err(|err| err.to_string())
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct StockNotReservedForOrder {
+    pub order_id: u64,
+}
+
+impl Handler<StockNotReservedForOrder> for ConnectionHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: StockNotReservedForOrder, ctx: &mut Self::Context) -> Self::Result {
+        if let Some(order) = self.orders_waiting_for_reservation.remove(&msg.order_id) {
+            info!(
+                "[ConnectionHandler] Stock of order [{}] could not be reserved in local [{}].",
+                msg.order_id,
+                order.get_local_id().ok_or("No local id set")?
+            );
+            return ctx
+                .address()
+                .try_send(HandlingCannotDispatchOrder { order })
+                .map_err(|err| err.to_string());
+        }
+        Ok(())
+    }
+}
+
 #[derive(Message, Debug, PartialEq, Eq)]
 #[rtype(result = "Result<(),String>")]
 pub struct HandlingCannotDispatchOrder {
@@ -1074,25 +1502,21 @@ impl Handler<TrySendPendingOrderResultsToOtherServer> for ConnectionHandler {
 #[rtype(result = "Result<(), String>")]
 pub struct SendOrderResultToDataBase {
     pub order: Order,
+    pub stock_version: u64,
 }
 
 impl Handler<SendOrderResultToDataBase> for ConnectionHandler {
     type Result = Result<(), String>;
 
     fn handle(&mut self, msg: SendOrderResultToDataBase, _: &mut Self::Context) -> Self::Result {
-        if let Some(db_middleman) = &self.db_middleman {
-            info!("[ConnectionHandler] Sending order result to DB.");
-            return db_middleman
-                .try_send(db_middleman::SendOnlineMsg {
-                    msg_to_send: DBRequest::PostOrderResult { order: msg.order }
-                        .to_string()
-                        .map_err(|err| err.to_string())?,
-                })
-                .map_err(|err| err.to_string());
-        }
-
-        error!("[ConnectionHandler] DBMiddleman not found.");
-        Err("DBMiddleman not found.".to_string())
+        let request_id = self.new_db_request_id();
+        info!("[ConnectionHandler] Sending order result to DB.");
+        self.send_write_to_db(DBRequest::PostOrderResult {
+            request_id,
+            order: msg.order,
+            stock_version: msg.stock_version,
+            epoch: 0,
+        })
     }
 }
 
@@ -1105,7 +1529,7 @@ impl Handler<SendOrderResultToDataBase> for ConnectionHandler {
 pub struct AskForStockProduct {
     pub requestor_ss_id: u16,
     pub requestor_worker_id: u16,
-    pub product_name: String,
+    pub product_names: Vec<String>,
 }
 
 impl Handler<AskForStockProduct> for ConnectionHandler {
@@ -1118,21 +1542,21 @@ impl Handler<AskForStockProduct> for ConnectionHandler {
                 .try_send(RedirectAskForStockProduct {
                     requestor_ss_id: msg.requestor_ss_id,
                     requestor_worker_id: msg.requestor_worker_id,
-                    product_name: msg.product_name.clone(),
+                    product_names: msg.product_names,
                 })
                 .map_err(|err| err.to_string());
         }
 
+        let request_id = self.new_db_request_id();
         if let Some(db_middleman) = &self.db_middleman {
             return db_middleman
-                .try_send(db_middleman::SendOnlineMsg {
-                    msg_to_send: DBRequest::GetProductQuantityFromAllLocals {
+                .try_send(db_middleman::SendDBRequest {
+                    request: DBRequest::GetProductsQuantityFromAllLocals {
+                        request_id,
                         ss_id: msg.requestor_ss_id,
                         worker_id: msg.requestor_worker_id,
-                        product_name: msg.product_name.clone(),
-                    }
-                    .to_string()
-                    .map_err(|err| err.to_string())?,
+                        product_names: msg.product_names,
+                    },
                 })
                 .map_err(|err| err.to_string());
         }
@@ -1146,7 +1570,7 @@ impl Handler<AskForStockProduct> for ConnectionHandler {
 struct RedirectAskForStockProduct {
     requestor_ss_id: u16,
     requestor_worker_id: u16,
-    product_name: String,
+    product_names: Vec<String>,
 }
 
 impl Handler<RedirectAskForStockProduct> for ConnectionHandler {
@@ -1160,7 +1584,7 @@ impl Handler<RedirectAskForStockProduct> for ConnectionHandler {
                         msg_to_send: SSMessage::DelegateAskForStockProductToLeader {
                             requestor_ss_id: msg.requestor_ss_id,
                             requestor_worker_id: msg.requestor_worker_id,
-                            product_name: msg.product_name.clone(),
+                            product_names: msg.product_names.clone(),
                         }
                         .to_string()
                         .map_err(|err| err.to_string())?,
@@ -1175,7 +1599,7 @@ impl Handler<RedirectAskForStockProduct> for ConnectionHandler {
             .try_send(AskForStockProduct {
                 requestor_ss_id: msg.requestor_ss_id,
                 requestor_worker_id: msg.requestor_worker_id,
-                product_name: msg.product_name.clone(),
+                product_names: msg.product_names,
             })
             .map_err(|err| err.to_string())
     }
@@ -1186,8 +1610,7 @@ impl Handler<RedirectAskForStockProduct> for ConnectionHandler {
 pub struct HandleSolvedQueryOfStockProductFromDB {
     pub ss_id: u16,
     pub worker_id: u16,
-    pub product_name: String,
-    pub stock: HashMap<u16, i32>,
+    pub stock: HashMap<String, HashMap<u16, i32>>,
 }
 
 impl Handler<HandleSolvedQueryOfStockProductFromDB> for ConnectionHandler {
@@ -1204,7 +1627,6 @@ impl Handler<HandleSolvedQueryOfStockProductFromDB> for ConnectionHandler {
                 .try_send(RedirectSolvedQueryOfStockProductFromDB {
                     ss_id: msg.ss_id,
                     worker_id: msg.worker_id,
-                    product_name: msg.product_name.clone(),
                     stock: msg.stock,
                 })
                 .map_err(|err| err.to_string());
@@ -1217,7 +1639,6 @@ impl Handler<HandleSolvedQueryOfStockProductFromDB> for ConnectionHandler {
             );
             return order_worker
                 .try_send(order_worker::SolvedStockProductQueryForOrderWorker {
-                    product_name: msg.product_name.clone(),
                     stock: msg.stock,
                     my_sl_id: self.my_sl_id,
                     my_ss_id: self.my_ss_id,
@@ -1235,8 +1656,7 @@ impl Handler<HandleSolvedQueryOfStockProductFromDB> for ConnectionHandler {
 pub struct RedirectSolvedQueryOfStockProductFromDB {
     pub ss_id: u16,
     pub worker_id: u16,
-    pub product_name: String,
-    pub stock: HashMap<u16, i32>,
+    pub stock: HashMap<String, HashMap<u16, i32>>,
 }
 
 impl Handler<RedirectSolvedQueryOfStockProductFromDB> for ConnectionHandler {
@@ -1257,7 +1677,6 @@ impl Handler<RedirectSolvedQueryOfStockProductFromDB> for ConnectionHandler {
                     msg_to_send: SSMessage::SolvedAskForStockProduct {
                         requestor_ss_id: msg.ss_id,
                         requestor_worker_id: msg.worker_id,
-                        product_name: msg.product_name.clone(),
                         stock: msg.stock,
                     }
                     .to_string()
@@ -1271,8 +1690,8 @@ impl Handler<RedirectSolvedQueryOfStockProductFromDB> for ConnectionHandler {
         self.back_up
             .solved_query_request_pending_to_redirect
             .entry(msg.ss_id)
-            .and_modify(|v| v.push((msg.worker_id, msg.product_name.clone(), msg.stock.clone())))
-            .or_insert(vec![(msg.worker_id, msg.product_name, msg.stock)]);
+            .and_modify(|v| v.push((msg.worker_id, msg.stock.clone())))
+            .or_insert(vec![(msg.worker_id, msg.stock)]);
         Ok(())
     }
 }
@@ -1300,12 +1719,11 @@ impl Handler<TrySendSolvedQueryOfStockProductFromDBToOtherServer> for Connection
             .solved_query_request_pending_to_redirect
             .get_mut(&msg.dest_ss_id)
         {
-            if let Some((worker_id, product_name, stock)) = orders.pop() {
+            if let Some((worker_id, stock)) = orders.pop() {
                 ctx.address()
                     .try_send(HandleSolvedQueryOfStockProductFromDB {
                         ss_id: msg.dest_ss_id,
                         worker_id,
-                        product_name,
                         stock,
                     })
                     .map_err(|err| err.to_string())?;
@@ -1349,9 +1767,7 @@ impl Handler<LeaderElection> for ConnectionHandler {
             }
         };
 
-        info!("[ConnectionHandler] I'm the new leader [{}]", self.my_ss_id);
-        self.leader_ss_id = Some(self.my_ss_id);
-        self.leader_sl_id = Some(self.my_sl_id);
+        self.become_leader();
         for (ss_id, ss_middleman) in self.ss_middlemen.iter() {
             info!("[ConnectionHandler] Notifying server: [{}]", ss_id);
             ss_middleman
@@ -1375,13 +1791,16 @@ pub struct LeaderSelected {
 impl Handler<LeaderSelected> for ConnectionHandler {
     type Result = Result<(), String>;
 
-    fn handle(&mut self, msg: LeaderSelected, _ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: LeaderSelected, ctx: &mut Self::Context) -> Self::Result {
         info!(
             "[ConnectionHandler] New leader selected: [{}]",
             msg.leader_ss_id
         );
         self.leader_ss_id = Some(msg.leader_ss_id);
         self.leader_sl_id = Some(msg.leader_sl_id);
+        if msg.leader_ss_id != self.my_ss_id {
+            self.stop_leading(ctx);
+        }
 
         for sl_middleman in self.sl_middlemen.values() {
             sl_middleman
@@ -1392,6 +1811,74 @@ impl Handler<LeaderSelected> for ConnectionHandler {
     }
 }
 
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct LeaderEpochFromDB {
+    pub epoch: u64,
+}
+
+impl Handler<LeaderEpochFromDB> for ConnectionHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: LeaderEpochFromDB, _ctx: &mut Self::Context) -> Self::Result {
+        if self.leader_ss_id != Some(self.my_ss_id) {
+            warn!(
+                "[ConnectionHandler] Ignoring leader epoch {}, this server is no longer the leader",
+                msg.epoch
+            );
+            return Ok(());
+        }
+        info!("[ConnectionHandler] Leader epoch: [{}]", msg.epoch);
+        self.leader_epoch = Some(msg.epoch);
+        self.send_writes_waiting_for_epoch();
+        Ok(())
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct WriteWithUnknownEpoch {
+    pub request: DBRequest,
+}
+
+impl Handler<WriteWithUnknownEpoch> for ConnectionHandler {
+    type Result = Result<(), String>;
+
+    /// The database does not know the epoch of the write, e.g. because a backup that never
+    /// received it took over, so a new one is acquired and the write is sent again with it.
+    fn handle(&mut self, msg: WriteWithUnknownEpoch, _ctx: &mut Self::Context) -> Self::Result {
+        if self.leader_ss_id != Some(self.my_ss_id) {
+            warn!("[ConnectionHandler] Discarding write with unknown epoch, this server is no longer the leader");
+            return Ok(());
+        }
+        if self.leader_epoch.is_some() && self.leader_epoch == msg.request.epoch() {
+            self.leader_epoch = None;
+            self.acquire_leader_epoch();
+        }
+        self.send_write_to_db(msg.request)
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct StockSubscribedInDB {
+    pub subscription_id: u64,
+}
+
+impl Handler<StockSubscribedInDB> for ConnectionHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: StockSubscribedInDB, _ctx: &mut Self::Context) -> Self::Result {
+        if self.leader_ss_id != Some(self.my_ss_id) {
+            // It stopped leading while subscribing.
+            self.cancel_stock_subscription(msg.subscription_id);
+            return Ok(());
+        }
+        self.stock_subscription_ids.push(msg.subscription_id);
+        Ok(())
+    }
+}
+
 #[derive(Message)]
 #[rtype(result = "Result<(),String>")]
 pub struct TriggerElectionIfNeededAfterClosedSS {
@@ -1404,7 +1891,7 @@ impl Handler<TriggerElectionIfNeededAfterClosedSS> for ConnectionHandler {
     fn handle(
         &mut self,
         msg: TriggerElectionIfNeededAfterClosedSS,
-        _ctx: &mut Self::Context,
+        ctx: &mut Self::Context,
     ) -> Self::Result {
         self.ss_middlemen
             .remove(&msg.closed_server_id)
@@ -1418,6 +1905,7 @@ impl Handler<TriggerElectionIfNeededAfterClosedSS> for ConnectionHandler {
             }
             self.leader_ss_id = None;
             self.leader_sl_id = None;
+            self.stop_leading(ctx);
             if let Some(max_ss_id) = self.ss_middlemen.keys().max() {
                 if let Some(min_ss_id) = self.ss_middlemen.keys().min() {
                     if &self.my_ss_id < min_ss_id {
@@ -1439,9 +1927,7 @@ impl Handler<TriggerElectionIfNeededAfterClosedSS> for ConnectionHandler {
                 }
                 return Ok(());
             };
-            self.leader_ss_id = Some(self.my_ss_id);
-            self.leader_sl_id = Some(self.my_sl_id);
-            info!("[ConnectionHandler] I'm the new leader [{}]", self.my_ss_id);
+            self.become_leader();
         }
         Ok(())
     }
@@ -1,2 +1,11 @@
 pub const DEFAULT_ORDERS_FILENAME: &str = "orders0.txt";
 pub const DEFAULT_NUM_WORKERS: u16 = 3;
+pub const DB_RECONNECTION_DELAY_SECS: u64 = 1;
+pub const DB_RECONNECTION_MAX_DELAY_SECS: u64 = 30;
+pub const LOCALS_HEARTBEAT_INTERVAL_SECS: u64 = 10;
+pub const LOW_STOCK_THRESHOLD: i32 = 5;
+pub const RECONCILIATION_INTERVAL_SECS: u64 = 60;
+pub const DB_OVERLOAD_BACKOFF_MILLIS: u64 = 100;
+pub const DB_OVERLOAD_MAX_BACKOFF_MILLIS: u64 = 5000;
+pub const MAX_UNANSWERED_DB_REQUESTS_KEPT: usize = 256;
+pub const MAX_WRITES_WAITING_FOR_EPOCH: usize = 256;
@@ -1,35 +1,114 @@
 //! This module is responsible for setting up the connection to the database and
 //! creating the `DBMiddleman` actor.
+//!
+//! The primary database (the first of the given addresses) is tried first, and then each of its
+//! backups in order, so that the e-commerce keeps working after a backup has taken over.
+//!
+//! The token configured through `FERRIS_DB_TOKEN` (empty if there is none) is presented to the
+//! database before the connection is used, and databases that do not accept it are skipped.
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use actix::{Actor, Addr, AsyncContext};
-use shared::model::constants::DATABASE_IP;
+use shared::{
+    communication::{db_auth::db_token_from_env, db_request::DBRequest, db_response::DBResponse},
+    model::constants::UNKNOWN_REQUEST_ID,
+};
 use tokio::{
-    io::{split, AsyncBufReadExt, BufReader},
+    io::{split, AsyncBufReadExt, AsyncWriteExt, BufReader, Lines, ReadHalf, WriteHalf},
     net::TcpStream as AsyncTcpStream,
     sync::Mutex,
+    time::sleep,
 };
 use tokio_stream::wrappers::LinesStream;
-use tracing::info;
+use tracing::{info, warn};
 
 use crate::e_commerce::db_middleman::DBMiddleman;
 
-use super::connection_handler::ConnectionHandler;
+use super::{
+    connection_handler::ConnectionHandler,
+    constants::{DB_RECONNECTION_DELAY_SECS, DB_RECONNECTION_MAX_DELAY_SECS},
+};
 
 pub async fn setup_db_connection(
     connection_handler: Addr<ConnectionHandler>,
+    db_addrs: &[String],
 ) -> Result<Addr<DBMiddleman>, String> {
-    let addr = DATABASE_IP.to_string();
+    let mut last_error = String::from("No database address configured");
+    for addr in db_addrs {
+        let stream = match AsyncTcpStream::connect(addr).await {
+            Ok(stream) => stream,
+            Err(err) => {
+                last_error = err.to_string();
+                continue;
+            }
+        };
+        info!("Connected to db: [{}]", addr);
+        let (reader, mut writer) = split(stream);
+        let mut lines = BufReader::new(reader).lines();
+        let token = db_token_from_env().unwrap_or_default();
+        if let Err(err) = authenticate(&mut writer, &mut lines, token).await {
+            warn!("Could not authenticate with db [{}]: {}", addr, err);
+            last_error = err;
+            continue;
+        }
+        let db_middleman = DBMiddleman::create(|ctx| {
+            ctx.add_stream(LinesStream::new(lines));
+            DBMiddleman::new(Arc::new(Mutex::new(writer)), connection_handler)
+        });
+        return Ok(db_middleman);
+    }
+    Err(last_error)
+}
 
-    let stream = AsyncTcpStream::connect(addr.clone())
+/// Presents the token to the database and waits for it to be accepted.
+async fn authenticate(
+    writer: &mut WriteHalf<AsyncTcpStream>,
+    lines: &mut Lines<BufReader<ReadHalf<AsyncTcpStream>>>,
+    token: String,
+) -> Result<(), String> {
+    // It is sent before any other request on this connection, so its id needs no tracking.
+    let authenticate_msg = (DBRequest::Authenticate {
+        request_id: UNKNOWN_REQUEST_ID,
+        token,
+    })
+    .to_string()?
+        + "\n";
+    writer
+        .write_all(authenticate_msg.as_bytes())
         .await
         .map_err(|err| err.to_string())?;
-    info!("Connected to db: [{}]", addr);
-    let (reader, writer) = split(stream);
-    let db_middleman = DBMiddleman::create(|ctx| {
-        ctx.add_stream(LinesStream::new(BufReader::new(reader).lines()));
-        DBMiddleman::new(Arc::new(Mutex::new(writer)), connection_handler)
-    });
-    Ok(db_middleman)
+    let line = lines
+        .next_line()
+        .await
+        .map_err(|err| err.to_string())?
+        .ok_or("Connection closed by db")?;
+    match DBResponse::from_string(&line)? {
+        DBResponse::Authenticated { .. } => Ok(()),
+        DBResponse::Error { detail, .. } => Err(detail),
+        response => Err(format!("Unexpected response: {:?}", response)),
+    }
+}
+
+/// Connects to a database again after the connection to the previous one was lost, trying every
+/// address until one answers, giving a backup some time to take over. The delay between attempts
+/// doubles with each failed one, up to `DB_RECONNECTION_MAX_DELAY_SECS`.
+pub async fn reconnect_db_connection(
+    connection_handler: Addr<ConnectionHandler>,
+    db_addrs: Vec<String>,
+) -> Addr<DBMiddleman> {
+    let mut delay_secs = DB_RECONNECTION_DELAY_SECS;
+    loop {
+        sleep(Duration::from_secs(delay_secs)).await;
+        match setup_db_connection(connection_handler.clone(), &db_addrs).await {
+            Ok(db_middleman) => return db_middleman,
+            Err(err) => {
+                delay_secs = (delay_secs * 2).min(DB_RECONNECTION_MAX_DELAY_SECS);
+                warn!(
+                    "Could not reconnect to db, trying again in {} seconds: {}",
+                    delay_secs, err
+                );
+            }
+        }
+    }
 }
@@ -1,14 +1,27 @@
 //! This module contains the DBMiddleman actor, which is responsible for
 //! the direct communication via TCP with the database.
+//!
+//! Requests the database answers as `Overloaded` are sent again after a backoff, which doubles
+//! with each overloaded answer in a row.
 
 use super::{
     connection_handler::{self, ConnectionHandler, HandleSolvedQueryOfStockProductFromDB},
+    constants::{
+        DB_OVERLOAD_BACKOFF_MILLIS, DB_OVERLOAD_MAX_BACKOFF_MILLIS, MAX_UNANSWERED_DB_REQUESTS_KEPT,
+    },
     sl_middleman::SLMiddleman,
 };
 use actix::fut::wrap_future;
 use actix::prelude::*;
-use shared::communication::{db_request::DBRequest, db_response::DBResponse};
-use std::sync::Arc;
+use shared::communication::{
+    db_request::DBRequest,
+    db_response::{DBErrorKind, DBResponse},
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
 use tokio::{
     io::{AsyncWriteExt, WriteHalf},
     net::TcpStream as AsyncTcpStream,
@@ -21,6 +34,14 @@ pub struct DBMiddleman {
     writer: Arc<Mutex<WriteHalf<AsyncTcpStream>>>,
 
     current_sl_requestor: Option<Addr<SLMiddleman>>,
+    pending_requests: HashMap<u64, DBRequest>,
+    /// The threshold of each subscription to stock changes, if it has one.
+    stock_subscriptions: HashMap<u64, Option<i32>>,
+    /// The last requests sent that the db does not answer when they succeed, in case it answers
+    /// that it was overloaded. It does so as soon as they arrive, so older ones are not needed.
+    unanswered_requests: VecDeque<DBRequest>,
+    /// The `Overloaded` answers received in a row.
+    consecutive_overloads: u32,
 }
 
 impl DBMiddleman {
@@ -33,8 +54,45 @@ impl DBMiddleman {
             writer,
 
             current_sl_requestor: None,
+            pending_requests: HashMap::new(),
+            stock_subscriptions: HashMap::new(),
+            unanswered_requests: VecDeque::new(),
+            consecutive_overloads: 0,
         }
     }
+
+    /// Takes a sent request that the db answered with an error, if it is still kept.
+    fn take_failed_request(&mut self, request_id: u64) -> Option<DBRequest> {
+        self.pending_requests.remove(&request_id).or_else(|| {
+            let position = self
+                .unanswered_requests
+                .iter()
+                .position(|request| request.request_id() == request_id)?;
+            self.unanswered_requests.remove(position)
+        })
+    }
+
+    /// Sends a request the db was too overloaded to take again, once the backoff is over.
+    fn retry_overloaded_request(&mut self, request_id: u64, ctx: &mut Context<Self>) {
+        let Some(request) = self.take_failed_request(request_id) else {
+            warn!(
+                "[DBMiddleman] Request [{}] is no longer kept, so it is not sent again",
+                request_id
+            );
+            return;
+        };
+        self.consecutive_overloads += 1;
+        let backoff_millis = DB_OVERLOAD_BACKOFF_MILLIS
+            .saturating_mul(1 << (self.consecutive_overloads - 1).min(16))
+            .min(DB_OVERLOAD_MAX_BACKOFF_MILLIS);
+        info!(
+            "[DBMiddleman] Db overloaded, sending request [{}] again in {} ms",
+            request_id, backoff_millis
+        );
+        ctx.run_later(Duration::from_millis(backoff_millis), move |_, ctx| {
+            ctx.notify(SendDBRequest { request });
+        });
+    }
 }
 
 impl Actor for DBMiddleman {
@@ -49,13 +107,8 @@ impl StreamHandler<Result<String, std::io::Error>> for DBMiddleman {
     fn handle(&mut self, msg: Result<String, std::io::Error>, ctx: &mut Self::Context) {
         if let Ok(msg) = msg {
             debug!("[ONLINE RECEIVER DB] Received msg:\n{}", msg);
-            if ctx
-                .address()
-                .try_send(HandleOnlineMsg { received_msg: msg })
-                .is_err()
-            {
-                error!("[ONLINE RECEIVER DB] Error sending msg to handler");
-            }
+            // Queued even if the mailbox is full, since an answer lost here is never sent again.
+            ctx.notify(HandleOnlineMsg { received_msg: msg });
         }
     }
 
@@ -77,27 +130,292 @@ impl Handler<HandleOnlineMsg> for DBMiddleman {
     type Result = Result<(), String>;
 
     fn handle(&mut self, msg: HandleOnlineMsg, ctx: &mut Self::Context) -> Self::Result {
-        match DBResponse::from_string(&msg.received_msg).map_err(|err| err.to_string())? {
-            DBResponse::NewLocalId { local_id } => {
+        let response = DBResponse::from_string(&msg.received_msg).map_err(|err| err.to_string())?;
+        if !matches!(
+            response,
+            DBResponse::Error {
+                kind: DBErrorKind::Overloaded,
+                ..
+            }
+        ) {
+            self.consecutive_overloads = 0;
+        }
+        match response {
+            DBResponse::NewLocalId {
+                request_id,
+                local_id,
+            } => {
+                self.pending_requests.remove(&request_id);
                 ctx.address()
                     .try_send(HandleNewLocalIdFromDB { local_id })
                     .map_err(|err| err.to_string())?;
             }
             DBResponse::ProductQuantityFromAllLocals {
+                request_id,
                 ss_id,
                 worker_id,
                 product_name,
                 product_quantity_by_local_id,
             } => {
+                self.pending_requests.remove(&request_id);
+                self.connection_handler
+                    .try_send(HandleSolvedQueryOfStockProductFromDB {
+                        ss_id,
+                        worker_id,
+                        stock: HashMap::from([(product_name, product_quantity_by_local_id)]),
+                    })
+                    .map_err(|err| err.to_string())?;
+            }
+            DBResponse::ProductsQuantityFromAllLocals {
+                request_id,
+                ss_id,
+                worker_id,
+                product_quantity_by_local_id,
+            } => {
+                self.pending_requests.remove(&request_id);
                 self.connection_handler
                     .try_send(HandleSolvedQueryOfStockProductFromDB {
                         ss_id,
                         worker_id,
-                        product_name,
                         stock: product_quantity_by_local_id,
                     })
                     .map_err(|err| err.to_string())?;
             }
+            DBResponse::LeaderEpoch { request_id, epoch } => {
+                self.pending_requests.remove(&request_id);
+                self.connection_handler
+                    .try_send(connection_handler::LeaderEpochFromDB { epoch })
+                    .map_err(|err| err.to_string())?;
+            }
+            DBResponse::Subscribed {
+                request_id,
+                subscription_id,
+            } => {
+                if let Some(DBRequest::SubscribeToStock { below, .. }) =
+                    self.pending_requests.remove(&request_id)
+                {
+                    self.stock_subscriptions.insert(subscription_id, below);
+                }
+                self.connection_handler
+                    .try_send(connection_handler::StockSubscribedInDB { subscription_id })
+                    .map_err(|err| err.to_string())?;
+            }
+            DBResponse::Unsubscribed { request_id } => {
+                if let Some(DBRequest::UnsubscribeFromStock {
+                    subscription_id, ..
+                }) = self.pending_requests.remove(&request_id)
+                {
+                    self.stock_subscriptions.remove(&subscription_id);
+                }
+            }
+            DBResponse::StockChanged {
+                subscription_id,
+                local_id,
+                product_name,
+                quantity,
+            } => match self.stock_subscriptions.get(&subscription_id) {
+                Some(Some(below)) => warn!(
+                    "[DBMiddleman] Low stock of [{}] in local [{}]: {} (below {})",
+                    product_name, local_id, quantity, below
+                ),
+                _ => info!(
+                    "[DBMiddleman] Stock of [{}] in local [{}] changed to {}",
+                    product_name, local_id, quantity
+                ),
+            },
+            DBResponse::StockNotificationsDropped {
+                subscription_id,
+                dropped,
+            } => warn!(
+                "[DBMiddleman] Missed {} stock changes of subscription [{}], some stock may have changed unnoticed",
+                dropped, subscription_id
+            ),
+            DBResponse::StockReconciled {
+                local_id,
+                policy,
+                discrepancies,
+                corrections,
+                ..
+            } => {
+                for discrepancy in discrepancies {
+                    warn!(
+                        "[DBMiddleman] Stock of [{}] in local [{}] is {} but db has {} ({:?})",
+                        discrepancy.product_name,
+                        local_id,
+                        discrepancy.local_quantity,
+                        discrepancy.db_quantity,
+                        policy
+                    );
+                }
+                if let Some(changes) = corrections {
+                    self.connection_handler
+                        .try_send(connection_handler::CorrectLocalStock { local_id, changes })
+                        .map_err(|err| err.to_string())?;
+                }
+            }
+            DBResponse::StockReserved {
+                request_id,
+                order_id,
+                reservation_id,
+            } => {
+                self.pending_requests.remove(&request_id);
+                info!(
+                    "[DBMiddleman] Stock reserved in db for order [{}]: [{}]",
+                    order_id, reservation_id
+                );
+                self.connection_handler
+                    .try_send(connection_handler::StockReservedForOrder {
+                        order_id,
+                        reservation_id,
+                    })
+                    .map_err(|err| err.to_string())?;
+            }
+            DBResponse::StockHistory {
+                request_id,
+                changes,
+            } => {
+                self.pending_requests.remove(&request_id);
+                info!(
+                    "[DBMiddleman] Stock history of request [{}]: {} changes",
+                    request_id,
+                    changes.len()
+                );
+                for change in changes {
+                    info!("[DBMiddleman] {:?}", change);
+                }
+            }
+            DBResponse::OrderResults {
+                request_id,
+                order_results,
+            } => {
+                self.pending_requests.remove(&request_id);
+                info!(
+                    "[DBMiddleman] Order results of request [{}]: {} orders",
+                    request_id,
+                    order_results.len()
+                );
+                for order_result in order_results {
+                    info!("[DBMiddleman] {:?}", order_result);
+                }
+            }
+            DBResponse::CatalogProduct {
+                request_id,
+                product,
+            } => {
+                self.pending_requests.remove(&request_id);
+                info!("[DBMiddleman] Catalog product: {:?}", product);
+            }
+            DBResponse::Catalog {
+                request_id,
+                products,
+            } => {
+                self.pending_requests.remove(&request_id);
+                info!("[DBMiddleman] Catalog: {} products", products.len());
+                for product in products {
+                    info!("[DBMiddleman] {:?}", product);
+                }
+            }
+            DBResponse::Locals {
+                request_id,
+                local_ids,
+            } => {
+                self.pending_requests.remove(&request_id);
+                info!("[DBMiddleman] Locals known by db: {:?}", local_ids);
+            }
+            DBResponse::LocalStock {
+                request_id,
+                local_id,
+                quantity_by_product,
+            } => {
+                self.pending_requests.remove(&request_id);
+                info!(
+                    "[DBMiddleman] Stock of local [{}]: {:?}",
+                    local_id, quantity_by_product
+                );
+            }
+            DBResponse::TotalQuantityByProduct {
+                request_id,
+                quantity_by_product,
+            } => {
+                self.pending_requests.remove(&request_id);
+                info!(
+                    "[DBMiddleman] Total quantity by product: {:?}",
+                    quantity_by_product
+                );
+            }
+            DBResponse::TopProducts {
+                request_id,
+                products,
+            } => {
+                self.pending_requests.remove(&request_id);
+                info!("[DBMiddleman] Top products: {:?}", products);
+            }
+            DBResponse::Authenticated { request_id } => {
+                debug!(
+                    "[DBMiddleman] Connection authenticated by request [{}]",
+                    request_id
+                );
+            }
+            DBResponse::OrderResultAlreadyApplied { request_id } => {
+                info!(
+                    "[DBMiddleman] Order result of request [{}] was already applied in db",
+                    request_id
+                );
+            }
+            DBResponse::Error {
+                request_id,
+                kind,
+                detail,
+            } => {
+                warn!(
+                    "[DBMiddleman] Request [{}] failed in db ({:?}): {}",
+                    request_id, kind, detail
+                );
+                if kind == DBErrorKind::Overloaded {
+                    self.retry_overloaded_request(request_id, ctx);
+                    return Ok(());
+                }
+                if kind == DBErrorKind::UnknownEpoch {
+                    if let Some(request) = self.take_failed_request(request_id) {
+                        self.connection_handler
+                            .try_send(connection_handler::WriteWithUnknownEpoch { request })
+                            .map_err(|err| err.to_string())?;
+                    }
+                    return Ok(());
+                }
+                match self.pending_requests.remove(&request_id) {
+                    Some(
+                        DBRequest::GetProductQuantityFromAllLocals {
+                            ss_id, worker_id, ..
+                        }
+                        | DBRequest::GetProductsQuantityFromAllLocals {
+                            ss_id, worker_id, ..
+                        },
+                    ) => {
+                        // Without stock to look at, the order is cancelled instead of waiting forever.
+                        self.connection_handler
+                            .try_send(HandleSolvedQueryOfStockProductFromDB {
+                                ss_id,
+                                worker_id,
+                                stock: HashMap::new(),
+                            })
+                            .map_err(|err| err.to_string())?;
+                    }
+                    Some(DBRequest::ReserveStock { order_id, .. }) => {
+                        self.connection_handler
+                            .try_send(connection_handler::StockNotReservedForOrder { order_id })
+                            .map_err(|err| err.to_string())?;
+                    }
+                    Some(DBRequest::AcquireLeaderEpoch { .. }) => {
+                        error!("[DBMiddleman] Could not get a leader epoch from db");
+                    }
+                    Some(DBRequest::GetNewLocalId { .. }) => {
+                        error!("[DBMiddleman] Could not get a new local id from db");
+                        self.current_sl_requestor = None;
+                    }
+                    _ => {}
+                }
+            }
         }
         Ok(())
     }
@@ -166,18 +484,60 @@ impl Handler<SendOnlineMsg> for DBMiddleman {
     }
 }
 
+/// Sets the local to hand the next local id received from the db to.
 #[derive(Message, Debug, PartialEq, Eq)]
 #[rtype(result = "Result<(), String>")]
-pub struct RequestGetNewLocalId {
+pub struct AwaitNewLocalId {
     pub requestor_sl_middleman: Addr<SLMiddleman>,
 }
 
-impl Handler<RequestGetNewLocalId> for DBMiddleman {
+impl Handler<AwaitNewLocalId> for DBMiddleman {
     type Result = Result<(), String>;
 
-    fn handle(&mut self, msg: RequestGetNewLocalId, ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: AwaitNewLocalId, _ctx: &mut Self::Context) -> Self::Result {
         self.current_sl_requestor = Some(msg.requestor_sl_middleman);
-        let msg_to_send = DBRequest::GetNewLocalId.to_string()?;
+        Ok(())
+    }
+}
+
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+pub struct SendDBRequest {
+    pub request: DBRequest,
+}
+
+impl Handler<SendDBRequest> for DBMiddleman {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: SendDBRequest, ctx: &mut Self::Context) -> Self::Result {
+        let msg_to_send = msg.request.to_string()?;
+        // Requests answered by the db are kept until then, to know what to do if they fail.
+        if matches!(
+            msg.request,
+            DBRequest::GetNewLocalId { .. }
+                | DBRequest::AcquireLeaderEpoch { .. }
+                | DBRequest::GetProductQuantityFromAllLocals { .. }
+                | DBRequest::GetProductsQuantityFromAllLocals { .. }
+                | DBRequest::ReserveStock { .. }
+                | DBRequest::GetStockHistory { .. }
+                | DBRequest::GetOrderResults { .. }
+                | DBRequest::GetCatalogProduct { .. }
+                | DBRequest::GetCatalog { .. }
+                | DBRequest::GetLocals { .. }
+                | DBRequest::GetLocalStock { .. }
+                | DBRequest::GetTotalQuantityByProduct { .. }
+                | DBRequest::GetTopProducts { .. }
+                | DBRequest::SubscribeToStock { .. }
+                | DBRequest::UnsubscribeFromStock { .. }
+        ) {
+            self.pending_requests
+                .insert(msg.request.request_id(), msg.request);
+        } else {
+            self.unanswered_requests.push_back(msg.request);
+            if self.unanswered_requests.len() > MAX_UNANSWERED_DB_REQUESTS_KEPT {
+                self.unanswered_requests.pop_front();
+            }
+        }
         ctx.address()
             .try_send(SendOnlineMsg { msg_to_send })
             .map_err(|err| err.to_string())
@@ -22,6 +22,7 @@ pub fn start(
     servers_listening_port: u16,
     locals_listening_port: u16,
     num_workers: u16,
+    db_addrs: Vec<String>,
 ) -> Result<(), Box<dyn Error>> {
     let orders = parse_given_orders(orders_file_name)?;
 
@@ -46,6 +47,7 @@ pub fn start(
         sender_tx_to_sl,
         sender_tx_to_ss,
         num_workers,
+        db_addrs,
     ))?;
 
     input_handle
@@ -69,6 +71,7 @@ fn parse_given_orders(orders_file_name: &str) -> Result<Vec<Order>, Box<dyn Erro
     Ok(orders)
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn start_async(
     orders: Vec<Order>,
     servers_listening_port: u16,
@@ -77,6 +80,7 @@ async fn start_async(
     sender_tx_to_sl: mpsc::Sender<mpsc::Sender<String>>,
     sender_tx_to_ss: mpsc::Sender<mpsc::Sender<String>>,
     num_workers: u16,
+    db_addrs: Vec<String>,
 ) -> Result<(), Box<dyn Error>> {
     let (tx_from_input_to_sl, rx_from_input_to_sl) = channel::<String>();
     let (tx_from_input_to_ss, rx_from_input_to_ss) = channel::<String>();
@@ -86,6 +90,7 @@ async fn start_async(
         servers_listening_port,
         locals_listening_port,
         num_workers,
+        db_addrs,
     )
     .await?;
     sender_of_connection_handler
@@ -122,10 +127,13 @@ async fn start_actors(
     ss_id: u16,
     sl_id: u16,
     num_workers: u16,
+    db_addrs: Vec<String>,
 ) -> Result<Addr<ConnectionHandler>, Box<dyn Error>> {
     let order_handler = OrderHandler::new(&orders).start();
-    let connection_handler = ConnectionHandler::new(order_handler.clone(), ss_id, sl_id).start();
-    let db_middleman = db_communicator::setup_db_connection(connection_handler.clone()).await?;
+    let connection_handler =
+        ConnectionHandler::new(order_handler.clone(), ss_id, sl_id, db_addrs.clone()).start();
+    let db_middleman =
+        db_communicator::setup_db_connection(connection_handler.clone(), &db_addrs).await?;
     connection_handler
         .send(connection_handler::AddDBMiddlemanAddr {
             db_middleman: db_middleman.clone(),
@@ -56,7 +56,9 @@ impl OrderHandler {
             if let Order::Web(web_order) = order {
                 for product in &web_order.get_products() {
                     let new_products = vec![product.clone()];
-                    new_orders.push(Order::Web(WebOrder::new(new_products)));
+                    let mut new_order = Order::Web(WebOrder::new(new_products));
+                    new_order.set_id(rand::random());
+                    new_orders.push(new_order);
                 }
             }
         }
@@ -6,13 +6,15 @@
 //! It is also responsible for receiving the stock of the products from the `ConnectionHandler`
 //! upon order processing and act accordingly in order to delegate the order to
 //! the closest local that has enough stock to complete it.
+//! The leader only sends the order to that local once its stock is reserved there, and otherwise
+//! gives it back, so the worker tries with another local.
 //!
 //! # Note
 //!
 //! The message handling that is done in this actor differs from the one of the actor with the same name
 //! defined in the local shop. Refer to the arquitecture documentation to see the differences.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use actix::{Actor, Addr, Context, Handler, Message};
 use rand::Rng;
@@ -56,6 +58,14 @@ impl OrderWorker {
     }
 }
 
+fn product_names_of(order: &Order) -> Vec<String> {
+    order
+        .get_products()
+        .iter()
+        .map(|product| product.get_name())
+        .collect()
+}
+
 // ==========================================================================
 
 #[derive(Message, Debug, PartialEq, Eq)]
@@ -75,23 +85,19 @@ impl Handler<WorkNewOrder> for OrderWorker {
         self.curr_order = Some(msg.order.clone());
         self.cache_of_available_locals_for_curr_order.clear();
 
-        for product in msg.order.get_products().iter() {
-            self.connection_handler
-                .try_send(connection_handler::AskForStockProductByOrderWorker {
-                    product_name: product.get_name(),
-                    worker_id: self.id,
-                })
-                .map_err(|err| err.to_string())?;
-        }
-        Ok(())
+        self.connection_handler
+            .try_send(connection_handler::AskForStockProductByOrderWorker {
+                product_names: product_names_of(&msg.order),
+                worker_id: self.id,
+            })
+            .map_err(|err| err.to_string())
     }
 }
 
 #[derive(Message, Debug, PartialEq, Eq)]
 #[rtype(result = "Result<(), String>")]
 pub struct SolvedStockProductQueryForOrderWorker {
-    pub product_name: String,
-    pub stock: HashMap<u16, i32>,
+    pub stock: HashMap<String, HashMap<u16, i32>>,
     pub my_ss_id: u16,
     pub my_sl_id: u16,
 }
@@ -105,29 +111,33 @@ impl Handler<SolvedStockProductQueryForOrderWorker> for OrderWorker {
         _ctx: &mut Context<Self>,
     ) -> Self::Result {
         info!(
-            "[OrderWorker {}] Got stock by local for product: {:?}",
-            self.id, msg.product_name
+            "[OrderWorker {}] Got stock by local for products: {:?}",
+            self.id,
+            msg.stock.keys()
         );
 
         if let Some(Order::Web(current_order)) = self.curr_order.as_mut() {
-            let mut available_locals = Vec::new();
-            let required_product_amount = current_order
-                .get_products()
-                .iter()
-                .find(|product| product.get_name() == msg.product_name)
-                .ok_or("Product should be in order.")?
-                .get_quantity();
-
-            for (local_id, stock_quantity) in msg.stock.iter() {
-                if *stock_quantity >= required_product_amount {
-                    available_locals.push(*local_id);
-                }
-            }
+            let products = current_order.get_products();
+            let mut available_locals: Vec<u16> = msg
+                .stock
+                .values()
+                .flat_map(|stock_by_local| stock_by_local.keys().copied())
+                .collect::<HashSet<u16>>()
+                .into_iter()
+                .filter(|local_id| {
+                    products.iter().all(|product| {
+                        msg.stock
+                            .get(&product.get_name())
+                            .and_then(|stock_by_local| stock_by_local.get(local_id))
+                            .is_some_and(|quantity| *quantity >= product.get_quantity())
+                    })
+                })
+                .collect();
 
             if available_locals.is_empty() {
                 info!(
-                    "[OrderWorker {}] No local has enough stock to complete order ( {:?} ; Required amnt: {} ).",
-                    self.id, msg.product_name, required_product_amount
+                    "[OrderWorker {}] No local has enough stock to complete order ( {:?} ).",
+                    self.id, products
                 );
                 self.order_handler
                     .try_send(order_handler::OrderCancelled {
@@ -296,7 +306,7 @@ impl Handler<OrderCancelledFromLocal> for OrderWorker {
             );
             self.connection_handler
                 .try_send(connection_handler::AskForStockProductByOrderWorker {
-                    product_name: current_order.get_products()[0].get_name(),
+                    product_names: product_names_of(&Order::Web(current_order.clone())),
                     worker_id: self.id,
                 })
                 .map_err(|err| err.to_string())?;
@@ -122,9 +122,9 @@ impl Handler<HandleOnlineMsg> for SLMiddleman {
                 .address()
                 .try_send(HandleAskLeaderMessage {})
                 .map_err(|err| err.to_string()),
-            LSMessage::Stock { stock } => ctx
+            LSMessage::Stock { stock, version } => ctx
                 .address()
-                .try_send(HandleStockMessageFromLocal { stock })
+                .try_send(HandleStockMessageFromLocal { stock, version })
                 .map_err(|err| err.to_string()),
             LSMessage::RegisterLocalMessage => ctx
                 .address()
@@ -134,9 +134,15 @@ impl Handler<HandleOnlineMsg> for SLMiddleman {
                 .address()
                 .try_send(HandleLoginLocalMessage { local_id })
                 .map_err(|err| err.to_string()),
-            LSMessage::OrderCompleted { order } => ctx
+            LSMessage::OrderCompleted {
+                order,
+                stock_version,
+            } => ctx
                 .address()
-                .try_send(HandleOrderCompletedMessage { order })
+                .try_send(HandleOrderCompletedMessage {
+                    order,
+                    stock_version,
+                })
                 .map_err(|err| err.to_string()),
             LSMessage::OrderCancelled { order } => ctx
                 .address()
@@ -167,6 +173,7 @@ impl Handler<HandleAskLeaderMessage> for SLMiddleman {
 #[rtype(result = "Result<(), String>")]
 struct HandleStockMessageFromLocal {
     stock: HashMap<String, Product>,
+    version: u64,
 }
 
 impl Handler<HandleStockMessageFromLocal> for SLMiddleman {
@@ -183,6 +190,7 @@ impl Handler<HandleStockMessageFromLocal> for SLMiddleman {
                     sl_middleman_addr: ctx.address(),
                     local_id: id,
                     stock: msg.stock,
+                    version: msg.version,
                 })
                 .map_err(|err| err.to_string())?;
             Ok(())
@@ -249,6 +257,7 @@ impl Handler<SetUpId> for SLMiddleman {
 #[rtype(result = "Result<(), String>")]
 struct HandleOrderCompletedMessage {
     order: Order,
+    stock_version: u64,
 }
 
 impl Handler<HandleOrderCompletedMessage> for SLMiddleman {
@@ -256,7 +265,10 @@ impl Handler<HandleOrderCompletedMessage> for SLMiddleman {
 
     fn handle(&mut self, msg: HandleOrderCompletedMessage, _: &mut Self::Context) -> Self::Result {
         self.connection_handler
-            .try_send(OrderCompletedFromLocal { order: msg.order })
+            .try_send(OrderCompletedFromLocal {
+                order: msg.order,
+                stock_version: msg.stock_version,
+            })
             .map_err(|err| err.to_string())?;
         Ok(())
     }
@@ -144,26 +144,24 @@ impl Handler<HandleOnlineMsg> for SSMiddleman {
             SSMessage::DelegateAskForStockProductToLeader {
                 requestor_ss_id,
                 requestor_worker_id,
-                product_name,
+                product_names,
             } => self
                 .connection_handler
                 .try_send(AskForStockProduct {
                     requestor_ss_id,
                     requestor_worker_id,
-                    product_name,
+                    product_names,
                 })
                 .map_err(|err| err.to_string()),
             SSMessage::SolvedAskForStockProduct {
                 requestor_ss_id,
                 requestor_worker_id,
-                product_name,
                 stock,
             } => self
                 .connection_handler
                 .try_send(HandleSolvedQueryOfStockProductFromDB {
                     ss_id: requestor_ss_id,
                     worker_id: requestor_worker_id,
-                    product_name,
                     stock,
                 })
                 .map_err(|err| err.to_string()),
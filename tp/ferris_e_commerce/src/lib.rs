@@ -12,6 +12,7 @@ pub mod e_commerce;
 use e_commerce::constants::{DEFAULT_NUM_WORKERS, DEFAULT_ORDERS_FILENAME};
 use shared::{
     model::constants::{LOG_LVL_DEBUG, LOG_LVL_INFO, SL_INITIAL_PORT, SS_INITIAL_PORT},
+    parsers::db_addrs_parser::{db_addrs_from_env, parse_db_addrs},
     port_binder::listener_binder::LOCALHOST,
 };
 use std::{error::Error, fmt};
@@ -32,8 +33,14 @@ impl fmt::Display for EcommerceError {
 impl Error for EcommerceError {}
 
 pub fn run() -> Result<(), EcommerceError> {
-    let (servers_listening_port, locals_listening_port, orders_name, num_workers, log_lvl) =
-        parse_args()?;
+    let (
+        servers_listening_port,
+        locals_listening_port,
+        orders_name,
+        num_workers,
+        log_lvl,
+        db_addrs,
+    ) = parse_args()?;
     init_logger(log_lvl);
     info!("[e-commerce] Starting e_commerce");
     e_commerce::handler::start(
@@ -41,6 +48,7 @@ pub fn run() -> Result<(), EcommerceError> {
         servers_listening_port,
         locals_listening_port,
         num_workers,
+        db_addrs,
     )
     .map_err(|err| EcommerceError::InternalError(err.to_string()))?;
 
@@ -66,17 +74,20 @@ fn init_logger(log_lvl: String) {
     }
 }
 
-fn parse_args() -> Result<(u16, u16, String, u16, String), EcommerceError> {
+/// Servers and locals listening ports, orders file name, number of workers, log level and database addresses.
+type Args = (u16, u16, String, u16, String, Vec<String>);
+
+fn parse_args() -> Result<Args, EcommerceError> {
     let mut args: Vec<String> = std::env::args().collect();
     args.remove(0);
     let args_quantity = args.len();
 
     if args_quantity < 4 || args_quantity % 2 != 0 {
-        println!("Usage: cargo run -p e_commerce -- -ss <servers_listening_port> -sl <locals_listening_port> [-o <orders_file_name>] [-w <num_workers>] [-l <log_level>]");
+        println!("Usage: cargo run -p e_commerce -- -ss <servers_listening_port> -sl <locals_listening_port> [-o <orders_file_name>] [-w <num_workers>] [-l <log_level>] [-db <db_addrs>]");
         return Err(EcommerceError::ArgsParsingError(String::from(
             "Too few arguments",
         )));
-    } else if args_quantity > 10 {
+    } else if args_quantity > 12 {
         println!("Too many arguments were given\n Usage: cargo run -p e_commerce -- [<orders_file_name>]");
         return Err(EcommerceError::ArgsParsingError(String::from(
             "Too many arguments",
@@ -88,6 +99,7 @@ fn parse_args() -> Result<(u16, u16, String, u16, String), EcommerceError> {
     let mut orders_file_name = String::from(DEFAULT_ORDERS_FILENAME);
     let mut num_workers = DEFAULT_NUM_WORKERS;
     let mut log_lvl = String::from(LOG_LVL_INFO);
+    let mut db_addrs = db_addrs_from_env().map_err(EcommerceError::ArgsParsingError)?;
 
     for dual_arg in args.chunks_exact(2) {
         if dual_arg[0] == "-ss" {
@@ -126,8 +138,14 @@ fn parse_args() -> Result<(u16, u16, String, u16, String), EcommerceError> {
         } else if dual_arg[0] == "-l" {
             println!("[e-commerce] Log level: {}", args[1].to_owned());
             log_lvl = dual_arg[1].clone();
+        } else if dual_arg[0] == "-db" {
+            println!(
+                "[e-commerce] Database addresses: {}",
+                dual_arg[1].to_owned()
+            );
+            db_addrs = parse_db_addrs(&dual_arg[1]).map_err(EcommerceError::ArgsParsingError)?;
         } else {
-            println!("Usage: cargo run -p e_commerce -- -ss <servers_listening_port> -sl <locals_listening_port> [-o <orders_file_name>] [-w <num_workers>] [-l <log_level>]");
+            println!("Usage: cargo run -p e_commerce -- -ss <servers_listening_port> -sl <locals_listening_port> [-o <orders_file_name>] [-w <num_workers>] [-l <log_level>] [-db <db_addrs>]");
             return Err(EcommerceError::ArgsParsingError(String::from(
                 "Invalid argument",
             )));
@@ -136,14 +154,15 @@ fn parse_args() -> Result<(u16, u16, String, u16, String), EcommerceError> {
 
     check_if_given_ports_are_valid(servers_listening_port, locals_listening_port)?;
 
-    println!("[LocalShop] Arguments: \n[SERVER PORT: {}]  [LOCAL PORT: {}]  [ORDERS FILE NAME: {}]  [NUM WORKERS: {}]  [LOG LEVEL: {}]",
-    servers_listening_port, locals_listening_port, orders_file_name, num_workers, log_lvl);
+    println!("[LocalShop] Arguments: \n[SERVER PORT: {}]  [LOCAL PORT: {}]  [ORDERS FILE NAME: {}]  [NUM WORKERS: {}]  [LOG LEVEL: {}]  [DB ADDRESSES: {}]",
+    servers_listening_port, locals_listening_port, orders_file_name, num_workers, log_lvl, db_addrs.join(","));
     Ok((
         servers_listening_port,
         locals_listening_port,
         orders_file_name,
         num_workers,
         log_lvl,
+        db_addrs,
     ))
 }
 
@@ -16,7 +16,10 @@ use shared::{
     communication::ls_message::LSMessage,
     model::{order::Order, stock_product::Product},
 };
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
 use tokio::sync::mpsc::Sender;
 use tracing::{error, info, warn};
 
@@ -44,7 +47,8 @@ pub struct ConnectionHandler {
 
     tx_input_handler: Option<Sender<String>>,
 
-    order_results_pending_to_report: Vec<(Order, bool)>,
+    order_results_pending_to_report: Vec<(Order, bool, Option<u64>)>,
+    stock_version: u64,
 }
 
 impl ConnectionHandler {
@@ -61,8 +65,20 @@ impl ConnectionHandler {
             tx_input_handler: None,
 
             order_results_pending_to_report: Vec::new(),
+            // Starting from the current time keeps the versions increasing across restarts.
+            stock_version: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_millis() as u64)
+                .unwrap_or(0),
         }
     }
+
+    /// Returns the version the stock reaches with a new change, so the database can apply the
+    /// changes in order.
+    fn next_stock_version(&mut self) -> u64 {
+        self.stock_version += 1;
+        self.stock_version
+    }
 }
 
 impl Actor for ConnectionHandler {
@@ -338,6 +354,25 @@ impl Handler<AskAllStockMessage> for ConnectionHandler {
     }
 }
 
+#[derive(Message, Debug)]
+#[rtype(result = "Result<(), String>")]
+pub struct CorrectStockMessage {
+    pub changes: HashMap<String, i32>,
+}
+
+impl Handler<CorrectStockMessage> for ConnectionHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: CorrectStockMessage, _: &mut Context<Self>) -> Self::Result {
+        info!("[ConnectionHandler] Correcting stock to match the database.");
+        self.stock_handler
+            .try_send(stock_handler::CorrectStock {
+                changes: msg.changes,
+            })
+            .map_err(|err| err.to_string())
+    }
+}
+
 #[derive(Message, Debug)]
 #[rtype(result = "Result<(), String>")]
 pub struct ResponseAllStockMessage {
@@ -352,9 +387,12 @@ impl Handler<ResponseAllStockMessage> for ConnectionHandler {
         if let Some(ls_middleman) = &self.ls_middleman {
             ls_middleman
                 .try_send(SendOnlineMessage {
-                    msg_to_send: LSMessage::Stock { stock: msg.stock }
-                        .to_string()
-                        .map_err(|err| err.to_string())?,
+                    msg_to_send: LSMessage::Stock {
+                        stock: msg.stock,
+                        version: self.stock_version,
+                    }
+                    .to_string()
+                    .map_err(|err| err.to_string())?,
                 })
                 .map_err(|err| err.to_string())?;
             return Ok(());
@@ -399,11 +437,14 @@ impl Handler<TrySendPendingOrderResults> for ConnectionHandler {
             return Ok(());
         }
 
-        if let Some((order, was_finished)) = self.order_results_pending_to_report.pop() {
+        if let Some((order, was_finished, stock_version)) =
+            self.order_results_pending_to_report.pop()
+        {
             ctx.address()
                 .try_send(TrySendFinishedOrder {
                     order,
                     was_completed: was_finished,
+                    stock_version,
                 })
                 .map_err(|err| err.to_string())?;
             ctx.address()
@@ -421,18 +462,27 @@ impl Handler<TrySendPendingOrderResults> for ConnectionHandler {
 pub struct TrySendFinishedOrder {
     pub order: Order,
     pub was_completed: bool,
+    /// The version of the stock after the order, assigned the first time a completed order is handled.
+    pub stock_version: Option<u64>,
 }
 
 impl Handler<TrySendFinishedOrder> for ConnectionHandler {
     type Result = Result<(), String>;
 
     fn handle(&mut self, msg: TrySendFinishedOrder, ctx: &mut Context<Self>) -> Self::Result {
+        let stock_version = match msg.stock_version {
+            Some(stock_version) => Some(stock_version),
+            None if msg.was_completed => Some(self.next_stock_version()),
+            None => None,
+        };
+
         if self.local_id.is_none() || self.ls_middleman.is_none() {
             return ctx
                 .address()
                 .try_send(SaveFinishedOrderResultForLater {
                     order: msg.order,
                     was_completed: msg.was_completed,
+                    stock_version,
                 })
                 .map_err(|err| err.to_string());
         }
@@ -446,6 +496,7 @@ impl Handler<TrySendFinishedOrder> for ConnectionHandler {
                 .try_send(TrySendFinishedLocalOrderResult {
                     order,
                     was_finished: msg.was_completed,
+                    stock_version,
                 })
                 .map_err(|err| err.to_string()),
             Order::Web(_) => ctx
@@ -453,6 +504,7 @@ impl Handler<TrySendFinishedOrder> for ConnectionHandler {
                 .try_send(TrySendFinishedWebOrder {
                     order,
                     was_finished: msg.was_completed,
+                    stock_version,
                 })
                 .map_err(|err| err.to_string()),
         }
@@ -464,6 +516,7 @@ impl Handler<TrySendFinishedOrder> for ConnectionHandler {
 struct SaveFinishedOrderResultForLater {
     order: Order,
     was_completed: bool,
+    stock_version: Option<u64>,
 }
 
 impl Handler<SaveFinishedOrderResultForLater> for ConnectionHandler {
@@ -478,8 +531,11 @@ impl Handler<SaveFinishedOrderResultForLater> for ConnectionHandler {
             "[ConnectionHandler] Saving finished order result to send later: {:?}",
             msg
         );
-        self.order_results_pending_to_report
-            .push((msg.order, msg.was_completed));
+        self.order_results_pending_to_report.push((
+            msg.order,
+            msg.was_completed,
+            msg.stock_version,
+        ));
         Ok(())
     }
 }
@@ -489,6 +545,7 @@ impl Handler<SaveFinishedOrderResultForLater> for ConnectionHandler {
 struct TrySendFinishedLocalOrderResult {
     order: Order,
     was_finished: bool,
+    stock_version: Option<u64>,
 }
 
 impl Handler<TrySendFinishedLocalOrderResult> for ConnectionHandler {
@@ -507,7 +564,12 @@ impl Handler<TrySendFinishedLocalOrderResult> for ConnectionHandler {
 
         if let Order::Local(_) = &msg.order {
             if msg.was_finished {
-                message = LSMessage::OrderCompleted { order: msg.order }
+                message = LSMessage::OrderCompleted {
+                    order: msg.order,
+                    stock_version: msg
+                        .stock_version
+                        .ok_or("Completed orders must have a stock version.".to_string())?,
+                }
             } else {
                 return Ok(());
             }
@@ -528,6 +590,7 @@ impl Handler<TrySendFinishedLocalOrderResult> for ConnectionHandler {
 struct TrySendFinishedWebOrder {
     order: Order,
     was_finished: bool,
+    stock_version: Option<u64>,
 }
 
 impl Handler<TrySendFinishedWebOrder> for ConnectionHandler {
@@ -541,7 +604,12 @@ impl Handler<TrySendFinishedWebOrder> for ConnectionHandler {
         let message;
         if let Order::Web(_) = &msg.order {
             if msg.was_finished {
-                message = LSMessage::OrderCompleted { order: msg.order }
+                message = LSMessage::OrderCompleted {
+                    order: msg.order,
+                    stock_version: msg
+                        .stock_version
+                        .ok_or("Completed orders must have a stock version.".to_string())?,
+                }
             } else {
                 message = LSMessage::OrderCancelled { order: msg.order }
             }
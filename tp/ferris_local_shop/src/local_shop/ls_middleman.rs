@@ -9,7 +9,7 @@ use actix::{
     Handler, Message, StreamHandler,
 };
 use shared::{communication::sl_message::SLMessage, model::order::Order};
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 use tokio::{
     io::{AsyncWriteExt, WriteHalf},
     net::TcpStream as AsyncTcpStream,
@@ -107,6 +107,10 @@ impl Handler<HandleOnlineMsg> for LSMiddleman {
                 .address()
                 .try_send(HandleAskAllStockMessage {})
                 .map_err(|err| err.to_string()),
+            SLMessage::CorrectStock { changes } => ctx
+                .address()
+                .try_send(HandleCorrectStockMessage { changes })
+                .map_err(|err| err.to_string()),
             SLMessage::WorkNewOrder { order } => ctx
                 .address()
                 .try_send(HandleWorkNewOrderMessage { order })
@@ -191,6 +195,24 @@ impl Handler<HandleAskAllStockMessage> for LSMiddleman {
     }
 }
 
+#[derive(Message, Debug, PartialEq, Eq)]
+#[rtype(result = "Result<(), String>")]
+struct HandleCorrectStockMessage {
+    changes: HashMap<String, i32>,
+}
+
+impl Handler<HandleCorrectStockMessage> for LSMiddleman {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: HandleCorrectStockMessage, _: &mut Self::Context) -> Self::Result {
+        self.connection_handler_addr
+            .try_send(connection_handler::CorrectStockMessage {
+                changes: msg.changes,
+            })
+            .map_err(|err| err.to_string())
+    }
+}
+
 #[derive(Message, Debug, PartialEq, Eq)]
 #[rtype(result = "Result<(), String>")]
 struct HandleWorkNewOrderMessage {
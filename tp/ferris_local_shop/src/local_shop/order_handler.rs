@@ -43,7 +43,11 @@ pub struct OrderHandler {
 }
 
 impl OrderHandler {
-    pub fn new(local_orders: Vec<Order>) -> Self {
+    pub fn new(mut local_orders: Vec<Order>) -> Self {
+        // The database recognizes a resent order result by this id, so it is not applied twice.
+        for order in &mut local_orders {
+            order.set_id(rand::random());
+        }
         Self {
             local_orders,
             web_orders: Vec::new(),
@@ -364,6 +368,7 @@ impl Handler<HandleFinishedOrder> for OrderHandler {
                     .try_send(connection_handler::TrySendFinishedOrder {
                         order: msg.order,
                         was_completed: msg.was_completed,
+                        stock_version: None,
                     })
                     .map_err(|err| err.to_string())?;
             } else {
@@ -378,6 +383,7 @@ impl Handler<HandleFinishedOrder> for OrderHandler {
                     .try_send(connection_handler::TrySendFinishedOrder {
                         order: msg.order,
                         was_completed: msg.was_completed,
+                        stock_version: None,
                     })
                     .map_err(|err| err.to_string())?;
             } else {
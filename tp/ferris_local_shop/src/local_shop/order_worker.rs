@@ -8,8 +8,6 @@
 //! The message handling that is done in this actor differs from the one of the actor with the same name
 //! defined in the e-commerce server. Refer to the arquitecture documentation to see the differences.
 
-use std::usize::MAX;
-
 use actix::prelude::*;
 use rand::Rng;
 use shared::model::{order::Order, stock_product::Product};
@@ -65,7 +63,7 @@ impl OrderWorker {
         if let Some(id) = self.id {
             id
         } else {
-            MAX
+            usize::MAX
         }
     }
 }
@@ -2,7 +2,8 @@
 //!
 //! This actor is responsible for handling the stock of the shop.
 //! It can take, restore, reserve and unreserve products upon request from the `OrderWorkers`.
-//! It can also give the stock to the `ConnectionHandler` actor if asked.
+//! It can also give the stock to the `ConnectionHandler` actor if asked, and correct it when
+//! the database finds it differs from its copy.
 
 extern crate actix;
 use actix::prelude::*;
@@ -253,3 +254,27 @@ impl Handler<AskAllStock> for StockHandler {
             .map_err(|err| err.to_string())
     }
 }
+
+#[derive(Message)]
+#[rtype(result = "Result<(), String>")]
+pub struct CorrectStock {
+    pub changes: HashMap<String, i32>,
+}
+
+impl Handler<CorrectStock> for StockHandler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: CorrectStock, _ctx: &mut SyncContext<Self>) -> Self::Result {
+        for (product_name, change) in msg.changes {
+            info!(
+                "[StockHandler] Correcting stock of {} by {}.",
+                product_name, change
+            );
+            self.stock
+                .entry(product_name.clone())
+                .or_insert_with(|| Product::new(product_name, 0))
+                .affect_quantity_with_value(change);
+        }
+        Ok(())
+    }
+}
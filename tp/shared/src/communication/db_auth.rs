@@ -0,0 +1,37 @@
+//! The shared-secret token that processes connecting to the database present before any other
+//! request. It is configured through the `FERRIS_DB_TOKEN` environment variable on every side.
+
+use crate::model::constants::DATABASE_TOKEN_ENV;
+
+/// Returns the token configured through the environment, if there is a non-empty one.
+pub fn db_token_from_env() -> Option<String> {
+    std::env::var(DATABASE_TOKEN_ENV)
+        .ok()
+        .filter(|token| !token.is_empty())
+}
+
+/// Compares two tokens in a time that does not depend on how many of their bytes match.
+pub fn tokens_match(token: &str, expected_token: &str) -> bool {
+    token.len() == expected_token.len()
+        && token
+            .bytes()
+            .zip(expected_token.bytes())
+            .fold(0, |diff, (byte, expected_byte)| {
+                diff | (byte ^ expected_byte)
+            })
+            == 0
+}
+
+#[cfg(test)]
+mod tests_db_auth {
+
+    use super::*;
+
+    #[test]
+    fn test01_only_the_same_token_matches() {
+        assert!(tokens_match("s3cr3t", "s3cr3t"));
+        assert!(!tokens_match("s3cr3T", "s3cr3t"));
+        assert!(!tokens_match("s3cr3", "s3cr3t"));
+        assert!(!tokens_match("", "s3cr3t"));
+    }
+}
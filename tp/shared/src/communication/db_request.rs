@@ -4,27 +4,198 @@ use serde::{Deserialize, Serialize};
 
 use crate::model::{order::Order, stock_product::Product};
 
+/// Requests sent to the database. Each one carries a `request_id` chosen by the requestor,
+/// which the database echoes in its response (including `DBResponse::Error`).
+///
+/// Every change to the stock of a local carries the version of that stock the change leads to.
+/// Each local increments its version on every change, so the database can reject stale updates
+/// and reorder the ones that arrive before the previous ones.
+///
+/// Every write carries the leader epoch of the e-commerce server sending it, obtained with
+/// `AcquireLeaderEpoch` when it became leader. The database only accepts writes with the highest
+/// epoch it has issued or received from its upstream, so that a deposed leader cannot keep
+/// changing the stock, and writes without an epoch are invalid.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub enum DBRequest {
+    /// Must be the first request on a connection when the database is configured with a token.
+    /// Any other request before it makes the database close the connection.
+    Authenticate {
+        request_id: u64,
+        token: String,
+    },
     TakeMyEcommerceId {
+        request_id: u64,
         ecommerce_id: u16,
     },
-    GetNewLocalId,
+    GetNewLocalId {
+        request_id: u64,
+        epoch: u64,
+    },
+    /// Sent by an e-commerce server when it becomes leader, to obtain an epoch higher than every
+    /// one issued before.
+    AcquireLeaderEpoch {
+        request_id: u64,
+    },
     PostStockFromLocal {
+        request_id: u64,
         local_id: u16,
         stock: HashMap<String, Product>,
+        version: u64,
+        epoch: u64,
     },
     PostOrderResult {
+        request_id: u64,
         order: Order,
+        stock_version: u64,
+        epoch: u64,
+    },
+    PostStockDelta {
+        request_id: u64,
+        local_id: u16,
+        product_name: String,
+        change: i32,
+        version: u64,
+        epoch: u64,
     },
     GetProductQuantityFromAllLocals {
+        request_id: u64,
+        ss_id: u16,
+        worker_id: u16,
+        product_name: String,
+    },
+    GetProductsQuantityFromAllLocals {
+        request_id: u64,
         ss_id: u16,
         worker_id: u16,
+        product_names: Vec<String>,
+    },
+    /// Sent periodically by the leader with the locals connected to it, so they are considered online.
+    LocalsHeartbeat {
+        request_id: u64,
+        local_ids: Vec<u16>,
+        epoch: u64,
+    },
+    RegisterBackup {
+        request_id: u64,
+    },
+    /// Holds stock of a local for an order before it is sent there. The order result consumes the
+    /// reservation, so there is no need to commit it, and it is otherwise released when it is
+    /// cancelled or expires.
+    ReserveStock {
+        request_id: u64,
+        local_id: u16,
+        order_id: u64,
+        products: Vec<Product>,
+        epoch: u64,
+    },
+    ReleaseReservation {
+        request_id: u64,
+        reservation_id: u64,
+        epoch: u64,
+    },
+    /// Asks for the changes to the stock recorded between `from` and `to` (seconds since the unix
+    /// epoch, both included), of a local and/or a product, or of every one if none is given.
+    GetStockHistory {
+        request_id: u64,
+        local_id: Option<u16>,
+        product_name: Option<String>,
+        from: u64,
+        to: u64,
+    },
+    /// Asks for the order results applied between `from` and `to` (seconds since the unix epoch,
+    /// both included), of a local, an e-commerce server and/or a product, or of every one if none
+    /// is given.
+    GetOrderResults {
+        request_id: u64,
+        local_id: Option<u16>,
+        ecommerce_id: Option<u16>,
+        product_name: Option<String>,
+        from: u64,
+        to: u64,
+    },
+    GetCatalogProduct {
+        request_id: u64,
         product_name: String,
     },
+    /// Asks for the products of the catalog in the given category, or for all of them if none is given.
+    GetCatalog {
+        request_id: u64,
+        category: Option<String>,
+    },
+    /// Asks for the ids of the locals whose stock is known.
+    GetLocals {
+        request_id: u64,
+    },
+    GetLocalStock {
+        request_id: u64,
+        local_id: u16,
+    },
+    /// Asks for the quantity of each product summed across all locals.
+    GetTotalQuantityByProduct {
+        request_id: u64,
+    },
+    /// Asks for the `limit` products with the most units available across all locals.
+    GetTopProducts {
+        request_id: u64,
+        limit: usize,
+    },
+    /// Asks to be sent a `DBResponse::StockChanged` every time the quantity of one of the given
+    /// products (or of any product, if none is given) changes in a local. If `below` is given,
+    /// only the changes that leave less than that quantity are sent.
+    ///
+    /// The subscription lasts until it is cancelled or the connection is closed.
+    SubscribeToStock {
+        request_id: u64,
+        product_names: Vec<String>,
+        below: Option<i32>,
+    },
+    UnsubscribeFromStock {
+        request_id: u64,
+        subscription_id: u64,
+    },
+    /// The whole stock of a local as of the given version, asked periodically by the leader, to be
+    /// compared with the copy of the database. What is done with the differences depends on the
+    /// reconciliation policy of the database.
+    ReconcileStock {
+        request_id: u64,
+        local_id: u16,
+        stock: HashMap<String, Product>,
+        version: u64,
+        epoch: u64,
+    },
 }
 
 impl DBRequest {
+    /// The leader epoch of a write, or `None` if the request does not change the state of the database.
+    pub fn epoch(&self) -> Option<u64> {
+        match self {
+            DBRequest::GetNewLocalId { epoch, .. }
+            | DBRequest::PostStockFromLocal { epoch, .. }
+            | DBRequest::PostOrderResult { epoch, .. }
+            | DBRequest::PostStockDelta { epoch, .. }
+            | DBRequest::LocalsHeartbeat { epoch, .. }
+            | DBRequest::ReserveStock { epoch, .. }
+            | DBRequest::ReleaseReservation { epoch, .. }
+            | DBRequest::ReconcileStock { epoch, .. } => Some(*epoch),
+            _ => None,
+        }
+    }
+
+    /// Sets the leader epoch of a write, which is attached right before sending it.
+    pub fn set_epoch(&mut self, leader_epoch: u64) {
+        match self {
+            DBRequest::GetNewLocalId { epoch, .. }
+            | DBRequest::PostStockFromLocal { epoch, .. }
+            | DBRequest::PostOrderResult { epoch, .. }
+            | DBRequest::PostStockDelta { epoch, .. }
+            | DBRequest::LocalsHeartbeat { epoch, .. }
+            | DBRequest::ReserveStock { epoch, .. }
+            | DBRequest::ReleaseReservation { epoch, .. }
+            | DBRequest::ReconcileStock { epoch, .. } => *epoch = leader_epoch,
+            _ => {}
+        }
+    }
+
     pub fn from_string(msg: &str) -> Result<Self, String> {
         let request: DBRequest = serde_json::from_str(msg).map_err(|err| err.to_string())?;
         Ok(request)
@@ -33,4 +204,33 @@ impl DBRequest {
     pub fn to_string(&self) -> Result<String, String> {
         serde_json::to_string(self).map_err(|err| err.to_string())
     }
+
+    pub fn request_id(&self) -> u64 {
+        match self {
+            DBRequest::Authenticate { request_id, .. }
+            | DBRequest::TakeMyEcommerceId { request_id, .. }
+            | DBRequest::GetNewLocalId { request_id, .. }
+            | DBRequest::AcquireLeaderEpoch { request_id }
+            | DBRequest::PostStockFromLocal { request_id, .. }
+            | DBRequest::PostOrderResult { request_id, .. }
+            | DBRequest::PostStockDelta { request_id, .. }
+            | DBRequest::GetProductQuantityFromAllLocals { request_id, .. }
+            | DBRequest::GetProductsQuantityFromAllLocals { request_id, .. }
+            | DBRequest::LocalsHeartbeat { request_id, .. }
+            | DBRequest::RegisterBackup { request_id }
+            | DBRequest::ReserveStock { request_id, .. }
+            | DBRequest::ReleaseReservation { request_id, .. }
+            | DBRequest::GetStockHistory { request_id, .. }
+            | DBRequest::GetOrderResults { request_id, .. }
+            | DBRequest::GetCatalogProduct { request_id, .. }
+            | DBRequest::GetCatalog { request_id, .. }
+            | DBRequest::GetLocals { request_id }
+            | DBRequest::GetLocalStock { request_id, .. }
+            | DBRequest::GetTotalQuantityByProduct { request_id }
+            | DBRequest::GetTopProducts { request_id, .. }
+            | DBRequest::SubscribeToStock { request_id, .. }
+            | DBRequest::UnsubscribeFromStock { request_id, .. }
+            | DBRequest::ReconcileStock { request_id, .. } => *request_id,
+        }
+    }
 }
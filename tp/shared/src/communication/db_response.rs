@@ -2,17 +2,171 @@ use std::collections::HashMap;
 
 use serde::{Deserialize, Serialize};
 
+use crate::model::{
+    catalog_product::CatalogProduct,
+    db_order_result::OrderResult,
+    reconciliation::{ReconciliationPolicy, StockDiscrepancy},
+    stock_change::StockChange,
+};
+
+/// Responses sent by the database, echoing the `request_id` of the request they answer.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub enum DBResponse {
+    /// Sent when the token presented with `DBRequest::Authenticate` is accepted.
+    Authenticated {
+        request_id: u64,
+    },
     NewLocalId {
+        request_id: u64,
         local_id: u16,
     },
+    /// The epoch issued for `DBRequest::AcquireLeaderEpoch`, to be attached to every write.
+    LeaderEpoch {
+        request_id: u64,
+        epoch: u64,
+    },
     ProductQuantityFromAllLocals {
+        request_id: u64,
         ss_id: u16,
         worker_id: u16,
         product_name: String,
         product_quantity_by_local_id: HashMap<u16, i32>,
     },
+    /// Quantity of each of the asked products in each local, indexed by product name and then by local id.
+    ProductsQuantityFromAllLocals {
+        request_id: u64,
+        ss_id: u16,
+        worker_id: u16,
+        product_quantity_by_local_id: HashMap<String, HashMap<u16, i32>>,
+    },
+    StockReserved {
+        request_id: u64,
+        order_id: u64,
+        reservation_id: u64,
+    },
+    /// Sent when an order result had already been applied, so it was ignored instead of being applied twice.
+    OrderResultAlreadyApplied {
+        request_id: u64,
+    },
+    /// Changes to the stock asked with `DBRequest::GetStockHistory`, from oldest to newest.
+    StockHistory {
+        request_id: u64,
+        changes: Vec<StockChange>,
+    },
+    /// Order results asked with `DBRequest::GetOrderResults`, from oldest to newest.
+    OrderResults {
+        request_id: u64,
+        order_results: Vec<OrderResult>,
+    },
+    CatalogProduct {
+        request_id: u64,
+        product: CatalogProduct,
+    },
+    /// Products of the catalog asked with `DBRequest::GetCatalog`, sorted by name.
+    Catalog {
+        request_id: u64,
+        products: Vec<CatalogProduct>,
+    },
+    /// Ids of the locals whose stock is known, sorted.
+    Locals {
+        request_id: u64,
+        local_ids: Vec<u16>,
+    },
+    /// Quantity of each product in the stock of a local.
+    LocalStock {
+        request_id: u64,
+        local_id: u16,
+        quantity_by_product: HashMap<String, i32>,
+    },
+    /// Quantity of each product summed across all locals.
+    TotalQuantityByProduct {
+        request_id: u64,
+        quantity_by_product: HashMap<String, i32>,
+    },
+    /// Products with the most units available across all locals, from most to fewest.
+    TopProducts {
+        request_id: u64,
+        products: Vec<(String, i32)>,
+    },
+    /// The id of the subscription created for `DBRequest::SubscribeToStock`.
+    Subscribed {
+        request_id: u64,
+        subscription_id: u64,
+    },
+    Unsubscribed {
+        request_id: u64,
+    },
+    /// Pushed to a subscriber, without being asked, when the quantity of a product it subscribed
+    /// to changes in a local. `quantity` is the quantity left after the change.
+    StockChanged {
+        subscription_id: u64,
+        local_id: u16,
+        product_name: String,
+        quantity: i32,
+    },
+    /// Pushed to a subscriber before the next change notified to it when it missed `dropped`
+    /// notifications because it was not keeping up, so the quantities it has may be outdated.
+    StockNotificationsDropped {
+        subscription_id: u64,
+        dropped: u64,
+    },
+    /// The products whose quantity in the stock sent with `DBRequest::ReconcileStock` differs
+    /// from the copy of the database, sorted by name, and the policy applied to them.
+    ///
+    /// When the database is trusted, `corrections` has how much the local must change the
+    /// quantity of each of them. It is only given if the local's stock was at the same version as
+    /// the copy of the database, since otherwise the differences may be changes still on their way.
+    StockReconciled {
+        request_id: u64,
+        local_id: u16,
+        policy: ReconciliationPolicy,
+        discrepancies: Vec<StockDiscrepancy>,
+        corrections: Option<HashMap<String, i32>>,
+    },
+    /// Sent when a request could not be handled. If the request could not even be parsed,
+    /// `request_id` is `UNKNOWN_REQUEST_ID`.
+    Error {
+        request_id: u64,
+        kind: DBErrorKind,
+        detail: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DBErrorKind {
+    /// The request could not be parsed.
+    InvalidRequest,
+    /// The connection did not present the right token before its first request, so it is closed.
+    Unauthorized,
+    /// The order result does not match the stock known by the database, or has products that
+    /// are not in the catalog, so it was not applied.
+    OrderResultRejected,
+    /// There is not enough stock available to reserve the requested quantities.
+    InsufficientStock,
+    /// The result of the order to reserve stock for was already applied.
+    OrderAlreadyApplied,
+    /// The reservation does not exist, or was already committed or released.
+    UnknownReservation,
+    /// The product is not in the catalog.
+    UnknownProduct,
+    /// The stock of the local is not known.
+    UnknownLocal,
+    /// The stock update has a version the local's stock already reached.
+    StaleUpdate,
+    /// The write carries a leader epoch older than the highest one known, so it comes from a
+    /// deposed leader and was not applied.
+    StaleEpoch,
+    /// The write carries a leader epoch the database did not issue nor receive, or none at all,
+    /// so it was not applied.
+    UnknownEpoch,
+    /// No more local ids can be issued.
+    LocalIdsExhausted,
+    /// The change could not be persisted, so it was not applied.
+    Storage,
+    /// The database failed to handle the request internally.
+    Internal,
+    /// The database is handling too many requests to take this one, which can be sent again later.
+    Overloaded,
 }
 
 impl DBResponse {
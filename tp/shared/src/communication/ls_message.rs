@@ -20,10 +20,22 @@ impl Error for LSMessageError {}
 pub enum LSMessage {
     AskLeaderMessage,
     RegisterLocalMessage,
-    LoginLocalMessage { local_id: u16 },
-    Stock { stock: HashMap<String, Product> },
-    OrderCompleted { order: Order },
-    OrderCancelled { order: Order },
+    LoginLocalMessage {
+        local_id: u16,
+    },
+    /// The whole stock of the local, as of the given stock version.
+    Stock {
+        stock: HashMap<String, Product>,
+        version: u64,
+    },
+    /// A completed order, which took the local's stock to the given version.
+    OrderCompleted {
+        order: Order,
+        stock_version: u64,
+    },
+    OrderCancelled {
+        order: Order,
+    },
 }
 
 impl LSMessage {
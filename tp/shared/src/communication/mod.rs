@@ -1,3 +1,4 @@
+pub mod db_auth;
 pub mod db_request;
 pub mod db_response;
 pub mod ls_message;
@@ -1,4 +1,4 @@
-use std::{error::Error, fmt};
+use std::{collections::HashMap, error::Error, fmt};
 
 use serde::{Deserialize, Serialize};
 
@@ -18,11 +18,21 @@ impl Error for SLMessageError {}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum SLMessage {
-    LeaderMessage { leader_sl_id: u16 },
-    LocalSuccessfullyRegistered { local_id: u16 },
+    LeaderMessage {
+        leader_sl_id: u16,
+    },
+    LocalSuccessfullyRegistered {
+        local_id: u16,
+    },
     LocalSuccessfullyLoggedIn,
     AskAllStock,
-    WorkNewOrder { order: Order },
+    WorkNewOrder {
+        order: Order,
+    },
+    /// How much to change the quantity of each product, to match the stock known by the database.
+    CorrectStock {
+        changes: HashMap<String, i32>,
+    },
 }
 
 impl SLMessage {
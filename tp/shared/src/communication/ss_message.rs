@@ -32,13 +32,12 @@ pub enum SSMessage {
     DelegateAskForStockProductToLeader {
         requestor_ss_id: u16,
         requestor_worker_id: u16,
-        product_name: String,
+        product_names: Vec<String>,
     },
     SolvedAskForStockProduct {
         requestor_ss_id: u16,
         requestor_worker_id: u16,
-        product_name: String,
-        stock: HashMap<u16, i32>,
+        stock: HashMap<String, HashMap<u16, i32>>,
     },
     DelegateOrderToLeader {
         order: Order,
@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+
+/// The metadata of a product sold by the locals, identified by the same name used in their stock.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CatalogProduct {
+    name: String,
+    sku: String,
+    display_name: String,
+    unit_price_cents: u64,
+    category: String,
+}
+
+impl CatalogProduct {
+    pub fn new(
+        name: String,
+        sku: String,
+        display_name: String,
+        unit_price_cents: u64,
+        category: String,
+    ) -> Self {
+        CatalogProduct {
+            name,
+            sku,
+            display_name,
+            unit_price_cents,
+            category,
+        }
+    }
+
+    pub fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn get_sku(&self) -> String {
+        self.sku.clone()
+    }
+
+    pub fn get_display_name(&self) -> String {
+        self.display_name.clone()
+    }
+
+    pub fn get_unit_price_cents(&self) -> u64 {
+        self.unit_price_cents
+    }
+
+    pub fn get_category(&self) -> String {
+        self.category.clone()
+    }
+}
@@ -5,6 +5,14 @@ pub const SS_MAX_PORT: u16 = 15009;
 pub const LOCAHOST: &str = "127.0.0.1";
 
 pub const DATABASE_IP: &str = "127.0.0.1:9999";
+pub const DATABASE_BACKUP_IPS: [&str; 3] = ["127.0.0.1:9990", "127.0.0.1:9991", "127.0.0.1:9992"];
+/// Environment variable with the addresses of the database cluster, overriding the ones above.
+pub const DATABASE_ADDRS_ENV: &str = "FERRIS_DB_ADDRS";
+/// Environment variable with the token shared by the database and the processes connecting to it.
+pub const DATABASE_TOKEN_ENV: &str = "FERRIS_DB_TOKEN";
+
+/// Request id used in `DBResponse::Error` when the request it answers could not be parsed.
+pub const UNKNOWN_REQUEST_ID: u64 = 0;
 
 pub const CONNECTION_FINISHED: &str = "Conection finished";
 
@@ -2,19 +2,25 @@ use serde::{Deserialize, Serialize};
 
 use super::order::Order;
 
+/// An order result applied to the stock of a local, as kept by the database.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct OrderResult {
     order: Order,
-    ecommerce_id: u16,
+    /// The e-commerce server the result came through, if it told the database its id.
+    ecommerce_id: Option<u16>,
     local_id: u16,
+    /// When the result was applied, in seconds since the unix epoch.
+    #[serde(default)]
+    timestamp: u64,
 }
 
 impl OrderResult {
-    pub fn new(product: Order, ecommerce_id: u16, local_id: u16) -> Self {
+    pub fn new(order: Order, ecommerce_id: Option<u16>, local_id: u16, timestamp: u64) -> Self {
         OrderResult {
-            order: product,
+            order,
             ecommerce_id,
             local_id,
+            timestamp,
         }
     }
 
@@ -22,11 +28,22 @@ impl OrderResult {
         self.order.clone()
     }
 
-    pub fn get_ecommerce_id(&self) -> u16 {
+    pub fn get_ecommerce_id(&self) -> Option<u16> {
         self.ecommerce_id
     }
 
     pub fn get_local_id(&self) -> u16 {
         self.local_id
     }
+
+    pub fn get_timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    pub fn contains_product(&self, product_name: &str) -> bool {
+        self.order
+            .get_products()
+            .iter()
+            .any(|product| product.get_name() == product_name)
+    }
 }
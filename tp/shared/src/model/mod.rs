@@ -1,4 +1,7 @@
+pub mod catalog_product;
 pub mod constants;
 pub mod db_order_result;
 pub mod order;
+pub mod reconciliation;
+pub mod stock_change;
 pub mod stock_product;
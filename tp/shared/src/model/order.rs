@@ -1,5 +1,3 @@
-use std::u16::MAX;
-
 use serde::{Deserialize, Serialize};
 
 use super::stock_product::Product;
@@ -39,6 +37,21 @@ impl Order {
         }
     }
 
+    /// Sets the id that identifies the order from when it is taken until its result is applied.
+    pub fn set_id(&mut self, id: u64) {
+        match self {
+            Order::Local(local_order) => local_order.id = Some(id),
+            Order::Web(web_order) => web_order.id = Some(id),
+        }
+    }
+
+    pub fn get_id(&self) -> Option<u64> {
+        match self {
+            Order::Local(local_order) => local_order.id,
+            Order::Web(web_order) => web_order.id,
+        }
+    }
+
     pub fn set_ss_id(&mut self, ss_id: u16) {
         if let Order::Web(web_order) = self {
             web_order.set_ss_id(ss_id)
@@ -67,27 +80,29 @@ impl Order {
     pub fn get_worker_id_web(&self) -> Option<u16> {
         match self {
             Order::Web(web_order) => web_order.worker_id,
-            _ => Some(MAX),
+            _ => Some(u16::MAX),
         }
     }
 
     pub fn get_ss_id_web(&self) -> Option<u16> {
         match self {
             Order::Web(web_order) => web_order.ss_id,
-            _ => Some(MAX),
+            _ => Some(u16::MAX),
         }
     }
 
     pub fn get_sl_id_web(&self) -> Option<u16> {
         match self {
             Order::Web(web_order) => web_order.sl_id,
-            _ => Some(MAX),
+            _ => Some(u16::MAX),
         }
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct WebOrder {
+    #[serde(default)]
+    pub id: Option<u64>,
     pub ss_id: Option<u16>,
     pub sl_id: Option<u16>,
     pub local_id: Option<u16>,
@@ -98,6 +113,7 @@ pub struct WebOrder {
 impl WebOrder {
     pub fn new(products: Vec<Product>) -> Self {
         Self {
+            id: None,
             ss_id: None,
             sl_id: None,
             local_id: None,
@@ -137,6 +153,8 @@ impl WebOrder {
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct LocalOrder {
+    #[serde(default)]
+    pub id: Option<u64>,
     pub local_id: Option<u16>,
     products: Vec<Product>,
 }
@@ -144,6 +162,7 @@ pub struct LocalOrder {
 impl LocalOrder {
     pub fn new(products: Vec<Product>) -> Self {
         Self {
+            id: None,
             products,
             local_id: None,
         }
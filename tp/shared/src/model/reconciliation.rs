@@ -0,0 +1,62 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// What the database does when its copy of a local's stock differs from the local's own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum ReconciliationPolicy {
+    /// The copy of the database is replaced with the local's stock.
+    TrustLocal,
+    /// The copy of the database is kept, and the local is told how to correct its stock.
+    TrustDatabase,
+    /// The differences are only reported.
+    #[default]
+    FlagOnly,
+}
+
+impl FromStr for ReconciliationPolicy {
+    type Err = String;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy {
+            "local" => Ok(ReconciliationPolicy::TrustLocal),
+            "db" => Ok(ReconciliationPolicy::TrustDatabase),
+            "flag" => Ok(ReconciliationPolicy::FlagOnly),
+            _ => Err(format!(
+                "Invalid reconciliation policy: {} (must be local, db or flag)",
+                policy
+            )),
+        }
+    }
+}
+
+/// A product whose quantity in the stock of a local differs from the copy of the database.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StockDiscrepancy {
+    pub product_name: String,
+    pub local_quantity: i32,
+    pub db_quantity: i32,
+}
+
+#[cfg(test)]
+mod tests_reconciliation {
+
+    use super::*;
+
+    #[test]
+    fn test01_policies_are_parsed_from_their_names() {
+        assert_eq!(
+            "local".parse::<ReconciliationPolicy>(),
+            Ok(ReconciliationPolicy::TrustLocal)
+        );
+        assert_eq!(
+            "db".parse::<ReconciliationPolicy>(),
+            Ok(ReconciliationPolicy::TrustDatabase)
+        );
+        assert_eq!(
+            "flag".parse::<ReconciliationPolicy>(),
+            Ok(ReconciliationPolicy::FlagOnly)
+        );
+        assert!("other".parse::<ReconciliationPolicy>().is_err());
+    }
+}
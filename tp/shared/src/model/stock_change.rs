@@ -0,0 +1,33 @@
+use serde::{Deserialize, Serialize};
+
+/// What caused a change to the stock of a local.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StockChangeCause {
+    /// The local uploaded its whole stock, taking it to the given version.
+    StockUpload { version: u64 },
+    /// The result of the order that took the local's stock to the given version.
+    OrderResult { stock_version: u64 },
+    /// A single product of the local changed, taking its stock to the given version.
+    StockDelta { version: u64 },
+    /// The order holding the given reservation was completed, which is only recorded in logs
+    /// written when reservations could be committed.
+    ReservationCommitted { reservation_id: u64 },
+    /// An operator made the database forget the local.
+    LocalForgotten,
+    /// The stock of the local as of the given version differed from the copy of the database,
+    /// which was replaced with it.
+    Reconciliation { version: u64 },
+}
+
+/// A change to the quantity of a product in a local, as recorded by the database.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StockChange {
+    /// Seconds since the unix epoch.
+    pub timestamp: u64,
+    pub local_id: u16,
+    pub product_name: String,
+    pub delta: i32,
+    pub cause: StockChangeCause,
+    /// The e-commerce server the change came through, if any.
+    pub ecommerce_id: Option<u16>,
+}
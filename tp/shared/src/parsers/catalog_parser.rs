@@ -0,0 +1,130 @@
+//! Parses the product catalog: one product per line, as
+//! `name:sku:display_name:unit_price_cents:category`.
+
+use crate::model::catalog_product::CatalogProduct;
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    fs::File,
+    io::{BufRead, BufReader},
+};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CatalogParserError {
+    CannotOpenFile(String),
+    CannotReadLine(String),
+    CannotParseLine(String),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct CatalogParser {
+    products: HashMap<String, CatalogProduct>,
+}
+
+impl fmt::Display for CatalogParserError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for CatalogParserError {}
+
+impl CatalogParser {
+    pub fn new(path: &str) -> Result<Self, CatalogParserError> {
+        let file =
+            File::open(path).map_err(|err| CatalogParserError::CannotOpenFile(err.to_string()))?;
+        let buf = BufReader::new(file);
+        let products = buf
+            .lines()
+            .map(|result| {
+                let line =
+                    result.map_err(|err| CatalogParserError::CannotReadLine(err.to_string()))?;
+                Self::parse_line(line)
+            })
+            .collect::<Result<HashMap<String, CatalogProduct>, CatalogParserError>>()?;
+        Ok(CatalogParser { products })
+    }
+
+    pub fn get_products(&self) -> HashMap<String, CatalogProduct> {
+        self.products.clone()
+    }
+
+    fn parse_line(line: String) -> Result<(String, CatalogProduct), CatalogParserError> {
+        let product_fields: Vec<&str> = line.split(':').collect();
+        if product_fields.len() != 5 || product_fields[0].is_empty() {
+            return Err(CatalogParserError::CannotParseLine(
+                "[CatalogParserError] Cannot parse a product.".to_string(),
+            ));
+        }
+
+        let name = product_fields[0].to_string();
+        let unit_price_cents = product_fields[3]
+            .parse::<u64>()
+            .map_err(|err| CatalogParserError::CannotParseLine(err.to_string()))?;
+
+        Ok((
+            name.clone(),
+            CatalogProduct::new(
+                name,
+                product_fields[1].to_string(),
+                product_fields[2].to_string(),
+                unit_price_cents,
+                product_fields[4].to_string(),
+            ),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests_catalog_parser {
+
+    use super::*;
+
+    #[test]
+    fn test01_catalog_parser_can_read_a_file_with_multiple_products_ok(
+    ) -> Result<(), CatalogParserError> {
+        let path = "./data/test_catalog_parser/test_catalog_parser_multiple_products.txt";
+        let parser = CatalogParser::new(path)?;
+
+        let read_catalog = parser.get_products();
+        let expected_catalog = HashMap::from([
+            (
+                "Product1".to_string(),
+                CatalogProduct::new(
+                    "Product1".to_string(),
+                    "SKU-0001".to_string(),
+                    "Ferris plush".to_string(),
+                    1250,
+                    "toys".to_string(),
+                ),
+            ),
+            (
+                "Product2".to_string(),
+                CatalogProduct::new(
+                    "Product2".to_string(),
+                    "SKU-0002".to_string(),
+                    "Crab mug".to_string(),
+                    800,
+                    "kitchen".to_string(),
+                ),
+            ),
+        ]);
+
+        assert_eq!(read_catalog, expected_catalog);
+        Ok(())
+    }
+
+    #[test]
+    fn test02_cannot_parse_a_product_with_a_bad_price_err() -> Result<(), CatalogParserError> {
+        let path = "./data/test_catalog_parser/test_catalog_parser_bad_price.txt";
+        let parser = CatalogParser::new(path);
+
+        assert!(matches!(
+            parser,
+            Err(CatalogParserError::CannotParseLine(_))
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,62 @@
+//! Parses the addresses of the database cluster: the primary database first, and then each of
+//! its backups in order.
+//!
+//! They are given as a comma separated list, either as an argument or through the
+//! `FERRIS_DB_ADDRS` environment variable, and default to `DATABASE_IP` and `DATABASE_BACKUP_IPS`.
+
+use crate::model::constants::{DATABASE_ADDRS_ENV, DATABASE_BACKUP_IPS, DATABASE_IP};
+
+pub fn default_db_addrs() -> Vec<String> {
+    std::iter::once(DATABASE_IP)
+        .chain(DATABASE_BACKUP_IPS)
+        .map(String::from)
+        .collect()
+}
+
+/// Parses a comma separated list of `host:port` addresses.
+pub fn parse_db_addrs(addrs: &str) -> Result<Vec<String>, String> {
+    addrs
+        .split(',')
+        .map(|addr| {
+            let addr = addr.trim();
+            match addr.rsplit_once(':') {
+                Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+                    Ok(addr.to_string())
+                }
+                _ => Err(format!("Invalid database address: {}", addr)),
+            }
+        })
+        .collect()
+}
+
+/// Returns the addresses given through the environment, or the default ones if there are none.
+pub fn db_addrs_from_env() -> Result<Vec<String>, String> {
+    match std::env::var(DATABASE_ADDRS_ENV) {
+        Ok(addrs) => parse_db_addrs(&addrs),
+        Err(_) => Ok(default_db_addrs()),
+    }
+}
+
+#[cfg(test)]
+mod tests_db_addrs_parser {
+
+    use super::*;
+
+    #[test]
+    fn test01_primary_and_backups_are_parsed_in_order() {
+        assert_eq!(
+            parse_db_addrs("127.0.0.1:8999, 127.0.0.1:8990"),
+            Ok(vec![
+                "127.0.0.1:8999".to_string(),
+                "127.0.0.1:8990".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn test02_addresses_without_a_valid_port_are_rejected() {
+        assert!(parse_db_addrs("127.0.0.1").is_err());
+        assert!(parse_db_addrs("127.0.0.1:9999,localhost:db").is_err());
+        assert!(parse_db_addrs("").is_err());
+    }
+}
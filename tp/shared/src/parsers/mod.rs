@@ -1,2 +1,4 @@
+pub mod catalog_parser;
+pub mod db_addrs_parser;
 pub mod orders_parser;
 pub mod stock_parser;